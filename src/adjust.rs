@@ -0,0 +1,381 @@
+use crate::constants::{WHITE_BALANCE_RANGE, WHITE_PATCH_PERCENTILE};
+use crate::image::RawImage;
+use std::f32::consts::FRAC_PI_4;
+
+// Per channel lookup table, every adjustment that maps a channel value on its
+// own is built as one so that consecutive steps fuse into a single pass.
+#[derive(Clone)]
+pub struct Lut {
+    tables: [[u8; 256]; 3],
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Levels {
+    pub input: (u8, u8),
+    pub gamma: f32,
+    pub output: (u8, u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WhiteBalance {
+    GrayWorld,
+    WhitePatch,
+}
+
+impl Lut {
+    // `map` receives the channel and a value in 0..=1 and returns one in 0..=1.
+    pub fn new(map: impl Fn(usize, f32) -> f32) -> Self {
+        let mut tables = [[0; 256]; 3];
+        for (channel, table) in tables.iter_mut().enumerate() {
+            for (value, entry) in table.iter_mut().enumerate() {
+                *entry = to_u8(map(channel, value as f32 / 255.0));
+            }
+        }
+        Lut { tables }
+    }
+
+    pub fn levels(levels: &[Levels; 3]) -> Self {
+        Lut::new(|channel, value| {
+            let Levels {
+                input,
+                gamma,
+                output,
+            } = levels[channel];
+            let (low, high) = (input.0 as f32 / 255.0, input.1 as f32 / 255.0);
+            let normalized = ((value - low) / (high - low).max(f32::EPSILON)).clamp(0.0, 1.0);
+            let (black, white) = (output.0 as f32 / 255.0, output.1 as f32 / 255.0);
+            black + normalized.powf(1.0 / gamma.max(f32::EPSILON)) * (white - black)
+        })
+    }
+
+    pub fn curve(points: &[(u8, u8)]) -> Self {
+        let curve = monotone_spline(points);
+        Lut::new(|_, value| curve[to_u8(value) as usize] / 255.0)
+    }
+
+    pub fn gamma(gamma: f32) -> Self {
+        Lut::new(|_, value| value.powf(1.0 / gamma.max(f32::EPSILON)))
+    }
+
+    // Exposure is applied in linear light, one stop doubles the light.
+    pub fn exposure(stops: f32) -> Self {
+        let gain = 2f32.powf(stops);
+        Lut::new(|_, value| linear_to_srgb(srgb_to_linear(value) * gain))
+    }
+
+    // Both amounts are in -1..=1, contrast pivots around the mid gray.
+    pub fn brightness_contrast(brightness: f32, contrast: f32) -> Self {
+        let slope = ((contrast.clamp(-0.99, 0.99) + 1.0) * FRAC_PI_4).tan();
+        Lut::new(|_, value| (value - 0.5) * slope + 0.5 + brightness)
+    }
+
+    pub fn gains(gains: [f32; 3]) -> Self {
+        Lut::new(|channel, value| value * gains[channel])
+    }
+
+    // Positive temperature warms the image, positive tint shifts it to magenta.
+    pub fn white_balance(temperature: f32, tint: f32) -> Self {
+        Lut::gains([
+            1.0 + temperature * WHITE_BALANCE_RANGE,
+            1.0 - tint * WHITE_BALANCE_RANGE,
+            1.0 - temperature * WHITE_BALANCE_RANGE,
+        ])
+    }
+
+    pub fn auto_white_balance(image: &RawImage, method: WhiteBalance) -> Self {
+        let gains = match method {
+            WhiteBalance::GrayWorld => gray_world_gains(image),
+            WhiteBalance::WhitePatch => white_patch_gains(image),
+        };
+        Lut::gains(gains)
+    }
+
+    pub fn then(&self, next: &Lut) -> Lut {
+        let mut tables = self.tables;
+        for (table, next) in tables.iter_mut().zip(&next.tables) {
+            for entry in table.iter_mut() {
+                *entry = next[*entry as usize];
+            }
+        }
+        Lut { tables }
+    }
+
+    pub fn apply(&self, image: &mut RawImage) {
+        for pixel in image.pixels_mut().chunks_exact_mut(4) {
+            for (value, table) in pixel.iter_mut().zip(&self.tables) {
+                *value = table[*value as usize];
+            }
+        }
+    }
+}
+
+// Saturation and vibrance mix channels, so they can't be tables. Vibrance
+// boosts muted colors more than the already saturated ones.
+pub fn saturation(image: &mut RawImage, amount: f32) {
+    scale_chroma(image, |_| 1.0 + amount);
+}
+
+pub fn vibrance(image: &mut RawImage, amount: f32) {
+    scale_chroma(image, |saturation| 1.0 + amount * (1.0 - saturation));
+}
+
+fn scale_chroma(image: &mut RawImage, scale: impl Fn(f32) -> f32) {
+    for pixel in image.pixels_mut().chunks_exact_mut(4) {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|value| value as f32);
+        let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+        let saturation = (r.max(g).max(b) - r.min(g).min(b)) / 255.0;
+        let factor = scale(saturation);
+        for value in pixel[..3].iter_mut() {
+            *value = to_u8((luma + (*value as f32 - luma) * factor) / 255.0);
+        }
+    }
+}
+
+fn gray_world_gains(image: &RawImage) -> [f32; 3] {
+    let mut sums = [0u64; 3];
+    for pixel in image.pixels().chunks_exact(4) {
+        for (sum, value) in sums.iter_mut().zip(pixel) {
+            *sum += *value as u64;
+        }
+    }
+    let gray = sums.iter().sum::<u64>() as f32 / 3.0;
+    sums.map(|sum| if sum == 0 { 1.0 } else { gray / sum as f32 })
+}
+
+// The brightest values are taken at a percentile so a few clipped highlights
+// don't decide the white point.
+fn white_patch_gains(image: &RawImage) -> [f32; 3] {
+    let mut histograms = [[0usize; 256]; 3];
+    for pixel in image.pixels().chunks_exact(4) {
+        for (histogram, value) in histograms.iter_mut().zip(pixel) {
+            histogram[*value as usize] += 1;
+        }
+    }
+    let limit = ((image.pixels().len() / 4) as f32 * WHITE_PATCH_PERCENTILE) as usize;
+    histograms.map(|histogram| {
+        let mut count = 0;
+        let white = histogram
+            .iter()
+            .position(|bin| {
+                count += bin;
+                count > limit
+            })
+            .unwrap_or(255);
+        255.0 / white.max(1) as f32
+    })
+}
+
+// Fritsch–Carlson monotone cubic interpolation, so the curve never overshoots
+// between its control points.
+fn monotone_spline(points: &[(u8, u8)]) -> [f32; 256] {
+    let mut points: Vec<(f32, f32)> = points.iter().map(|&(x, y)| (x as f32, y as f32)).collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    points.dedup_by(|a, b| a.0 == b.0);
+
+    let mut curve = [0.0; 256];
+    if points.len() < 2 {
+        for (value, entry) in curve.iter_mut().enumerate() {
+            *entry = points.first().map_or(value as f32, |point| point.1);
+        }
+        return curve;
+    }
+
+    let n = points.len();
+    let secants: Vec<f32> = points
+        .windows(2)
+        .map(|pair| (pair[1].1 - pair[0].1) / (pair[1].0 - pair[0].0))
+        .collect();
+    let mut tangents = vec![0.0; n];
+    tangents[0] = secants[0];
+    tangents[n - 1] = secants[n - 2];
+    for k in 1..n - 1 {
+        if secants[k - 1] * secants[k] > 0.0 {
+            tangents[k] = (secants[k - 1] + secants[k]) / 2.0;
+        }
+    }
+    for k in 0..n - 1 {
+        if secants[k] == 0.0 {
+            tangents[k] = 0.0;
+            tangents[k + 1] = 0.0;
+            continue;
+        }
+        let (a, b) = (tangents[k] / secants[k], tangents[k + 1] / secants[k]);
+        let s = a * a + b * b;
+        if s > 9.0 {
+            let t = 3.0 / s.sqrt();
+            tangents[k] = t * a * secants[k];
+            tangents[k + 1] = t * b * secants[k];
+        }
+    }
+
+    for (value, entry) in curve.iter_mut().enumerate() {
+        let x = value as f32;
+        let k = points
+            .windows(2)
+            .position(|pair| x <= pair[1].0)
+            .unwrap_or(n - 2);
+        let ((x0, y0), (x1, y1)) = (points[k], points[k + 1]);
+        *entry = if x <= x0 {
+            y0
+        } else if x >= x1 {
+            y1
+        } else {
+            let h = x1 - x0;
+            let t = (x - x0) / h;
+            let (t2, t3) = (t * t, t * t * t);
+            (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+                + (t3 - 2.0 * t2 + t) * h * tangents[k]
+                + (-2.0 * t3 + 3.0 * t2) * y1
+                + (t3 - t2) * h * tangents[k + 1]
+        };
+    }
+    curve
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every value on every channel, one pixel per value.
+    fn ramp() -> RawImage {
+        let pixels = (0..=255u8)
+            .flat_map(|value| [value, 255 - value, value / 2, 255])
+            .collect();
+        RawImage::from_raw(pixels, 256, 1)
+    }
+
+    fn channel(image: &RawImage, c: usize) -> Vec<u8> {
+        image
+            .pixels()
+            .chunks_exact(4)
+            .map(|pixel| pixel[c])
+            .collect()
+    }
+
+    #[test]
+    fn fused_luts_match_applying_them_in_turn() {
+        let steps = [
+            Lut::levels(
+                &[Levels {
+                    input: (20, 230),
+                    gamma: 1.3,
+                    output: (10, 250),
+                }; 3],
+            ),
+            Lut::curve(&[(0, 0), (90, 60), (170, 200), (255, 255)]),
+            Lut::exposure(0.7),
+            Lut::brightness_contrast(-0.1, 0.4),
+        ];
+        let mut sequential = ramp();
+        for step in &steps {
+            step.apply(&mut sequential);
+        }
+        let fused = steps[1..]
+            .iter()
+            .fold(steps[0].clone(), |fused, step| fused.then(step));
+        let mut image = ramp();
+        fused.apply(&mut image);
+        assert_eq!(image.pixels(), sequential.pixels());
+    }
+
+    #[test]
+    fn curve_is_monotone_and_passes_through_its_points() {
+        let points = [
+            (0, 10),
+            (40, 120),
+            (60, 125),
+            (128, 128),
+            (200, 250),
+            (255, 255),
+        ];
+        let curve = monotone_spline(&points);
+        for &(x, y) in &points {
+            assert!((curve[x as usize] - y as f32).abs() < 1e-3, "at {}", x);
+        }
+        for pair in curve.windows(2) {
+            assert!(pair[1] >= pair[0], "{:?}", pair);
+        }
+
+        let flat = monotone_spline(&[(0, 0), (100, 80), (160, 80), (255, 255)]);
+        assert!(flat[100..=160]
+            .iter()
+            .all(|&value| (value - 80.0).abs() < 1e-3));
+    }
+
+    #[test]
+    fn levels_map_the_input_range_onto_the_output() {
+        let lut = Lut::levels(
+            &[Levels {
+                input: (30, 220),
+                gamma: 1.0,
+                output: (0, 255),
+            }; 3],
+        );
+        let mut image = ramp();
+        lut.apply(&mut image);
+        let red = channel(&image, 0);
+        assert!(red[..=30].iter().all(|&value| value == 0));
+        assert!(red[220..].iter().all(|&value| value == 255));
+        assert_eq!(red[125], 128);
+        assert!(red.windows(2).all(|pair| pair[1] >= pair[0]));
+    }
+
+    #[test]
+    fn neutral_settings_change_nothing() {
+        for lut in [
+            Lut::exposure(0.0),
+            Lut::gamma(1.0),
+            Lut::brightness_contrast(0.0, 0.0),
+            Lut::white_balance(0.0, 0.0),
+            Lut::curve(&[(0, 0), (255, 255)]),
+        ] {
+            let mut image = ramp();
+            lut.apply(&mut image);
+            assert_eq!(image.pixels(), ramp().pixels());
+        }
+    }
+
+    #[test]
+    fn gray_world_evens_out_a_tint() {
+        let pixels = (0..64 * 64)
+            .flat_map(|index| {
+                let gray = (index * 37 % 200 + 30) as f32;
+                [gray, gray * 0.8, gray * 0.55]
+                    .map(|value| value as u8)
+                    .into_iter()
+                    .chain([255])
+            })
+            .collect();
+        let mut image = RawImage::from_raw(pixels, 64, 64);
+        Lut::auto_white_balance(&image, WhiteBalance::GrayWorld).apply(&mut image);
+        let means = [0, 1, 2].map(|c| {
+            let values = channel(&image, c);
+            values.iter().map(|&value| value as f32).sum::<f32>() / values.len() as f32
+        });
+        assert!(
+            means.iter().all(|mean| (mean - means[1]).abs() < 1.0),
+            "{:?}",
+            means
+        );
+    }
+}
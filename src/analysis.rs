@@ -0,0 +1,883 @@
+use crate::background::{BackgroundModel, BackgroundView};
+use crate::binary::{self, BinaryImage};
+use crate::browser;
+use crate::canvas::{Point, Renderer};
+use crate::components::{self, Connectivity, Region};
+use crate::constants::{
+    ACCUMULATOR_SIZE, CANNY_HIGH, CANNY_LOW, CANNY_SIGMA, COMPONENTS_ALPHA, COMPONENTS_LOGGED,
+    CONTOUR_COLOR, CONTOUR_EPSILON, CONTOUR_MIN_AREA, FLOW_ARROW_SCALE, FLOW_ARROW_STEP,
+    FLOW_COLOR, FLOW_MAX_TRACKS, FLOW_MIN_ARROW, HOLE_COLOR, HOUGH_CENTER_VOTES,
+    HOUGH_CIRCLE_SUPPORT, HOUGH_COLOR, HOUGH_LINE_VOTES, HOUGH_MAX_CIRCLES, HOUGH_MAX_GAP,
+    HOUGH_MAX_LINES, HOUGH_MIN_SEGMENT, HOUGH_RADII, HOUGH_SEGMENT_VOTES, HOUGH_THETA_STEPS,
+    HULL_COLOR, KEYPOINT_COLOR, KEYPOINT_RADIUS, MATCH_COLOR, MATCH_RATIO, MAX_KEYPOINTS,
+    MIN_RECT_COLOR, MOTION_BOX_COLOR, ORB_FEATURES, OUTLIER_COLOR, RANSAC_CONFIDENCE,
+    RANSAC_MAX_ITERATIONS, RANSAC_THRESHOLD, STABILIZATION_WINDOW,
+};
+use crate::contours::{self, Contour};
+use crate::edges;
+use crate::features::{self, Detector, Keypoint};
+use crate::flow::{self, FlowMode};
+use crate::gray::GrayImage;
+use crate::homography::{self, Homography};
+use crate::hough::{self, HoughMode};
+use crate::image::RawImage;
+use crate::mask::Mask;
+use crate::motion::MotionDetector;
+use crate::orb::{self, Feature};
+use crate::seams::SeamCarver;
+use crate::stabilize::Stabilizer;
+use anyhow::Result;
+
+// Everything each simulation step does after the pipeline, one stage per
+// feature. Carving reshapes the frame, the temporal stages follow it from
+// step to step and the rest is worked out from the frame as it stands.
+pub struct Analysis {
+    pub carving: SeamCarver,
+    pub motion: MotionStage,
+    pub background: BackgroundStage,
+    pub stabilization: StabilizationStage,
+    pub flow: FlowStage,
+    pub detection: Detection,
+    pub matching: Matching,
+}
+
+impl Analysis {
+    pub fn new() -> Self {
+        Analysis {
+            carving: SeamCarver::new(),
+            motion: MotionStage::default(),
+            background: BackgroundStage::new(),
+            stabilization: StabilizationStage::new(),
+            flow: FlowStage::new(),
+            detection: Detection::new(),
+            matching: Matching::default(),
+        }
+    }
+
+    // Settings survive, everything learnt from earlier frames is dropped.
+    pub fn clear(&mut self) {
+        self.motion.clear();
+        self.background.clear();
+        self.stabilization.clear();
+        self.flow.clear();
+        self.detection.clear();
+        self.matching.clear();
+    }
+
+    // Motion, background and flow follow the frames as the source gives them,
+    // before any effect, while stabilization works on the frame to be shown.
+    pub fn step(
+        &mut self,
+        image: &mut RawImage,
+        source: Option<&RawImage>,
+        mask: Option<&Mask>,
+        position: Point,
+    ) {
+        self.carving.carve_seam(image, mask);
+        let input = source.unwrap_or(image);
+        self.motion.track(input);
+        self.background.track(input);
+        self.flow.track(input);
+        self.stabilization.track(image);
+        self.analyze(image, position);
+    }
+
+    pub fn analyze(&mut self, image: &RawImage, position: Point) {
+        self.detection.analyze(image, position);
+        self.matching.match_frame(image);
+    }
+
+    // What is shown instead of the processed frame, if anything.
+    pub fn view(&self, width: u32, height: u32) -> Option<RawImage> {
+        self.flow
+            .colors
+            .clone()
+            .or_else(|| self.background.view(width, height))
+    }
+
+    pub fn draw(&self, renderer: &Renderer, position: Point) -> Result<()> {
+        self.detection.draw(renderer, position)?;
+        self.flow.draw(renderer, position);
+        self.motion.draw(renderer, position)
+    }
+}
+
+#[derive(Default)]
+pub struct MotionStage {
+    detector: Option<MotionDetector>,
+    detections: Vec<Region>,
+}
+
+impl MotionStage {
+    pub fn toggle(&mut self) -> bool {
+        self.detector = match self.detector {
+            Some(_) => None,
+            None => Some(MotionDetector::new()),
+        };
+        self.detections.clear();
+        self.detector.is_some()
+    }
+
+    fn clear(&mut self) {
+        if self.detector.is_some() {
+            self.detector = Some(MotionDetector::new());
+        }
+        self.detections.clear();
+    }
+
+    fn track(&mut self, frame: &RawImage) {
+        if let Some(detector) = &mut self.detector {
+            self.detections = detector.detect(frame);
+            log_detections(&self.detections);
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer, position: Point) -> Result<()> {
+        let (x, y) = (position.x as f64, position.y as f64);
+        for region in &self.detections {
+            let bounds = &region.bounds;
+            let (left, top) = (x + bounds.x as f64, y + bounds.y as f64);
+            renderer.stroke_rect(
+                left,
+                top,
+                bounds.width.into(),
+                bounds.height.into(),
+                MOTION_BOX_COLOR,
+            );
+            renderer.fill_text(
+                &region.area.to_string(),
+                left + 2.0,
+                top + 12.0,
+                MOTION_BOX_COLOR,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub struct BackgroundStage {
+    model: BackgroundModel,
+    view: BackgroundView,
+    foreground: Vec<u8>,
+}
+
+impl BackgroundStage {
+    fn new() -> Self {
+        BackgroundStage {
+            model: BackgroundModel::new(),
+            view: BackgroundView::Off,
+            foreground: vec![],
+        }
+    }
+
+    pub fn view_mode(&self) -> BackgroundView {
+        self.view
+    }
+
+    // Leaving the off state starts learning from scratch.
+    pub fn next_view(&mut self) -> BackgroundView {
+        self.view = self.view.next();
+        if self.view == BackgroundView::Off {
+            self.clear();
+        }
+        self.view
+    }
+
+    pub fn learning_rate(&self) -> f32 {
+        self.model.learning_rate().unwrap_or_default()
+    }
+
+    pub fn set_learning_rate(&mut self, learning_rate: f32) {
+        let learning_rate = (learning_rate > 0.0).then_some(learning_rate);
+        self.model.set_learning_rate(learning_rate);
+    }
+
+    fn clear(&mut self) {
+        self.model.clear();
+        self.foreground.clear();
+    }
+
+    fn track(&mut self, frame: &RawImage) {
+        if self.view != BackgroundView::Off {
+            self.foreground = self.model.apply(frame);
+        }
+    }
+
+    fn view(&self, width: u32, height: u32) -> Option<RawImage> {
+        match self.view {
+            _ if self.foreground.is_empty() => None,
+            BackgroundView::Foreground => {
+                Some(BackgroundModel::mask_image(&self.foreground, width, height))
+            }
+            BackgroundView::Background => Some(self.model.background()),
+            BackgroundView::Frame | BackgroundView::Off => None,
+        }
+    }
+}
+
+pub struct StabilizationStage {
+    stabilizer: Option<Stabilizer>,
+    window: usize,
+    stabilized: Option<RawImage>,
+}
+
+impl StabilizationStage {
+    fn new() -> Self {
+        StabilizationStage {
+            stabilizer: None,
+            window: STABILIZATION_WINDOW,
+            stabilized: None,
+        }
+    }
+
+    pub fn toggle(&mut self) -> bool {
+        self.stabilizer = match self.stabilizer {
+            Some(_) => None,
+            None => Some(Stabilizer::new(self.window)),
+        };
+        self.stabilized = None;
+        self.is_active()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.stabilizer.is_some()
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window;
+        if let Some(stabilizer) = &mut self.stabilizer {
+            stabilizer.set_window(window);
+        }
+    }
+
+    // The stabilized frame is only shown, the pipeline keeps working on the
+    // frame as it came.
+    pub fn frame(&self) -> Option<&RawImage> {
+        self.stabilized.as_ref()
+    }
+
+    // Whenever the frame is replaced outside of a simulation step.
+    pub fn forget(&mut self) {
+        self.stabilized = None;
+    }
+
+    fn clear(&mut self) {
+        if self.stabilizer.is_some() {
+            self.stabilizer = Some(Stabilizer::new(self.window));
+        }
+        self.stabilized = None;
+    }
+
+    fn track(&mut self, frame: &RawImage) {
+        if let Some(stabilizer) = &mut self.stabilizer {
+            self.stabilized = Some(stabilizer.stabilize(frame));
+        }
+    }
+}
+
+pub struct FlowStage {
+    mode: FlowMode,
+    previous: Option<GrayImage>,
+    tracks: Vec<((f32, f32), (f32, f32))>,
+    colors: Option<RawImage>,
+}
+
+impl FlowStage {
+    fn new() -> Self {
+        FlowStage {
+            mode: FlowMode::Off,
+            previous: None,
+            tracks: vec![],
+            colors: None,
+        }
+    }
+
+    pub fn mode(&self) -> FlowMode {
+        self.mode
+    }
+
+    pub fn next_mode(&mut self) -> FlowMode {
+        self.mode = self.mode.next();
+        self.clear();
+        self.mode
+    }
+
+    pub fn clear(&mut self) {
+        self.previous = None;
+        self.tracks.clear();
+        self.colors = None;
+    }
+
+    // Flow needs two frames of the same size, so the first one only becomes
+    // the reference for the next.
+    fn track(&mut self, frame: &RawImage) {
+        if self.mode == FlowMode::Off {
+            return;
+        }
+        let gray = GrayImage::from_raw(frame);
+        let previous = match self.previous.replace(gray.clone()) {
+            Some(previous)
+                if previous.width() == gray.width() && previous.height() == gray.height() =>
+            {
+                previous
+            }
+            _ => return,
+        };
+        match self.mode {
+            FlowMode::Sparse => {
+                let points: Vec<(f32, f32)> = features::shi_tomasi(&previous, FLOW_MAX_TRACKS)
+                    .iter()
+                    .map(|keypoint| (keypoint.x, keypoint.y))
+                    .collect();
+                self.tracks = points
+                    .iter()
+                    .zip(flow::lucas_kanade(&previous, &gray, &points))
+                    .filter_map(|(&start, end)| Some((start, end?)))
+                    .collect();
+                log!("{} of {} points tracked", self.tracks.len(), points.len());
+            }
+            FlowMode::Dense => {
+                let field = flow::farneback(&previous, &gray);
+                log!("mean flow of {:.2} pixels", field.mean_magnitude());
+                self.tracks = field.arrows(FLOW_ARROW_STEP, FLOW_MIN_ARROW, FLOW_ARROW_SCALE);
+                self.colors = Some(field.to_colors());
+            }
+            FlowMode::Off => {}
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer, position: Point) {
+        let to_point = |(x, y): (f32, f32)| Point {
+            x: position.x + x.round() as i16,
+            y: position.y + y.round() as i16,
+        };
+        for &(start, end) in &self.tracks {
+            renderer.stroke_arrow(&to_point(start), &to_point(end), FLOW_COLOR);
+        }
+    }
+}
+
+// Hough detections in canvas coordinates, with the votes they came from.
+struct Shapes {
+    lines: Vec<(Point, Point)>,
+    circles: Vec<(Point, f64)>,
+    accumulator: RawImage,
+}
+
+// A contour with its simplified polygon, hull and minimum-area rectangle, in
+// canvas coordinates.
+struct Outline {
+    polygon: Vec<Point>,
+    hull: Vec<Point>,
+    rect: Vec<Point>,
+    hole: bool,
+}
+
+// Components, contours, Hough shapes and corners of the frame as it stands.
+pub struct Detection {
+    connectivity: Option<Connectivity>,
+    components: Option<RawImage>,
+    outlines: Option<Vec<Outline>>,
+    hough_mode: HoughMode,
+    shapes: Option<Shapes>,
+    show_accumulator: bool,
+    detector: Option<Detector>,
+    max_keypoints: usize,
+    keypoints: Vec<Keypoint>,
+}
+
+impl Detection {
+    fn new() -> Self {
+        Detection {
+            connectivity: None,
+            components: None,
+            outlines: None,
+            hough_mode: HoughMode::Off,
+            shapes: None,
+            show_accumulator: false,
+            detector: None,
+            max_keypoints: MAX_KEYPOINTS,
+            keypoints: vec![],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.components = None;
+        if self.outlines.is_some() {
+            self.outlines = Some(vec![]);
+        }
+        self.shapes = None;
+        self.keypoints.clear();
+    }
+
+    pub fn connectivity(&self) -> Option<Connectivity> {
+        self.connectivity
+    }
+
+    pub fn next_connectivity(&mut self) -> Option<Connectivity> {
+        self.connectivity = match self.connectivity {
+            None => Some(Connectivity::Four),
+            Some(Connectivity::Four) => Some(Connectivity::Eight),
+            Some(Connectivity::Eight) => None,
+        };
+        self.connectivity
+    }
+
+    pub fn toggle_contours(&mut self) -> bool {
+        self.outlines = match self.outlines {
+            Some(_) => None,
+            None => Some(vec![]),
+        };
+        self.outlines.is_some()
+    }
+
+    pub fn hough_mode(&self) -> HoughMode {
+        self.hough_mode
+    }
+
+    pub fn next_hough_mode(&mut self) -> HoughMode {
+        self.hough_mode = self.hough_mode.next();
+        self.hough_mode
+    }
+
+    pub fn toggle_accumulator(&mut self) -> bool {
+        self.show_accumulator = !self.show_accumulator;
+        self.show_accumulator
+    }
+
+    pub fn accumulator(&self) -> Option<&RawImage> {
+        self.shapes
+            .as_ref()
+            .filter(|_| self.show_accumulator)
+            .map(|shapes| &shapes.accumulator)
+    }
+
+    pub fn detector(&self) -> Option<Detector> {
+        self.detector
+    }
+
+    pub fn next_detector(&mut self) -> Option<Detector> {
+        self.detector = match self.detector {
+            None => Some(Detector::Harris),
+            Some(Detector::Harris) => Some(Detector::ShiTomasi),
+            Some(Detector::ShiTomasi) => Some(Detector::Fast),
+            Some(Detector::Fast) => None,
+        };
+        self.detector
+    }
+
+    pub fn max_keypoints(&self) -> usize {
+        self.max_keypoints
+    }
+
+    pub fn set_max_keypoints(&mut self, max_keypoints: usize) {
+        self.max_keypoints = max_keypoints;
+    }
+
+    pub fn analyze(&mut self, image: &RawImage, position: Point) {
+        if image.is_empty() {
+            return;
+        }
+        self.label_components(image);
+        self.trace_contours(image, position);
+        self.detect_shapes(image, position);
+        self.detect_keypoints(image);
+    }
+
+    fn detect_keypoints(&mut self, image: &RawImage) {
+        self.keypoints = match self.detector {
+            Some(detector) => detector.detect(&GrayImage::from_raw(image), self.max_keypoints),
+            None => vec![],
+        };
+    }
+
+    fn detect_shapes(&mut self, image: &RawImage, position: Point) {
+        if self.hough_mode == HoughMode::Off {
+            self.shapes = None;
+            return;
+        }
+        let gray = GrayImage::from_raw(image).blur(CANNY_SIGMA);
+        let (gx, gy) = gray.sobel();
+        let edges = edges::canny(&gx, &gy, CANNY_LOW, CANNY_HIGH);
+        let to_point = |(px, py): (f32, f32)| Point {
+            x: position.x + px.round() as i16,
+            y: position.y + py.round() as i16,
+        };
+        let to_points = |(px, py): (i32, i32)| to_point((px as f32, py as f32));
+
+        let shapes = match self.hough_mode {
+            HoughMode::Lines => {
+                let (lines, accumulator) =
+                    hough::lines(&edges, HOUGH_THETA_STEPS, HOUGH_LINE_VOTES, HOUGH_MAX_LINES);
+                for line in &lines {
+                    log!(
+                        "line rho {:.0} theta {:.1}° with {} votes",
+                        line.rho,
+                        line.theta.to_degrees(),
+                        line.votes
+                    );
+                }
+                let lines = lines
+                    .iter()
+                    .filter_map(|line| line.endpoints(image.width(), image.height()))
+                    .map(|(from, to)| (to_point(from), to_point(to)))
+                    .collect();
+                Shapes {
+                    lines,
+                    circles: vec![],
+                    accumulator: accumulator.to_image(),
+                }
+            }
+            HoughMode::Segments => {
+                let (segments, accumulator) = hough::segments(
+                    &edges,
+                    HOUGH_THETA_STEPS,
+                    HOUGH_SEGMENT_VOTES,
+                    HOUGH_MIN_SEGMENT,
+                    HOUGH_MAX_GAP,
+                    HOUGH_MAX_LINES,
+                );
+                for segment in &segments {
+                    log!(
+                        "segment {:?} to {:?} with {} votes",
+                        segment.start,
+                        segment.end,
+                        segment.votes
+                    );
+                }
+                Shapes {
+                    lines: segments
+                        .iter()
+                        .map(|segment| (to_points(segment.start), to_points(segment.end)))
+                        .collect(),
+                    circles: vec![],
+                    accumulator: accumulator.to_image(),
+                }
+            }
+            HoughMode::Circles => {
+                let (circles, accumulator) = hough::circles(
+                    &edges,
+                    (&gx, &gy),
+                    HOUGH_RADII,
+                    HOUGH_CENTER_VOTES,
+                    HOUGH_CIRCLE_SUPPORT,
+                    HOUGH_MAX_CIRCLES,
+                );
+                for circle in &circles {
+                    log!(
+                        "circle at {:?} of radius {} with {} votes",
+                        circle.center,
+                        circle.radius,
+                        circle.votes
+                    );
+                }
+                Shapes {
+                    lines: vec![],
+                    circles: circles
+                        .iter()
+                        .map(|circle| (to_points(circle.center), circle.radius.into()))
+                        .collect(),
+                    accumulator: accumulator.to_image(),
+                }
+            }
+            HoughMode::Off => return,
+        };
+        self.shapes = Some(shapes);
+    }
+
+    fn trace_contours(&mut self, image: &RawImage, position: Point) {
+        if self.outlines.is_none() {
+            return;
+        }
+        let (binary, _) = threshold(image);
+        let to_point = |(x, y): (f32, f32)| Point {
+            x: position.x + x.round() as i16,
+            y: position.y + y.round() as i16,
+        };
+        let to_points = |points: &[(i32, i32)]| {
+            points
+                .iter()
+                .map(|&(x, y)| to_point((x as f32, y as f32)))
+                .collect()
+        };
+
+        let contours = contours::find_contours(&binary);
+        log_contours(&contours);
+        let outlines = contours
+            .iter()
+            .filter(|contour| contours::moments(&contour.points).m00 >= CONTOUR_MIN_AREA)
+            .map(|contour| Outline {
+                polygon: to_points(&contours::simplify(&contour.points, CONTOUR_EPSILON)),
+                hull: to_points(&contours::convex_hull(&contour.points)),
+                rect: contours::min_area_rect(&contour.points)
+                    .map_or(vec![], |rect| rect.corners().map(to_point).to_vec()),
+                hole: contour.hole,
+            })
+            .collect();
+        self.outlines = Some(outlines);
+    }
+
+    fn label_components(&mut self, image: &RawImage) {
+        self.components = self.connectivity.map(|connectivity| {
+            let (binary, level) = threshold(image);
+            let components = components::label(&binary, connectivity);
+            log_components(&components.regions, level);
+            components.colorize(COMPONENTS_ALPHA)
+        });
+    }
+
+    fn draw(&self, renderer: &Renderer, position: Point) -> Result<()> {
+        renderer.draw_side(self.accumulator(), ACCUMULATOR_SIZE)?;
+        if let Some(components) = &self.components {
+            renderer.draw_overlay(components, &position)?;
+        }
+        self.draw_outlines(renderer);
+        self.draw_shapes(renderer)?;
+        for keypoint in &self.keypoints {
+            let center = Point {
+                x: position.x + keypoint.x.round() as i16,
+                y: position.y + keypoint.y.round() as i16,
+            };
+            renderer.stroke_circle(&center, KEYPOINT_RADIUS, KEYPOINT_COLOR)?;
+        }
+        Ok(())
+    }
+
+    // Holes only get their polygon, the hull and rectangle of the region
+    // already cover them.
+    fn draw_outlines(&self, renderer: &Renderer) {
+        for outline in self.outlines.iter().flatten() {
+            if outline.hole {
+                renderer.stroke_polygon(&outline.polygon, HOLE_COLOR);
+            } else {
+                renderer.stroke_polygon(&outline.rect, MIN_RECT_COLOR);
+                renderer.stroke_polygon(&outline.hull, HULL_COLOR);
+                renderer.stroke_polygon(&outline.polygon, CONTOUR_COLOR);
+            }
+        }
+    }
+
+    fn draw_shapes(&self, renderer: &Renderer) -> Result<()> {
+        let Some(shapes) = &self.shapes else {
+            return Ok(());
+        };
+        for (from, to) in &shapes.lines {
+            renderer.stroke_line(from, to, HOUGH_COLOR);
+        }
+        for (center, radius) in &shapes.circles {
+            renderer.stroke_circle(center, *radius, HOUGH_COLOR)?;
+        }
+        Ok(())
+    }
+}
+
+fn threshold(image: &RawImage) -> (BinaryImage, u8) {
+    let level = binary::otsu(image);
+    (BinaryImage::threshold(image, level), level)
+}
+
+// ORB matches between the frame and a second image, and the homography they
+// agree on.
+#[derive(Default)]
+pub struct Matching {
+    image: Option<(RawImage, Vec<Feature>)>,
+    matches: Vec<((f32, f32), (f32, f32))>,
+    inliers: Vec<bool>,
+    alignment: Option<Homography>,
+    show: bool,
+}
+
+impl Matching {
+    // The second image is described once, the frame every time it changes.
+    pub fn set_image(&mut self, image: RawImage, frame: &RawImage) {
+        let features = orb::orb(&GrayImage::from_raw(&image), ORB_FEATURES);
+        log!("{} features in the match image", features.len());
+        self.image = Some((image, features));
+        self.show = true;
+        self.match_frame(frame);
+    }
+
+    pub fn toggle(&mut self) -> bool {
+        self.show = !self.show && self.image.is_some();
+        self.show
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.show
+    }
+
+    fn clear(&mut self) {
+        self.matches.clear();
+        self.inliers.clear();
+        self.alignment = None;
+    }
+
+    fn match_frame(&mut self, frame: &RawImage) {
+        self.matches = match &self.image {
+            Some((_, train)) if !frame.is_empty() => {
+                let query = orb::orb(&GrayImage::from_raw(frame), ORB_FEATURES);
+                let matches = orb::match_features(&query, train, MATCH_RATIO, true);
+                let distance: u32 = matches.iter().map(|m| m.distance).sum();
+                log!(
+                    "{} of {} features matched, mean distance {:.1}",
+                    matches.len(),
+                    query.len(),
+                    distance as f32 / matches.len().max(1) as f32
+                );
+                matches
+                    .iter()
+                    .map(|m| {
+                        let (a, b) = (query[m.query].keypoint, train[m.train].keypoint);
+                        ((a.x, a.y), (b.x, b.y))
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        };
+        self.estimate_alignment();
+    }
+
+    // The homography maps the match image onto the frame.
+    fn estimate_alignment(&mut self) {
+        let correspondences: Vec<_> = self
+            .matches
+            .iter()
+            .map(|&(frame, other)| {
+                (
+                    (other.0 as f64, other.1 as f64),
+                    (frame.0 as f64, frame.1 as f64),
+                )
+            })
+            .collect();
+        let estimate = homography::ransac(
+            &correspondences,
+            RANSAC_THRESHOLD,
+            RANSAC_CONFIDENCE,
+            RANSAC_MAX_ITERATIONS,
+        );
+        match estimate {
+            Some(estimate) => {
+                log!(
+                    "homography {:?} with {} of {} matches as inliers",
+                    estimate.homography.0,
+                    estimate.inlier_count(),
+                    correspondences.len()
+                );
+                self.alignment = Some(estimate.homography);
+                self.inliers = estimate.inliers;
+            }
+            None => {
+                self.alignment = None;
+                self.inliers = vec![false; self.matches.len()];
+            }
+        }
+    }
+
+    // The match image warped onto a frame of the given size, which hides the
+    // matches so the alignment can be checked against what is underneath.
+    pub fn align(&mut self, width: u32, height: u32) -> Option<RawImage> {
+        let (other, _) = self.image.as_ref()?;
+        let warped = homography::warp(other, &self.alignment?, width, height);
+        self.show = false;
+        Some(warped)
+    }
+
+    // Both images side by side, scaled down together when they don't fit.
+    pub fn draw(&self, renderer: &Renderer, frame: &RawImage, position: Point) -> Result<()> {
+        let Some((other, _)) = &self.image else {
+            return Ok(());
+        };
+        let (left, right) = (frame.width() as f64, other.width() as f64);
+        let scale = (renderer.width() / (left + right)).min(1.0);
+        let (x, y) = (position.x as f64, position.y as f64);
+        let offset = x + left * scale;
+        renderer.clear();
+        renderer.draw_inset(frame, x, y, left * scale, frame.height() as f64 * scale)?;
+        renderer.draw_inset(
+            other,
+            offset,
+            y,
+            right * scale,
+            other.height() as f64 * scale,
+        )?;
+        let to_point = |origin: f64, (px, py): (f32, f32)| Point {
+            x: (origin + px as f64 * scale).round() as i16,
+            y: (y + py as f64 * scale).round() as i16,
+        };
+        for (&(from, to), &inlier) in self.matches.iter().zip(&self.inliers) {
+            let color = if inlier { MATCH_COLOR } else { OUTLIER_COLOR };
+            renderer.stroke_line(&to_point(x, from), &to_point(offset, to), color);
+        }
+        Ok(())
+    }
+}
+
+fn log_contours(contours: &[Contour]) {
+    let holes = contours.iter().filter(|contour| contour.hole).count();
+    log!("{} borders, {} of them holes", contours.len(), holes);
+    let mut largest: Vec<(usize, contours::Moments)> = contours
+        .iter()
+        .enumerate()
+        .filter(|(_, contour)| !contour.hole)
+        .map(|(index, contour)| (index, contours::moments(&contour.points)))
+        .filter(|(_, moments)| moments.m00 >= CONTOUR_MIN_AREA)
+        .collect();
+    largest.sort_by(|a, b| b.1.m00.total_cmp(&a.1.m00));
+    for (index, moments) in largest.into_iter().take(COMPONENTS_LOGGED) {
+        let (x, y) = moments.centroid().unwrap_or_default();
+        let children = contours
+            .iter()
+            .filter(|contour| contour.parent == Some(index))
+            .count();
+        log!(
+            "contour of area {:.0} around ({:.1}, {:.1}) with {} holes",
+            moments.m00,
+            x,
+            y,
+            children
+        );
+    }
+}
+
+// Only the largest components are worth reading in the console.
+fn log_components(regions: &[Region], level: u8) {
+    log!("{} components above level {}", regions.len(), level);
+    let mut largest: Vec<&Region> = regions.iter().collect();
+    largest.sort_by_key(|region| std::cmp::Reverse(region.area));
+    for region in largest.into_iter().take(COMPONENTS_LOGGED) {
+        log!(
+            "area {}, centroid ({:.1}, {:.1}), perimeter {}, orientation {:.1}°, eccentricity {:.2}",
+            region.area,
+            region.centroid.0,
+            region.centroid.1,
+            region.perimeter,
+            region.orientation.to_degrees(),
+            region.eccentricity
+        );
+    }
+}
+
+fn log_detections(detections: &[Region]) {
+    if detections.is_empty() {
+        return;
+    }
+    let timestamp = browser::now().unwrap_or_default();
+    for region in detections {
+        let bounds = &region.bounds;
+        log!(
+            "[{:.0}ms] motion at {}x{}+{}+{}, area {}, centroid ({:.1}, {:.1})",
+            timestamp,
+            bounds.width,
+            bounds.height,
+            bounds.x,
+            bounds.y,
+            region.area,
+            region.centroid.0,
+            region.centroid.1
+        );
+    }
+}
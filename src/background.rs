@@ -0,0 +1,361 @@
+use crate::constants::{
+    MOG2_BACKGROUND_RATIO, MOG2_COMPLEXITY_REDUCTION, MOG2_HISTORY, MOG2_MAX_MODES,
+    MOG2_SHADOW_THRESHOLD, MOG2_VARIANCE_INIT, MOG2_VARIANCE_MAX, MOG2_VARIANCE_MIN,
+    MOG2_VARIANCE_THRESHOLD, MOG2_VARIANCE_THRESHOLD_GENERATE,
+};
+use crate::image::RawImage;
+
+pub const FOREGROUND: u8 = 255;
+pub const SHADOW: u8 = 127;
+pub const BACKGROUND: u8 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackgroundView {
+    Off,
+    Frame,
+    Foreground,
+    Background,
+}
+
+impl BackgroundView {
+    pub fn next(self) -> Self {
+        match self {
+            BackgroundView::Off => BackgroundView::Frame,
+            BackgroundView::Frame => BackgroundView::Foreground,
+            BackgroundView::Foreground => BackgroundView::Background,
+            BackgroundView::Background => BackgroundView::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BackgroundView::Off => "Background: off",
+            BackgroundView::Frame => "Background: learning",
+            BackgroundView::Foreground => "Background: mask",
+            BackgroundView::Background => "Background: model",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Mode {
+    weight: f32,
+    mean: [f32; 3],
+    variance: f32,
+}
+
+// Zivkovic's adaptive mixture (MOG2): every pixel keeps up to a few gaussians
+// sorted by weight, and the heaviest ones that together explain most of the
+// history are the background. Foreground pixels that look like a darker copy
+// of the background are marked as shadows (Prati et al.).
+pub struct BackgroundModel {
+    modes: Vec<Mode>,
+    counts: Vec<u8>,
+    width: u32,
+    height: u32,
+    frames: u32,
+    learning_rate: Option<f32>,
+}
+
+impl BackgroundModel {
+    pub fn new() -> Self {
+        BackgroundModel {
+            modes: vec![],
+            counts: vec![],
+            width: 0,
+            height: 0,
+            frames: 0,
+            learning_rate: None,
+        }
+    }
+
+    pub fn learning_rate(&self) -> Option<f32> {
+        self.learning_rate
+    }
+
+    // Without a fixed rate the model learns from the average of the frames
+    // seen so far, up to a history length.
+    pub fn set_learning_rate(&mut self, learning_rate: Option<f32>) {
+        self.learning_rate = learning_rate;
+    }
+
+    // Returns the foreground mask with FOREGROUND, SHADOW or BACKGROUND per
+    // pixel, and updates the model with the frame.
+    pub fn apply(&mut self, frame: &RawImage) -> Vec<u8> {
+        if frame.width() != self.width || frame.height() != self.height {
+            self.reset(frame.width(), frame.height());
+        }
+        self.frames = self.frames.saturating_add(1);
+        let alpha = self
+            .learning_rate
+            .unwrap_or(1.0 / self.frames.min(MOG2_HISTORY) as f32)
+            .clamp(0.0, 1.0);
+
+        frame
+            .pixels()
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(i, pixel)| {
+                let color = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+                let start = i * MOG2_MAX_MODES;
+                let modes = &mut self.modes[start..start + MOG2_MAX_MODES];
+                let count = &mut self.counts[i];
+                update_pixel(modes, count, color, alpha)
+            })
+            .collect()
+    }
+
+    // Weighted mean of the background modes of each pixel.
+    pub fn background(&self) -> RawImage {
+        let mut pixels = Vec::with_capacity(self.counts.len() * 4);
+        for (i, &count) in self.counts.iter().enumerate() {
+            let modes = &self.modes[i * MOG2_MAX_MODES..i * MOG2_MAX_MODES + count as usize];
+            let (mut color, mut total) = ([0.0; 3], 0.0);
+            for mode in modes {
+                for (value, mean) in color.iter_mut().zip(mode.mean) {
+                    *value += mode.weight * mean;
+                }
+                total += mode.weight;
+                if total > MOG2_BACKGROUND_RATIO {
+                    break;
+                }
+            }
+            let total = if total > 0.0 { total } else { 1.0 };
+            pixels.extend(color.map(|value| (value / total).round().clamp(0.0, 255.0) as u8));
+            pixels.push(255);
+        }
+        RawImage::from_raw(pixels, self.width, self.height)
+    }
+
+    // The mask as an opaque grayscale image.
+    pub fn mask_image(mask: &[u8], width: u32, height: u32) -> RawImage {
+        let pixels = mask
+            .iter()
+            .flat_map(|&value| [value, value, value, 255])
+            .collect();
+        RawImage::from_raw(pixels, width, height)
+    }
+
+    pub fn clear(&mut self) {
+        self.reset(0, 0);
+    }
+
+    fn reset(&mut self, width: u32, height: u32) {
+        let pixels = (width * height) as usize;
+        self.modes = vec![Mode::default(); pixels * MOG2_MAX_MODES];
+        self.counts = vec![0; pixels];
+        self.width = width;
+        self.height = height;
+        self.frames = 0;
+    }
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+fn update_pixel(modes: &mut [Mode], count: &mut u8, color: [f32; 3], alpha: f32) -> u8 {
+    let prune = alpha * MOG2_COMPLEXITY_REDUCTION;
+    let mut matched = false;
+    let mut background = false;
+    let mut cumulative = 0.0;
+    let mut n = *count as usize;
+
+    let mut m = 0;
+    while m < n {
+        let mode = &mut modes[m];
+        let in_background = cumulative < MOG2_BACKGROUND_RATIO;
+        cumulative += mode.weight;
+        let mut weight = (1.0 - alpha) * mode.weight - prune;
+
+        if !matched {
+            let d2 = distance(color, mode.mean);
+            if in_background && d2 < MOG2_VARIANCE_THRESHOLD * mode.variance {
+                background = true;
+            }
+            if d2 < MOG2_VARIANCE_THRESHOLD_GENERATE * mode.variance {
+                matched = true;
+                weight += alpha;
+                let k = alpha / weight.max(f32::EPSILON);
+                for (mean, value) in mode.mean.iter_mut().zip(color) {
+                    *mean += k * (value - *mean);
+                }
+                mode.variance = (mode.variance + k * (d2 - mode.variance))
+                    .clamp(MOG2_VARIANCE_MIN, MOG2_VARIANCE_MAX);
+            }
+        }
+
+        if weight < 0.0 {
+            // Modes that lost all their support are dropped.
+            modes.copy_within(m + 1..n, m);
+            n -= 1;
+            continue;
+        }
+        modes[m].weight = weight;
+        m += 1;
+    }
+
+    // Decided before a mode is made for an unmatched color, which would
+    // otherwise explain the color as a shadow of itself.
+    let shadow = !background && is_shadow(&modes[..n], color);
+
+    if !matched {
+        if n == modes.len() {
+            n -= 1;
+        }
+        modes[n] = Mode {
+            weight: alpha.max(f32::EPSILON),
+            mean: color,
+            variance: MOG2_VARIANCE_INIT,
+        };
+        n += 1;
+    }
+
+    let total: f32 = modes[..n].iter().map(|mode| mode.weight).sum();
+    if total > 0.0 {
+        for mode in &mut modes[..n] {
+            mode.weight /= total;
+        }
+    }
+    modes[..n].sort_by(|a, b| b.weight.total_cmp(&a.weight));
+    *count = n as u8;
+
+    if background {
+        BACKGROUND
+    } else if shadow {
+        SHADOW
+    } else {
+        FOREGROUND
+    }
+}
+
+// A shadow keeps the chromaticity of a background mode at a lower brightness.
+fn is_shadow(modes: &[Mode], color: [f32; 3]) -> bool {
+    let total: f32 = modes.iter().map(|mode| mode.weight).sum();
+    let mut cumulative = 0.0;
+    for mode in modes {
+        let norm = distance(mode.mean, [0.0; 3]);
+        if norm > 0.0 {
+            let dot: f32 = (0..3).map(|c| color[c] * mode.mean[c]).sum();
+            let a = dot / norm;
+            if (MOG2_SHADOW_THRESHOLD..=1.0).contains(&a) {
+                let d2 = distance(mode.mean.map(|mean| a * mean), color);
+                if d2 < MOG2_VARIANCE_THRESHOLD * mode.variance * a * a {
+                    return true;
+                }
+            }
+        }
+        cumulative += mode.weight;
+        if cumulative > MOG2_BACKGROUND_RATIO * total {
+            break;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Xorshift;
+
+    const SCENE: [u8; 4] = [120, 90, 60, 255];
+
+    // The scene with a little sensor noise, and a square of another color at
+    // the top left when one is given.
+    fn frame(random: &mut Xorshift, square: Option<[u8; 4]>) -> RawImage {
+        let mut pixels = Vec::with_capacity(32 * 32 * 4);
+        for y in 0..32 {
+            for x in 0..32 {
+                let color = match square {
+                    Some(color) if x < 8 && y < 8 => color,
+                    _ => SCENE,
+                };
+                pixels.extend(
+                    color[..3].iter().map(|&value| {
+                        (value as i32 + random.below(5) as i32 - 2).clamp(0, 255) as u8
+                    }),
+                );
+                pixels.push(255);
+            }
+        }
+        RawImage::from_raw(pixels, 32, 32)
+    }
+
+    fn learned(random: &mut Xorshift) -> BackgroundModel {
+        let mut model = BackgroundModel::new();
+        for _ in 0..30 {
+            model.apply(&frame(random, None));
+        }
+        model
+    }
+
+    fn in_square(i: usize) -> bool {
+        i % 32 < 8 && i / 32 < 8
+    }
+
+    #[test]
+    fn static_scene_becomes_the_background() {
+        let mut random = Xorshift::new(7);
+        let mut model = learned(&mut random);
+        let mask = model.apply(&frame(&mut random, None));
+        assert!(mask.iter().all(|&value| value == BACKGROUND));
+        for pixel in model.background().pixels().chunks_exact(4) {
+            for (value, expected) in pixel.iter().zip(SCENE) {
+                assert!(value.abs_diff(expected) <= 2, "{:?}", pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn new_object_is_foreground() {
+        let mut random = Xorshift::new(7);
+        let mut model = learned(&mut random);
+        let mask = model.apply(&frame(&mut random, Some([20, 200, 240, 255])));
+        for (i, &value) in mask.iter().enumerate() {
+            let expected = if in_square(i) { FOREGROUND } else { BACKGROUND };
+            assert_eq!(value, expected, "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn darker_copy_of_the_background_is_a_shadow() {
+        let mut random = Xorshift::new(7);
+        let mut model = learned(&mut random);
+        let shade = SCENE.map(|value| (value as f32 * 0.7) as u8);
+        let mask = model.apply(&frame(
+            &mut random,
+            Some([shade[0], shade[1], shade[2], 255]),
+        ));
+        assert!((0..mask.len())
+            .filter(|&i| in_square(i))
+            .all(|i| mask[i] == SHADOW));
+    }
+
+    #[test]
+    fn object_that_stays_is_absorbed_into_the_background() {
+        let mut random = Xorshift::new(7);
+        let mut model = learned(&mut random);
+        model.set_learning_rate(Some(0.1));
+        let object = Some([20, 200, 240, 255]);
+        assert_eq!(model.apply(&frame(&mut random, object))[0], FOREGROUND);
+        for _ in 0..50 {
+            model.apply(&frame(&mut random, object));
+        }
+        assert!(model
+            .apply(&frame(&mut random, object))
+            .iter()
+            .all(|&value| value == BACKGROUND));
+    }
+
+    #[test]
+    fn change_of_size_starts_a_new_model() {
+        let mut random = Xorshift::new(7);
+        let mut model = learned(&mut random);
+        let other = RawImage::from_raw([0, 0, 0, 255].repeat(16 * 8), 16, 8);
+        let mask = model.apply(&other);
+        assert_eq!(mask.len(), 16 * 8);
+        // A single frame is all the history there is, so it explains itself.
+        assert!(mask.iter().all(|&value| value == FOREGROUND));
+        assert_eq!(model.background().pixels(), other.pixels());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::image::RawImage;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone)]
+pub struct BinaryImage {
+    values: Vec<bool>,
+    width: u32,
+    height: u32,
+}
+
+impl BinaryImage {
+    pub fn from_values(values: Vec<bool>, width: u32, height: u32) -> Self {
+        BinaryImage {
+            values,
+            width,
+            height,
+        }
+    }
+
+    // Pixels brighter than the level are set.
+    pub fn threshold(image: &RawImage, level: u8) -> Self {
+        let values = image
+            .pixels()
+            .chunks_exact(4)
+            .map(|pixel| luma(pixel) > level as i32)
+            .collect();
+        BinaryImage::from_values(values, image.width(), image.height())
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn values(&self) -> &[bool] {
+        &self.values
+    }
+
+    // Square structuring elements are separable, so each pass is a running
+    // count along one axis. Outside the image counts as set for erosion and as
+    // unset for dilation, so borders are left alone.
+    pub fn erode(&self, radius: u32) -> Self {
+        self.morph(radius, true)
+    }
+
+    pub fn dilate(&self, radius: u32) -> Self {
+        self.morph(radius, false)
+    }
+
+    // Opening removes specks smaller than the element.
+    pub fn open(&self, radius: u32) -> Self {
+        self.erode(radius).dilate(radius)
+    }
+
+    // Closing fills gaps and holes smaller than the element.
+    pub fn close(&self, radius: u32) -> Self {
+        self.dilate(radius).erode(radius)
+    }
+
+    fn morph(&self, radius: u32, erode: bool) -> Self {
+        if radius == 0 {
+            return self.clone();
+        }
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut horizontal = vec![false; self.values.len()];
+        for y in 0..height {
+            let row = &self.values[y * width..(y + 1) * width];
+            sweep(row.iter().copied(), radius as usize, erode, |x, value| {
+                horizontal[y * width + x] = value
+            });
+        }
+        let mut values = vec![false; self.values.len()];
+        for x in 0..width {
+            let column = (0..height).map(|y| horizontal[y * width + x]);
+            sweep(column, radius as usize, erode, |y, value| {
+                values[y * width + x] = value
+            });
+        }
+        BinaryImage {
+            values,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+fn sweep(
+    line: impl Iterator<Item = bool>,
+    radius: usize,
+    erode: bool,
+    mut write: impl FnMut(usize, bool),
+) {
+    let mut prefix = vec![0];
+    for value in line {
+        prefix.push(prefix[prefix.len() - 1] + value as usize);
+    }
+    let len = prefix.len() - 1;
+    for i in 0..len {
+        let (low, high) = (i.saturating_sub(radius), (i + radius + 1).min(len));
+        let count = prefix[high] - prefix[low];
+        write(
+            i,
+            if erode {
+                count == high - low
+            } else {
+                count > 0
+            },
+        );
+    }
+}
+
+pub fn luma(pixel: &[u8]) -> i32 {
+    (299 * pixel[0] as i32 + 587 * pixel[1] as i32 + 114 * pixel[2] as i32) / 1000
+}
+
+// Otsu's level: the one that maximises the variance between the two classes
+// of the luma histogram.
+pub fn otsu(image: &RawImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels().chunks_exact(4) {
+        histogram[luma(pixel) as usize] += 1;
+    }
+    let total: u64 = histogram.iter().sum();
+    let sum: f64 = (0..256).map(|i| i as f64 * histogram[i] as f64).sum();
+
+    let (mut below, mut below_sum) = (0u64, 0.0);
+    let (mut best, mut best_variance) = (0, 0.0);
+    for (level, &count) in histogram.iter().enumerate() {
+        below += count;
+        below_sum += level as f64 * count as f64;
+        let above = total - below;
+        if below == 0 || above == 0 {
+            continue;
+        }
+        let difference = below_sum / below as f64 - (sum - below_sum) / above as f64;
+        let variance = below as f64 * above as f64 * difference * difference;
+        if variance > best_variance {
+            best = level;
+            best_variance = variance;
+        }
+    }
+    best as u8
+}
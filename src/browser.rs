@@ -7,12 +7,21 @@ use web_sys::{
     Window,
 };
 
+#[cfg(not(test))]
 macro_rules! log {
     ($($t:tt)*) => {
         web_sys::console::log_1(&format!( $( $t )*).into());
     };
 }
 
+// Native tests have no console to log to.
+#[cfg(test)]
+macro_rules! log {
+    ($($t:tt)*) => {
+        println!( $( $t )*);
+    };
+}
+
 macro_rules! error {
     ( $( $t:tt )* ) => {
         web_sys::console::error_1(&format!( $( $t )*).into());
@@ -33,6 +42,8 @@ pub fn request_animation_frame(callback: &LoopClosure) -> Result<i32> {
 
 pub type LoopClosure = Closure<dyn FnMut(f64)>;
 
+pub type EventClosure<T> = Closure<dyn FnMut(T)>;
+
 pub fn closure_once<F, A, R>(fn_once: F) -> Closure<F::FnMut>
 where
     F: 'static + WasmClosureFnOnce<A, R>,
@@ -66,6 +77,29 @@ pub fn new_image() -> Result<HtmlImageElement> {
     HtmlImageElement::new().map_err(|e| anyhow!("error creating image: {:#?}", e))
 }
 
+pub fn new_canvas() -> Result<HtmlCanvasElement> {
+    document()?
+        .create_element("canvas")
+        .map_err(|err| anyhow!("error creating canvas: {:#?}", err))?
+        .dyn_into::<HtmlCanvasElement>()
+        .map_err(|element| anyhow!("error converting {:#?} to HtmlCanvasElement", element))
+}
+
+// Clicking a detached link with a download attribute saves its target
+// instead of navigating to it.
+pub fn download(href: &str, filename: &str) -> Result<()> {
+    let link = document()?
+        .create_element("a")
+        .map_err(|err| anyhow!("error creating link: {:#?}", err))?;
+    link.set_attribute("href", href)
+        .and_then(|_| link.set_attribute("download", filename))
+        .map_err(|err| anyhow!("error setting up download link: {:#?}", err))?;
+    link.dyn_into::<HtmlElement>()
+        .map_err(|element| anyhow!("error converting {:#?} to HtmlElement", element))?
+        .click();
+    Ok(())
+}
+
 pub fn window() -> Result<Window> {
     web_sys::window().ok_or_else(|| anyhow!("no window found :S"))
 }
@@ -77,9 +111,17 @@ pub fn document() -> Result<Document> {
 }
 
 pub fn canvas() -> Result<HtmlCanvasElement> {
+    canvas_by_id("canvas")
+}
+
+pub fn side_canvas() -> Result<HtmlCanvasElement> {
+    canvas_by_id("side_canvas")
+}
+
+fn canvas_by_id(id: &str) -> Result<HtmlCanvasElement> {
     document()?
-        .get_element_by_id("canvas")
-        .ok_or_else(|| anyhow!("error getting canvas element"))?
+        .get_element_by_id(id)
+        .ok_or_else(|| anyhow!("error getting {} element", id))?
         .dyn_into::<HtmlCanvasElement>()
         .map_err(|element| anyhow!("error converting {:#?} to HtmlCanvasElement", element))
 }
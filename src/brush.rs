@@ -0,0 +1,341 @@
+use crate::canvas::Point;
+use crate::constants::{BRUSH_HARDNESS, BRUSH_OPACITY, BRUSH_SIZE};
+use crate::image::RawImage;
+use crate::mask::Mask;
+use crate::pointer::PointerEvent;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BrushMode {
+    Off,
+    Add,
+    Subtract,
+    Eraser,
+}
+
+impl BrushMode {
+    pub fn next(self) -> Self {
+        match self {
+            BrushMode::Off => BrushMode::Add,
+            BrushMode::Add => BrushMode::Subtract,
+            BrushMode::Subtract => BrushMode::Eraser,
+            BrushMode::Eraser => BrushMode::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BrushMode::Off => "Brush: off",
+            BrushMode::Add => "Brush: add",
+            BrushMode::Subtract => "Brush: subtract",
+            BrushMode::Eraser => "Brush: eraser",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Brush {
+    pub size: u32,
+    pub hardness: f32,
+    pub opacity: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Brush {
+            size: BRUSH_SIZE,
+            hardness: BRUSH_HARDNESS,
+            opacity: BRUSH_OPACITY,
+        }
+    }
+}
+
+impl Brush {
+    // Strength of a dab at `distance` pixels from its center: full inside
+    // the hard core, then a smooth falloff to zero at the brush radius.
+    fn falloff(&self, distance: f32) -> f32 {
+        let radius = self.size as f32 / 2.0;
+        let core = radius * self.hardness.clamp(0.0, 1.0);
+        if distance <= core {
+            1.0
+        } else if distance >= radius {
+            0.0
+        } else {
+            let t = 1.0 - (distance - core) / (radius - core);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+pub struct MaskPainter {
+    brush: Brush,
+    mode: BrushMode,
+    mask: Option<Mask>,
+    base: Option<Mask>,
+    stroke: Option<Box<Stroke>>,
+}
+
+struct Stroke {
+    before: Mask,
+    coverage: Vec<f32>,
+    last: Point,
+}
+
+impl MaskPainter {
+    pub fn new(brush: Brush) -> Self {
+        MaskPainter {
+            brush,
+            mode: BrushMode::Off,
+            mask: None,
+            base: None,
+            stroke: None,
+        }
+    }
+
+    pub fn mode(&self) -> BrushMode {
+        self.mode
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.mode != BrushMode::Off
+    }
+
+    pub fn mask(&self) -> Option<&Mask> {
+        self.mask.as_ref()
+    }
+
+    pub fn next_mode(&mut self) -> BrushMode {
+        self.mode = self.mode.next();
+        self.stroke = None;
+        self.mode
+    }
+
+    pub fn brush(&self) -> Brush {
+        self.brush
+    }
+
+    pub fn brush_mut(&mut self) -> &mut Brush {
+        &mut self.brush
+    }
+
+    // The eraser paints back towards `base`, which is whatever mask was
+    // there before painting started (e.g. a geometric selection).
+    pub fn start(&mut self, base: Mask) {
+        self.mask = Some(base.clone());
+        self.base = Some(base);
+        self.stroke = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.mask = None;
+        self.base = None;
+        self.stroke = None;
+    }
+
+    pub fn handle_pointer(&mut self, event: PointerEvent) {
+        if !self.is_active() {
+            return;
+        }
+        match event {
+            PointerEvent::Down(position) => {
+                if let Some(mask) = &self.mask {
+                    self.stroke = Some(Box::new(Stroke {
+                        before: mask.clone(),
+                        coverage: vec![0.0; (mask.width() * mask.height()) as usize],
+                        last: position,
+                    }));
+                    self.dab(position);
+                }
+            }
+            PointerEvent::Move(position) => self.paint_to(position),
+            PointerEvent::Up(position) => {
+                self.paint_to(position);
+                self.stroke = None;
+            }
+        }
+    }
+
+    pub fn overlay(&self, color: [u8; 3], alpha: u8) -> Option<RawImage> {
+        self.mask.as_ref().map(|mask| {
+            let mut pixels = Vec::with_capacity(mask.values().len() * 4);
+            for &value in mask.values() {
+                pixels.extend_from_slice(&color);
+                pixels.push((value as u32 * alpha as u32 / 255) as u8);
+            }
+            RawImage::from_raw(pixels, mask.width(), mask.height())
+        })
+    }
+
+    fn paint_to(&mut self, position: Point) {
+        let last = match &self.stroke {
+            Some(stroke) => stroke.last,
+            None => return,
+        };
+        let dx = position.x as f32 - last.x as f32;
+        let dy = position.y as f32 - last.y as f32;
+        let spacing = (self.brush.size as f32 / 4.0).max(1.0);
+        let steps = ((dx * dx + dy * dy).sqrt() / spacing).ceil() as u32;
+        for step in 1..=steps {
+            let t = step as f32 / steps as f32;
+            self.dab(Point {
+                x: (last.x as f32 + dx * t).round() as i16,
+                y: (last.y as f32 + dy * t).round() as i16,
+            });
+        }
+        if let Some(stroke) = &mut self.stroke {
+            stroke.last = position;
+        }
+    }
+
+    // Coverage within a stroke is the maximum of the overlapping dabs, so a
+    // single stroke never exceeds the brush opacity.
+    fn dab(&mut self, center: Point) {
+        let (mask, stroke) = match (&mut self.mask, &mut self.stroke) {
+            (Some(mask), Some(stroke)) => (mask, stroke),
+            _ => return,
+        };
+        let radius = self.brush.size as f32 / 2.0;
+        let width = mask.width() as i32;
+        let height = mask.height() as i32;
+        let x0 = ((center.x as f32 - radius).floor() as i32).max(0);
+        let x1 = ((center.x as f32 + radius).ceil() as i32).min(width - 1);
+        let y0 = ((center.y as f32 - radius).floor() as i32).max(0);
+        let y1 = ((center.y as f32 + radius).ceil() as i32).min(height - 1);
+
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let dx = x as f32 + 0.5 - center.x as f32;
+                let dy = y as f32 + 0.5 - center.y as f32;
+                let strength = self.brush.falloff((dx * dx + dy * dy).sqrt()) * self.brush.opacity;
+                let i = (y * width + x) as usize;
+                if strength <= stroke.coverage[i] {
+                    continue;
+                }
+                stroke.coverage[i] = strength;
+
+                let before = stroke.before.values()[i] as f32;
+                let target = match self.mode {
+                    BrushMode::Add => 255.0,
+                    BrushMode::Subtract => 0.0,
+                    BrushMode::Eraser => self
+                        .base
+                        .as_ref()
+                        .map(|base| base.values()[i] as f32)
+                        .unwrap_or(0.0),
+                    BrushMode::Off => before,
+                };
+                mask.values_mut()[i] = (before + (target - before) * strength).round() as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mask::Shape;
+
+    const HARD: Brush = Brush {
+        size: 6,
+        hardness: 1.0,
+        opacity: 1.0,
+    };
+
+    fn point(x: i16, y: i16) -> Point {
+        Point { x, y }
+    }
+
+    fn painter(brush: Brush, mode: BrushMode, base: Mask) -> MaskPainter {
+        let mut painter = MaskPainter::new(brush);
+        while painter.mode() != mode {
+            painter.next_mode();
+        }
+        painter.start(base);
+        painter
+    }
+
+    fn stroke(painter: &mut MaskPainter, points: &[(i16, i16)]) {
+        let (first, rest) = points.split_first().unwrap();
+        painter.handle_pointer(PointerEvent::Down(point(first.0, first.1)));
+        for &(x, y) in rest {
+            painter.handle_pointer(PointerEvent::Move(point(x, y)));
+        }
+        let last = points.last().unwrap();
+        painter.handle_pointer(PointerEvent::Up(point(last.0, last.1)));
+    }
+
+    fn at(painter: &MaskPainter, x: u32, y: u32) -> u8 {
+        let mask = painter.mask().unwrap();
+        mask.values()[(y * mask.width() + x) as usize]
+    }
+
+    #[test]
+    fn falloff_is_full_in_the_core_and_fades_to_the_radius() {
+        let brush = Brush {
+            size: 20,
+            hardness: 0.5,
+            opacity: 1.0,
+        };
+        assert_eq!(brush.falloff(0.0), 1.0);
+        assert_eq!(brush.falloff(5.0), 1.0);
+        assert_eq!(brush.falloff(10.0), 0.0);
+        let fading: Vec<f32> = (5..=10).map(|d| brush.falloff(d as f32)).collect();
+        assert!(fading.windows(2).all(|pair| pair[1] < pair[0]));
+    }
+
+    #[test]
+    fn adding_paints_along_the_whole_stroke() {
+        let mut painter = painter(HARD, BrushMode::Add, Mask::new(40, 20));
+        stroke(&mut painter, &[(5, 10), (35, 10)]);
+        assert!((5..35).all(|x| at(&painter, x, 10) == 255));
+        assert_eq!(at(&painter, 20, 2), 0);
+        assert_eq!(at(&painter, 39, 10), 0);
+    }
+
+    // Going back and forth within one stroke stays at the brush opacity.
+    #[test]
+    fn one_stroke_never_exceeds_the_opacity() {
+        let brush = Brush {
+            opacity: 0.5,
+            ..HARD
+        };
+        let mut painter = painter(brush, BrushMode::Add, Mask::new(40, 20));
+        stroke(&mut painter, &[(5, 10), (35, 10), (5, 10), (35, 10)]);
+        let painted: Vec<u8> = (5..35).map(|x| at(&painter, x, 10)).collect();
+        assert!(painted.iter().all(|&value| value == 128), "{:?}", painted);
+
+        stroke(&mut painter, &[(5, 10), (35, 10)]);
+        assert_eq!(at(&painter, 20, 10), 192);
+    }
+
+    #[test]
+    fn eraser_paints_back_the_base_mask() {
+        let shape = Shape::Rectangle {
+            from: point(0, 0),
+            to: point(40, 10),
+        };
+        let base = Mask::from_shape(&shape, 40, 20);
+        let mut painter = painter(HARD, BrushMode::Subtract, base.clone());
+        stroke(&mut painter, &[(20, 0), (20, 19)]);
+        assert_eq!(at(&painter, 20, 5), 0);
+
+        while painter.mode() != BrushMode::Eraser {
+            painter.next_mode();
+        }
+        stroke(&mut painter, &[(20, 0), (20, 19)]);
+        assert_eq!(painter.mask().unwrap().values(), base.values());
+    }
+
+    #[test]
+    fn pointer_is_ignored_when_off_and_overlay_follows_the_mask() {
+        let mut painter = painter(HARD, BrushMode::Off, Mask::new(10, 10));
+        stroke(&mut painter, &[(5, 5), (6, 6)]);
+        assert!(painter.mask().unwrap().values().iter().all(|&v| v == 0));
+
+        painter.next_mode();
+        stroke(&mut painter, &[(5, 5)]);
+        let overlay = painter.overlay([255, 0, 0], 128).unwrap();
+        let alpha = |x: usize, y: usize| overlay.pixels()[4 * (y * 10 + x) + 3];
+        assert_eq!((alpha(5, 5), alpha(0, 0)), (128, 0));
+        assert_eq!(overlay.pixels()[..3], [255, 0, 0]);
+    }
+}
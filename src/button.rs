@@ -1,16 +1,13 @@
-use crate::browser;
-use futures::channel::mpsc::{unbounded, UnboundedReceiver};
-use wasm_bindgen::JsCast;
+use crate::events::{self, Action, EventSender, Listener, UiEvent};
+use anyhow::Result;
 use web_sys::HtmlElement;
 
-pub fn add_click_handler(elem: HtmlElement) -> UnboundedReceiver<()> {
-    let (mut click_sender, click_receiver) = unbounded();
-    let on_click = browser::closure_wrap(Box::new(move || {
-        if let Err(err) = click_sender.start_send(()) {
-            error!("Error starting sending {:#?}", err);
-        };
-    }) as Box<dyn FnMut()>);
-    elem.set_onclick(Some(on_click.as_ref().unchecked_ref()));
-    on_click.forget();
-    click_receiver
+pub fn add_click_handler(
+    elem: HtmlElement,
+    sender: EventSender,
+    action: Action,
+) -> Result<Listener> {
+    Listener::new(&elem, "click", move |_event| {
+        events::send(&sender, UiEvent::Click(action));
+    })
 }
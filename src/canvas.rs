@@ -1,32 +1,237 @@
+use crate::analysis::{Analysis, Detection};
 use crate::browser;
+use crate::constants::{ACCUMULATOR_SIZE, ALIGNED_LAYER_OPACITY, HISTORY_DEPTH};
+use crate::cube::{CubeLut, Interpolation};
+use crate::frames::{FrameSource, SourceMode};
 use crate::image::RawImage;
+use crate::layers::{Layer, LayerStack};
+use crate::mask::Mask;
+use crate::panorama;
+use crate::pipeline::{Pipeline, EFFECTS};
+use crate::quantize::{self, Color, QuantizeMethod};
 
 use anyhow::{anyhow, Result};
 use futures::channel::oneshot::channel;
+use std::collections::VecDeque;
+use std::f64::consts::{PI, TAU};
 use std::rc::Rc;
 use std::sync::Mutex;
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::{JsCast, JsValue};
-use web_sys::{CanvasRenderingContext2d, HtmlImageElement, ImageData};
+use web_sys::{
+    CanvasRenderingContext2d, File, HtmlCanvasElement, HtmlImageElement, ImageData, Url,
+};
 
 pub struct Renderer {
     pub context: CanvasRenderingContext2d,
+    pub overlay: HtmlCanvasElement,
+    pub side: HtmlCanvasElement,
 }
 
 impl Renderer {
-    pub fn draw_image(&self, image: &HtmlImageElement, position: &Point) {
+    pub fn draw_image(&self, image: &HtmlImageElement, position: &Point) -> Result<()> {
         self.context
             .draw_image_with_html_image_element(image, position.x.into(), position.y.into())
-            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+            .map_err(|err| anyhow!("Could not draw image {:#?}", err))
     }
 
-    pub fn put_image(&self, image_data: &ImageData, position: &Point) {
+    pub fn put_image(&self, image_data: &ImageData, position: &Point) -> Result<()> {
         self.context
             .put_image_data(image_data, position.x.into(), position.y.into())
-            .expect("Put Image is throwing exceptions! Unrecoverable error.");
+            .map_err(|err| anyhow!("Could not put ImageData {:#?}", err))
+    }
+
+    // put_image_data replaces pixels instead of compositing them, so
+    // translucent overlays go through an offscreen canvas first.
+    pub fn draw_overlay(&self, overlay: &RawImage, position: &Point) -> Result<()> {
+        self.put_offscreen(overlay)?;
+        self.context
+            .draw_image_with_html_canvas_element(
+                &self.overlay,
+                position.x.into(),
+                position.y.into(),
+            )
+            .map_err(|err| anyhow!("Could not draw overlay {:#?}", err))
+    }
+
+    // Draws the image scaled into the given box.
+    pub fn draw_inset(
+        &self,
+        image: &RawImage,
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    ) -> Result<()> {
+        self.put_offscreen(image)?;
+        self.context
+            .draw_image_with_html_canvas_element_and_dw_and_dh(&self.overlay, x, y, width, height)
+            .map_err(|err| anyhow!("Could not draw inset {:#?}", err))
+    }
+
+    // The canvas beside the main one shows the image scaled so its longest
+    // side fits the given size, and is hidden when there is nothing to show.
+    pub fn draw_side(&self, image: Option<&RawImage>, size: f64) -> Result<()> {
+        let Some(image) = image else {
+            self.side.set_hidden(true);
+            return Ok(());
+        };
+        let scale = size / image.width().max(image.height()).max(1) as f64;
+        let (width, height) = (
+            (image.width() as f64 * scale).round() as u32,
+            (image.height() as f64 * scale).round() as u32,
+        );
+        if self.side.width() != width || self.side.height() != height {
+            self.side.set_width(width);
+            self.side.set_height(height);
+        }
+        self.side.set_hidden(false);
+        self.put_offscreen(image)?;
+        self.side
+            .get_context("2d")
+            .map_err(|err| anyhow!("error getting side 2d context {:#?}", err))?
+            .ok_or_else(|| anyhow!("no side 2d context found"))?
+            .dyn_into::<CanvasRenderingContext2d>()
+            .map_err(|element| anyhow!("error converting {:#?} to 2d context", element))?
+            .draw_image_with_html_canvas_element_and_dw_and_dh(
+                &self.overlay,
+                0.0,
+                0.0,
+                width as f64,
+                height as f64,
+            )
+            .map_err(|err| anyhow!("Could not draw on the side canvas {:#?}", err))
+    }
+
+    fn put_offscreen(&self, image: &RawImage) -> Result<()> {
+        if self.overlay.width() != image.width() || self.overlay.height() != image.height() {
+            self.overlay.set_width(image.width());
+            self.overlay.set_height(image.height());
+        }
+        self.overlay
+            .get_context("2d")
+            .map_err(|err| anyhow!("error getting overlay 2d context {:#?}", err))?
+            .ok_or_else(|| anyhow!("no overlay 2d context found"))?
+            .dyn_into::<CanvasRenderingContext2d>()
+            .map_err(|element| anyhow!("error converting {:#?} to 2d context", element))?
+            .put_image_data(&image.to_image_data()?, 0.0, 0.0)
+            .map_err(|err| anyhow!("Could not put overlay ImageData {:#?}", err))
+    }
+
+    pub fn width(&self) -> f64 {
+        self.context
+            .canvas()
+            .map_or(0.0, |canvas| canvas.width().into())
+    }
+
+    // Decodes an image element into pixels through the offscreen canvas.
+    pub fn read_image(&self, element: &HtmlImageElement) -> Result<RawImage> {
+        let (width, height) = (element.natural_width(), element.natural_height());
+        self.overlay.set_width(width);
+        self.overlay.set_height(height);
+        let context = self
+            .overlay
+            .get_context("2d")
+            .map_err(|err| anyhow!("error getting overlay 2d context {:#?}", err))?
+            .ok_or_else(|| anyhow!("no overlay 2d context found"))?
+            .dyn_into::<CanvasRenderingContext2d>()
+            .map_err(|element| anyhow!("error converting {:#?} to 2d context", element))?;
+        context
+            .draw_image_with_html_image_element(element, 0.0, 0.0)
+            .map_err(|err| anyhow!("Could not draw image {:#?}", err))?;
+        context
+            .get_image_data(0.0, 0.0, width.into(), height.into())
+            .map(Into::into)
+            .map_err(|err| anyhow!("Could not get ImageData {:#?}", err))
+    }
+
+    pub fn clear(&self) {
+        if let Some(canvas) = self.context.canvas() {
+            self.context
+                .clear_rect(0.0, 0.0, canvas.width().into(), canvas.height().into());
+        }
+    }
+
+    pub fn stroke_polygon(&self, points: &[Point], color: &str) {
+        let mut points = points.iter();
+        if let Some(first) = points.next() {
+            self.context.begin_path();
+            self.context.move_to(first.x.into(), first.y.into());
+            for point in points {
+                self.context.line_to(point.x.into(), point.y.into());
+            }
+            self.context.close_path();
+            self.context.set_stroke_style(&JsValue::from_str(color));
+            self.context.stroke();
+        }
+    }
+
+    pub fn stroke_line(&self, from: &Point, to: &Point, color: &str) {
+        self.context.begin_path();
+        self.context.move_to(from.x.into(), from.y.into());
+        self.context.line_to(to.x.into(), to.y.into());
+        self.context.set_stroke_style(&JsValue::from_str(color));
+        self.context.stroke();
+    }
+
+    // The head is two short strokes back from the tip.
+    pub fn stroke_arrow(&self, from: &Point, to: &Point, color: &str) {
+        self.stroke_line(from, to, color);
+        let (dx, dy) = ((to.x - from.x) as f64, (to.y - from.y) as f64);
+        let length = dx.hypot(dy);
+        if length < 1.0 {
+            return;
+        }
+        let head = (length / 3.0).min(4.0);
+        let angle = dy.atan2(dx);
+        for side in [-0.5, 0.5] {
+            let back = angle + PI + side;
+            let tip = Point {
+                x: to.x + (head * back.cos()).round() as i16,
+                y: to.y + (head * back.sin()).round() as i16,
+            };
+            self.stroke_line(to, &tip, color);
+        }
+    }
+
+    pub fn stroke_circle(&self, center: &Point, radius: f64, color: &str) -> Result<()> {
+        self.context.begin_path();
+        self.context
+            .arc(center.x.into(), center.y.into(), radius, 0.0, TAU)
+            .map_err(|err| anyhow!("Could not trace circle {:#?}", err))?;
+        self.context.set_stroke_style(&JsValue::from_str(color));
+        self.context.stroke();
+        Ok(())
+    }
+
+    pub fn stroke_rect(&self, x: f64, y: f64, width: f64, height: f64, color: &str) {
+        self.context.set_stroke_style(&JsValue::from_str(color));
+        self.context.stroke_rect(x, y, width, height);
+    }
+
+    pub fn fill_text(&self, text: &str, x: f64, y: f64, color: &str) -> Result<()> {
+        self.context.set_fill_style(&JsValue::from_str(color));
+        self.context
+            .fill_text(text, x, y)
+            .map_err(|err| anyhow!("Could not draw text {:#?}", err))
+    }
+
+    pub fn stroke_ellipse(&self, from: &Point, to: &Point, color: &str) -> Result<()> {
+        let center_x = (from.x as f64 + to.x as f64) / 2.0;
+        let center_y = (from.y as f64 + to.y as f64) / 2.0;
+        let radius_x = (to.x as f64 - from.x as f64).abs() / 2.0;
+        let radius_y = (to.y as f64 - from.y as f64).abs() / 2.0;
+        self.context.begin_path();
+        self.context
+            .ellipse(center_x, center_y, radius_x, radius_y, 0.0, 0.0, TAU)
+            .map_err(|err| anyhow!("Could not trace ellipse {:#?}", err))?;
+        self.context.set_stroke_style(&JsValue::from_str(color));
+        self.context.stroke();
+        Ok(())
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Point {
     pub x: i16,
     pub y: i16,
@@ -36,6 +241,14 @@ pub struct Image {
     element: HtmlImageElement,
     image: RawImage,
     position: Point,
+    pipeline: Pipeline,
+    history: VecDeque<RawImage>,
+    palette_method: QuantizeMethod,
+    effect: usize,
+    layers: LayerStack,
+    analysis: Analysis,
+    panorama_images: Vec<RawImage>,
+    source: FrameSource,
 }
 
 impl Image {
@@ -44,36 +257,264 @@ impl Image {
             element,
             image: RawImage::new(),
             position: Point { x: 0, y: 0 },
+            pipeline: Pipeline::default(),
+            history: VecDeque::new(),
+            palette_method: QuantizeMethod::MedianCut,
+            effect: 0,
+            layers: LayerStack::default(),
+            analysis: Analysis::new(),
+            panorama_images: vec![],
+            source: FrameSource::new(),
         }
     }
 
-    pub fn load_image(mut self, renderer: &Renderer) -> Self {
-        let imgdata = load_image_data(renderer).expect("cannot load raw image data!");
-        self.image = imgdata.into();
-        self
+    // The element is drawn again before reading the canvas back so that
+    // overlays like the selection outline never end up in the pixels.
+    pub fn load_image(&mut self, renderer: &Renderer) -> Result<()> {
+        if self.image.is_empty() {
+            self.draw(renderer)?;
+            self.image = load_image_data(renderer)?.into();
+        }
+        Ok(())
     }
 
     pub fn refresh(mut self) -> Self {
         self.image = RawImage::new();
+        self.pipeline.set_mask(None);
+        self.history.clear();
+        self.layers.clear();
+        self.pipeline.clear_history();
+        self.analysis.clear();
+        self.panorama_images.clear();
+        self.source.take_still();
         self
     }
 
-    pub fn draw(&self, renderer: &Renderer) {
-        renderer.draw_image(&self.element, &self.position);
+    pub fn checkpoint(&mut self) {
+        if self.history.len() == HISTORY_DEPTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(self.image.clone());
     }
 
-    pub fn put_image(&self, renderer: &Renderer) {
-        let data = self
-            .image
-            .to_image_data()
-            .expect("unrecoverable error: cannot get ImageData");
+    pub fn undo(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some(image) => {
+                self.image = image;
+                self.replaced();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Whenever the frame changes outside of a simulation step.
+    fn replaced(&mut self) {
+        self.analysis.stabilization.forget();
+        self.analysis.analyze(&self.image, self.position);
+    }
+
+    pub fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.image.height()
+    }
 
-        renderer.put_image(&data, &self.position);
+    pub fn set_mask(&mut self, mask: Option<Mask>) {
+        self.pipeline.set_mask(mask);
     }
 
-    pub fn run_simulation_step(&mut self) {
-        self.image.solarize();
-        self.image.grayscale();
+    pub fn has_image_data(&self) -> bool {
+        !self.image.is_empty()
+    }
+
+    pub fn effect_label(&self) -> &'static str {
+        EFFECTS[self.effect].0
+    }
+
+    pub fn next_effect(&mut self) -> &'static str {
+        self.effect = (self.effect + 1) % EFFECTS.len();
+        self.pipeline.set_operations(EFFECTS[self.effect].1);
+        self.effect_label()
+    }
+
+    pub fn analysis(&self) -> &Analysis {
+        &self.analysis
+    }
+
+    pub fn analysis_mut(&mut self) -> &mut Analysis {
+        &mut self.analysis
+    }
+
+    // Detection settings take effect on the current frame right away.
+    pub fn detect<T>(&mut self, change: impl FnOnce(&mut Detection) -> T) -> T {
+        let result = change(&mut self.analysis.detection);
+        self.analysis.detection.analyze(&self.image, self.position);
+        result
+    }
+
+    pub fn set_match_image(&mut self, image: RawImage) {
+        self.analysis.matching.set_image(image, &self.image);
+    }
+
+    // The match image is warped onto the frame as a translucent layer.
+    pub fn align_match(&mut self) -> Option<&mut Layer> {
+        let (width, height) = (self.image.width(), self.image.height());
+        let mut layer = Layer::new(self.analysis.matching.align(width, height)?);
+        layer.opacity = ALIGNED_LAYER_OPACITY;
+        self.layers.push(layer);
+        self.layers.top_mut()
+    }
+
+    // Pictures bigger than the canvas are shrunk right away, which keeps
+    // matching fast and the panorama within reach of the canvas.
+    pub fn add_panorama_image(&mut self, image: RawImage) -> usize {
+        let (width, height) = (self.image.width(), self.image.height());
+        self.panorama_images
+            .push(panorama::shrink(&image, width, height));
+        self.panorama_images.len()
+    }
+
+    // The panorama replaces the frame, scaled to fit the canvas.
+    pub fn stitch(&mut self) -> Result<()> {
+        let stitched = panorama::stitch(&self.panorama_images)?;
+        self.image = panorama::place(&stitched, self.image.width(), self.image.height());
+        self.panorama_images.clear();
+        self.analysis.flow.clear();
+        self.replaced();
+        Ok(())
+    }
+
+    pub fn add_seams(&mut self) {
+        let inserted = self
+            .analysis
+            .carving
+            .add_seams(&mut self.image, self.pipeline.mask());
+        if inserted > 0 {
+            self.replaced();
+        }
+    }
+
+    // The processed frame is copied into a new layer on top of the stack.
+    pub fn add_layer(&mut self) -> Option<&mut Layer> {
+        if self.image.is_empty() {
+            return None;
+        }
+        self.layers.push(Layer::new(self.image.clone()));
+        self.layers.top_mut()
+    }
+
+    pub fn top_layer_mut(&mut self) -> Option<&mut Layer> {
+        self.layers.top_mut()
+    }
+
+    pub fn history_depth(&self) -> usize {
+        self.pipeline.history_depth()
+    }
+
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.pipeline.set_history_depth(depth);
+    }
+
+    pub fn set_lut(&mut self, lut: CubeLut) {
+        self.pipeline.set_lut(lut);
+    }
+
+    pub fn lut_interpolation(&self) -> Interpolation {
+        self.pipeline.interpolation()
+    }
+
+    pub fn next_lut_interpolation(&mut self) -> Interpolation {
+        self.pipeline.next_interpolation()
+    }
+
+    pub fn lut_strength(&self) -> f32 {
+        self.pipeline.strength()
+    }
+
+    pub fn set_lut_strength(&mut self, strength: f32) {
+        self.pipeline.set_strength(strength);
+    }
+
+    pub fn palette_method(&self) -> QuantizeMethod {
+        self.palette_method
+    }
+
+    pub fn next_palette_method(&mut self) -> QuantizeMethod {
+        self.palette_method = self.palette_method.next();
+        self.palette_method
+    }
+
+    pub fn palette(&self, colors: usize) -> Vec<Color> {
+        quantize::palette(&self.image, colors, self.palette_method)
+    }
+
+    pub fn draw(&self, renderer: &Renderer) -> Result<()> {
+        renderer.draw_side(None, ACCUMULATOR_SIZE)?;
+        renderer.draw_image(&self.element, &self.position)
+    }
+
+    // Layers are composited over the processed frame every time it is drawn.
+    pub fn put_image(&self, renderer: &Renderer) -> Result<()> {
+        if self.analysis.matching.is_shown() {
+            renderer.draw_side(None, ACCUMULATOR_SIZE)?;
+            return self
+                .analysis
+                .matching
+                .draw(renderer, &self.image, self.position);
+        }
+        let (width, height) = (self.image.width(), self.image.height());
+        let data = match self.analysis.view(width, height) {
+            Some(view) => view.to_image_data()?,
+            None => self.composite()?,
+        };
+        renderer.put_image(&data, &self.position)?;
+        self.analysis.draw(renderer, self.position)
+    }
+
+    fn composite(&self) -> Result<ImageData> {
+        let image = self.analysis.stabilization.frame().unwrap_or(&self.image);
+        if self.layers.is_empty() {
+            image.to_image_data()
+        } else {
+            self.layers.composite_over(image)?.to_image_data()
+        }
+    }
+
+    pub fn source_mode(&self) -> SourceMode {
+        self.source.mode()
+    }
+
+    // Temporal state learnt from one source means nothing for the next, and
+    // leaving the sequence brings back the still it was made from.
+    pub fn next_source(&mut self) -> SourceMode {
+        let mode = self.source.next_mode();
+        if let Some(still) = self.source.take_still() {
+            self.image = still;
+        }
+        self.pipeline.clear_history();
+        self.analysis.clear();
+        self.replaced();
+        mode
+    }
+
+    // A playing source replaces the frame on every step, the still is
+    // processed again instead.
+    pub fn run_simulation_step(&mut self) -> Result<()> {
+        let frame = self.source.next_frame(&self.image);
+        if let Some(frame) = &frame {
+            self.image = frame.clone();
+        }
+        self.pipeline.run(&mut self.image)?;
+        self.analysis.step(
+            &mut self.image,
+            frame.as_ref(),
+            self.pipeline.mask(),
+            self.position,
+        );
+        Ok(())
     }
 }
 
@@ -103,6 +544,16 @@ pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
     Ok(image)
 }
 
+pub async fn load_image_file(file: &File) -> Result<HtmlImageElement> {
+    let url = Url::create_object_url_with_blob(file)
+        .map_err(|err| anyhow!("Could not create object url {:#?}", err))?;
+    let image = load_image(&url).await;
+    if let Err(err) = Url::revoke_object_url(&url) {
+        error!("Error revoking object url {:#?}", err);
+    }
+    image
+}
+
 pub fn load_image_data(renderer: &Renderer) -> Result<ImageData> {
     let canvas = browser::canvas()?;
     renderer
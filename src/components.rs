@@ -0,0 +1,313 @@
+use crate::binary::{BinaryImage, Rect};
+use crate::image::RawImage;
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    Four,
+    Eight,
+}
+
+impl Connectivity {
+    pub fn label(self) -> &'static str {
+        match self {
+            Connectivity::Four => "Components: 4-connected",
+            Connectivity::Eight => "Components: 8-connected",
+        }
+    }
+
+    // Neighbours already visited by a raster scan, as (dx, dy).
+    fn previous(self) -> &'static [(isize, isize)] {
+        match self {
+            Connectivity::Four => &[(-1, 0), (0, -1)],
+            Connectivity::Eight => &[(-1, 0), (-1, -1), (0, -1), (1, -1)],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Region {
+    pub bounds: Rect,
+    pub area: usize,
+    pub centroid: (f32, f32),
+    // Length of the pixel edges between the region and the background.
+    pub perimeter: usize,
+    // Angle of the major axis in radians, clockwise from the x axis since y
+    // grows downwards.
+    pub orientation: f32,
+    // Of the ellipse with the same second moments: 0 for a circle, towards 1
+    // for a line.
+    pub eccentricity: f32,
+}
+
+// Label 0 is the background, components are numbered from 1 in raster order
+// and `regions[label - 1]` describes each of them.
+pub struct Components {
+    pub labels: Vec<u32>,
+    pub width: u32,
+    pub height: u32,
+    pub regions: Vec<Region>,
+}
+
+impl Components {
+    // Every component gets its own hue, the background stays transparent.
+    pub fn colorize(&self, alpha: u8) -> RawImage {
+        let colors: Vec<[u8; 3]> = (0..self.regions.len()).map(color).collect();
+        let pixels = self
+            .labels
+            .iter()
+            .flat_map(|&label| match label {
+                0 => [0, 0, 0, 0],
+                label => {
+                    let [r, g, b] = colors[label as usize - 1];
+                    [r, g, b, alpha]
+                }
+            })
+            .collect();
+        RawImage::from_raw(pixels, self.width, self.height)
+    }
+}
+
+pub fn regions(binary: &BinaryImage) -> Vec<Region> {
+    label(binary, Connectivity::Eight).regions
+}
+
+// Two-pass labeling: the first scan gives each pixel a provisional label and
+// records equivalences in a union-find, the second resolves them to compact
+// labels while accumulating the moments of each component.
+pub fn label(binary: &BinaryImage, connectivity: Connectivity) -> Components {
+    let (width, height) = (binary.width() as usize, binary.height() as usize);
+    let values = binary.values();
+    let mut labels = vec![0u32; width * height];
+    let mut parents = vec![0u32];
+
+    for y in 0..height {
+        for x in 0..width {
+            let index = y * width + x;
+            if !values[index] {
+                continue;
+            }
+            let mut current = 0;
+            for &(dx, dy) in connectivity.previous() {
+                let (nx, ny) = (x as isize + dx, y as isize + dy);
+                if nx < 0 || ny < 0 || nx >= width as isize {
+                    continue;
+                }
+                let neighbor = labels[ny as usize * width + nx as usize];
+                if neighbor == 0 {
+                    continue;
+                }
+                current = match current {
+                    0 => find(&mut parents, neighbor),
+                    current => union(&mut parents, current, neighbor),
+                };
+            }
+            if current == 0 {
+                current = parents.len() as u32;
+                parents.push(current);
+            }
+            labels[index] = current;
+        }
+    }
+
+    let mut compact = vec![0u32; parents.len()];
+    let mut moments: Vec<Moments> = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            let index = y * width + x;
+            if labels[index] == 0 {
+                continue;
+            }
+            let root = find(&mut parents, labels[index]) as usize;
+            if compact[root] == 0 {
+                moments.push(Moments::new(x, y));
+                compact[root] = moments.len() as u32;
+            }
+            labels[index] = compact[root];
+
+            let exposed = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                .iter()
+                .filter(|&&(dx, dy)| {
+                    let (nx, ny) = (x as isize + dx, y as isize + dy);
+                    nx < 0
+                        || ny < 0
+                        || nx >= width as isize
+                        || ny >= height as isize
+                        || !values[ny as usize * width + nx as usize]
+                })
+                .count();
+            moments[compact[root] as usize - 1].add(x, y, exposed);
+        }
+    }
+
+    Components {
+        labels,
+        width: binary.width(),
+        height: binary.height(),
+        regions: moments.iter().map(Moments::region).collect(),
+    }
+}
+
+fn find(parents: &mut [u32], mut label: u32) -> u32 {
+    while parents[label as usize] != label {
+        let parent = parents[label as usize];
+        parents[label as usize] = parents[parent as usize];
+        label = parent;
+    }
+    label
+}
+
+// The smaller root wins, so labels keep their raster order.
+fn union(parents: &mut [u32], a: u32, b: u32) -> u32 {
+    let (a, b) = (find(parents, a), find(parents, b));
+    let (root, child) = (a.min(b), a.max(b));
+    parents[child as usize] = root;
+    root
+}
+
+struct Moments {
+    min: (usize, usize),
+    max: (usize, usize),
+    area: usize,
+    perimeter: usize,
+    sum: (f64, f64),
+    squares: (f64, f64, f64),
+}
+
+impl Moments {
+    fn new(x: usize, y: usize) -> Self {
+        Moments {
+            min: (x, y),
+            max: (x, y),
+            area: 0,
+            perimeter: 0,
+            sum: (0.0, 0.0),
+            squares: (0.0, 0.0, 0.0),
+        }
+    }
+
+    fn add(&mut self, x: usize, y: usize, exposed: usize) {
+        self.min = (self.min.0.min(x), self.min.1.min(y));
+        self.max = (self.max.0.max(x), self.max.1.max(y));
+        self.area += 1;
+        self.perimeter += exposed;
+        let (fx, fy) = (x as f64, y as f64);
+        self.sum = (self.sum.0 + fx, self.sum.1 + fy);
+        self.squares = (
+            self.squares.0 + fx * fx,
+            self.squares.1 + fy * fy,
+            self.squares.2 + fx * fy,
+        );
+    }
+
+    fn region(&self) -> Region {
+        let area = self.area as f64;
+        let (cx, cy) = (self.sum.0 / area, self.sum.1 / area);
+        // Central second moments, with the variance of a unit pixel so single
+        // pixels and lines still have a defined shape.
+        let mu20 = self.squares.0 / area - cx * cx + 1.0 / 12.0;
+        let mu02 = self.squares.1 / area - cy * cy + 1.0 / 12.0;
+        let mu11 = self.squares.2 / area - cx * cy;
+        let spread = ((mu20 - mu02).powi(2) + 4.0 * mu11 * mu11).sqrt();
+        let (major, minor) = ((mu20 + mu02 + spread) / 2.0, (mu20 + mu02 - spread) / 2.0);
+
+        Region {
+            bounds: Rect {
+                x: self.min.0 as u32,
+                y: self.min.1 as u32,
+                width: (self.max.0 - self.min.0 + 1) as u32,
+                height: (self.max.1 - self.min.1 + 1) as u32,
+            },
+            area: self.area,
+            centroid: (cx as f32, cy as f32),
+            perimeter: self.perimeter,
+            orientation: (0.5 * (2.0 * mu11).atan2(mu20 - mu02)) as f32,
+            eccentricity: (1.0 - minor.max(0.0) / major).max(0.0).sqrt() as f32,
+        }
+    }
+}
+
+// Hues step by the golden angle so neighbouring labels never look alike.
+fn color(index: usize) -> [u8; 3] {
+    let hue = (index as f32 * 0.618_034).fract() * 2.0 * PI;
+    [0.0, 2.0 * PI / 3.0, 4.0 * PI / 3.0]
+        .map(|offset| (127.5 + 127.5 * (hue - offset).cos()).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(rows: &[&str]) -> BinaryImage {
+        let values = rows
+            .iter()
+            .flat_map(|row| row.chars().map(|c| c == '#'))
+            .collect();
+        BinaryImage::from_values(values, rows[0].len() as u32, rows.len() as u32)
+    }
+
+    #[test]
+    fn u_shape_is_one_component() {
+        // The arms get separate labels on the first rows and are only joined
+        // by the bottom row.
+        let image = binary(&["#...#.", "#...#.", "#...#.", "#####.", "......"]);
+        for connectivity in [Connectivity::Four, Connectivity::Eight] {
+            let components = label(&image, connectivity);
+            assert_eq!(components.regions.len(), 1, "{:?}", connectivity);
+            let region = &components.regions[0];
+            assert_eq!(region.area, 11);
+            assert_eq!(
+                (
+                    region.bounds.x,
+                    region.bounds.y,
+                    region.bounds.width,
+                    region.bounds.height
+                ),
+                (0, 0, 5, 4)
+            );
+            assert!(components
+                .labels
+                .iter()
+                .zip(image.values())
+                .all(|(&label, &set)| label == set as u32));
+        }
+    }
+
+    #[test]
+    fn comb_merges_every_tooth() {
+        let image = binary(&["#.#.#.#", "#.#.#.#", "#######"]);
+        assert_eq!(label(&image, Connectivity::Four).regions.len(), 1);
+    }
+
+    #[test]
+    fn diagonal_neighbours_only_join_with_eight_connectivity() {
+        let image = binary(&["#...", ".#..", "..#.", "...#"]);
+        assert_eq!(label(&image, Connectivity::Four).regions.len(), 4);
+        assert_eq!(label(&image, Connectivity::Eight).regions.len(), 1);
+    }
+
+    #[test]
+    fn labels_follow_raster_order() {
+        let image = binary(&["..##", "#...", "#..#"]);
+        let components = label(&image, Connectivity::Four);
+        assert_eq!(components.labels, [0, 0, 1, 1, 2, 0, 0, 0, 2, 0, 0, 3]);
+        let areas: Vec<usize> = components.regions.iter().map(|r| r.area).collect();
+        assert_eq!(areas, [2, 2, 1]);
+        assert_eq!(components.regions[0].centroid, (2.5, 0.0));
+    }
+
+    #[test]
+    fn shape_measures() {
+        let square = &label(&binary(&["##", "##"]), Connectivity::Four).regions[0];
+        assert_eq!(square.perimeter, 8);
+        assert!(square.eccentricity.abs() < 1e-3);
+
+        let line = &label(&binary(&["########"]), Connectivity::Four).regions[0];
+        assert_eq!(line.perimeter, 18);
+        assert!(line.orientation.abs() < 1e-3);
+        assert!(line.eccentricity > 0.99);
+
+        let column = &label(&binary(&["#", "#", "#", "#"]), Connectivity::Four).regions[0];
+        assert!((column.orientation.abs() - PI / 2.0).abs() < 1e-3);
+    }
+}
@@ -1,4 +1,5 @@
 pub const IMAGE_SOURCE: &str = "me.jpg";
+pub const HISTORY_DEPTH: usize = 20;
 
 pub const RUN_SIMULATION_BUTTON: &str =
     "<button class='run_button' id='run_simulation'>Run simulation</button>";
@@ -12,6 +13,17 @@ pub const FINISH_SIMULATION_BUTTON: &str =
     "<button class='finish_button' id='finish_simulation'>Finish simulation</button>";
 pub const FINISH_SIMULATION_ID: &str = "finish_simulation";
 
+pub const STABILIZE_BUTTON: &str =
+    "<button class='stabilize_button' id='stabilize'>Stabilize: off</button>";
+pub const STABILIZE_ID: &str = "stabilize";
+
+pub const STABILIZATION_WINDOW_SLIDER: &str =
+    "<label class='slider'>Smoothing <input type='range' id='stabilization_window' min='1' max='120' step='1'></label>";
+pub const STABILIZATION_WINDOW_ID: &str = "stabilization_window";
+
+pub const SOURCE_BUTTON: &str = "<button class='source_button' id='source'>Source: still</button>";
+pub const SOURCE_ID: &str = "source";
+
 pub const REFRESH_IMAGE_BUTTON: &str =
     "<button class='refresh_button' id='refresh_image'>Refresh image</button>";
 pub const REFRESH_IMAGE_ID: &str = "refresh_image";
@@ -19,3 +31,316 @@ pub const REFRESH_IMAGE_ID: &str = "refresh_image";
 pub const SAVE_IMAGE_BUTTON: &str =
     "<button class='save_button' id='save_image'>Save image</button>";
 pub const SAVE_IMAGE_ID: &str = "save_image";
+pub const SAVED_IMAGE_NAME: &str = "processed.png";
+
+pub const RETRY_BUTTON: &str = "<button class='retry_button' id='retry'>Retry</button>";
+pub const RETRY_ID: &str = "retry";
+
+pub const RESET_BUTTON: &str = "<button class='reset_button' id='reset'>Reset</button>";
+pub const RESET_ID: &str = "reset";
+
+pub const ERROR_MESSAGE: &str = "<p class='error_message' id='error_message'></p>";
+pub const ERROR_MESSAGE_ID: &str = "error_message";
+
+pub const SELECTION_TOOL_BUTTON: &str =
+    "<button class='selection_button' id='selection_tool'>Selection: none</button>";
+pub const SELECTION_TOOL_ID: &str = "selection_tool";
+
+pub const INVERT_SELECTION_BUTTON: &str =
+    "<button class='invert_button' id='invert_selection'>Apply: inside</button>";
+pub const INVERT_SELECTION_ID: &str = "invert_selection";
+
+pub const FEATHER_SLIDER: &str =
+    "<label class='slider'>Feather <input type='range' id='feather' min='0' max='32' step='1'></label>";
+pub const FEATHER_ID: &str = "feather";
+
+pub const SELECTION_COLOR: &str = "#008CBA";
+pub const SELECTION_FEATHER_RADIUS: u32 = 8;
+
+pub const EFFECT_BUTTON: &str =
+    "<button class='effect_button' id='effect'>Effect: solarize</button>";
+pub const EFFECT_ID: &str = "effect";
+
+pub const LUT_INPUT: &str =
+    "<label class='lut_input'>LUT <input type='file' id='lut_file' accept='.cube'></label>";
+pub const LUT_INPUT_ID: &str = "lut_file";
+
+pub const LUT_INTERPOLATION_BUTTON: &str =
+    "<button class='lut_button' id='lut_interpolation'>LUT: tetrahedral</button>";
+pub const LUT_INTERPOLATION_ID: &str = "lut_interpolation";
+
+pub const LUT_STRENGTH_SLIDER: &str =
+    "<label class='slider'>Strength <input type='range' id='lut_strength' min='0' max='1' step='0.05'></label>";
+pub const LUT_STRENGTH_ID: &str = "lut_strength";
+pub const LUT_STRENGTH: f32 = 1.0;
+// Largest sizes the .cube specification allows, checked before any table is
+// built.
+pub const LUT_MAX_1D_SIZE: usize = 65536;
+pub const LUT_MAX_3D_SIZE: usize = 256;
+
+pub const ADD_LAYER_BUTTON: &str = "<button class='layer_button' id='add_layer'>Add layer</button>";
+pub const ADD_LAYER_ID: &str = "add_layer";
+
+pub const BLEND_MODE_BUTTON: &str =
+    "<button class='layer_button' id='blend_mode'>Blend: normal</button>";
+pub const BLEND_MODE_ID: &str = "blend_mode";
+
+pub const LAYER_VISIBILITY_BUTTON: &str =
+    "<button class='layer_button' id='layer_visibility'>Layer: visible</button>";
+pub const LAYER_VISIBILITY_ID: &str = "layer_visibility";
+
+pub const SEAMS_BUTTON: &str = "<button class='layer_button' id='seams'>Seams: hard</button>";
+pub const SEAMS_ID: &str = "seams";
+
+pub const LAYER_OPACITY_SLIDER: &str =
+    "<label class='slider'>Layer opacity <input type='range' id='layer_opacity' min='0' max='1' step='0.05'></label>";
+pub const LAYER_OPACITY_ID: &str = "layer_opacity";
+
+pub const HISTORY_DEPTH_SLIDER: &str =
+    "<label class='slider'>Frames <input type='range' id='history_depth' min='1' max='32' step='1'></label>";
+pub const HISTORY_DEPTH_ID: &str = "history_depth";
+
+pub const MOTION_BUTTON: &str = "<button class='motion_button' id='motion'>Motion: off</button>";
+pub const MOTION_ID: &str = "motion";
+
+pub const BACKGROUND_VIEW_BUTTON: &str =
+    "<button class='background_button' id='background_view'>Background: off</button>";
+pub const BACKGROUND_VIEW_ID: &str = "background_view";
+
+pub const LEARNING_RATE_SLIDER: &str =
+    "<label class='slider'>Learning rate <input type='range' id='learning_rate' min='0' max='0.1' step='0.005'></label>";
+pub const LEARNING_RATE_ID: &str = "learning_rate";
+
+pub const COMPONENTS_BUTTON: &str =
+    "<button class='components_button' id='components'>Components: off</button>";
+pub const COMPONENTS_ID: &str = "components";
+
+pub const CONTOURS_BUTTON: &str =
+    "<button class='contours_button' id='contours'>Contours: off</button>";
+pub const CONTOURS_ID: &str = "contours";
+
+pub const HOUGH_BUTTON: &str = "<button class='hough_button' id='hough'>Hough: off</button>";
+pub const HOUGH_ID: &str = "hough";
+
+pub const ACCUMULATOR_BUTTON: &str =
+    "<button class='hough_button' id='accumulator'>Accumulator: hidden</button>";
+pub const ACCUMULATOR_ID: &str = "accumulator";
+
+pub const CORNERS_BUTTON: &str =
+    "<button class='corners_button' id='corners'>Corners: off</button>";
+pub const CORNERS_ID: &str = "corners";
+
+pub const MAX_KEYPOINTS_SLIDER: &str =
+    "<label class='slider'>Keypoints <input type='range' id='max_keypoints' min='10' max='1000' step='10'></label>";
+pub const MAX_KEYPOINTS_ID: &str = "max_keypoints";
+
+pub const MATCH_INPUT: &str =
+    "<label class='match_input'>Match image <input type='file' id='match_file' accept='image/*'></label>";
+pub const MATCH_INPUT_ID: &str = "match_file";
+
+pub const MATCHES_BUTTON: &str =
+    "<button class='matches_button' id='matches'>Matches: off</button>";
+pub const MATCHES_ID: &str = "matches";
+
+pub const ALIGN_BUTTON: &str = "<button class='matches_button' id='align'>Align</button>";
+pub const ALIGN_ID: &str = "align";
+
+pub const FLOW_BUTTON: &str = "<button class='flow_button' id='flow'>Flow: off</button>";
+pub const FLOW_ID: &str = "flow";
+
+pub const PANORAMA_INPUT: &str =
+    "<label class='panorama_input'>Panorama <input type='file' id='panorama_files' accept='image/*' multiple></label>";
+pub const PANORAMA_INPUT_ID: &str = "panorama_files";
+
+pub const STITCH_BUTTON: &str = "<button class='stitch_button' id='stitch'>Stitch</button>";
+pub const STITCH_ID: &str = "stitch";
+
+pub const CARVE_BUTTON: &str = "<button class='carve_button' id='carve'>Carve: off</button>";
+pub const CARVE_ID: &str = "carve";
+
+pub const CARVE_MASK_BUTTON: &str =
+    "<button class='carve_button' id='carve_mask'>Carve mask: protect</button>";
+pub const CARVE_MASK_ID: &str = "carve_mask";
+
+pub const ADD_SEAMS_BUTTON: &str = "<button class='carve_button' id='add_seams'>Add seams</button>";
+pub const ADD_SEAMS_ID: &str = "add_seams";
+
+pub const BRUSH_MODE_BUTTON: &str =
+    "<button class='brush_button' id='brush_mode'>Brush: off</button>";
+pub const BRUSH_MODE_ID: &str = "brush_mode";
+
+pub const BRUSH_SIZE_SLIDER: &str =
+    "<label class='slider'>Size <input type='range' id='brush_size' min='2' max='128' step='1'></label>";
+pub const BRUSH_SIZE_ID: &str = "brush_size";
+
+pub const BRUSH_HARDNESS_SLIDER: &str =
+    "<label class='slider'>Hardness <input type='range' id='brush_hardness' min='0' max='1' step='0.05'></label>";
+pub const BRUSH_HARDNESS_ID: &str = "brush_hardness";
+
+pub const BRUSH_OPACITY_SLIDER: &str =
+    "<label class='slider'>Opacity <input type='range' id='brush_opacity' min='0' max='1' step='0.05'></label>";
+pub const BRUSH_OPACITY_ID: &str = "brush_opacity";
+
+pub const BRUSH_SIZE: u32 = 16;
+pub const BRUSH_HARDNESS: f32 = 0.5;
+pub const BRUSH_OPACITY: f32 = 0.8;
+
+pub const MASK_OVERLAY_COLOR: [u8; 3] = [0, 140, 186];
+pub const MASK_OVERLAY_ALPHA: u8 = 96;
+
+// Up to this radius sorting the window beats the sliding histograms.
+pub const MEDIAN_SORT_RADIUS: u32 = 1;
+// Pipeline steps slower than a frame at 60fps are logged with their timing.
+pub const SLOW_OPERATION_MS: f64 = 16.0;
+
+// Palettes are built from at most this many pixels of a frame.
+pub const QUANTIZE_SAMPLES: usize = 16384;
+pub const OCTREE_DEPTH: usize = 8;
+pub const KMEANS_ITERATIONS: usize = 16;
+pub const KMEANS_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+pub const SWATCH_STRIP: &str = "<div class='swatches' id='swatches'></div>";
+pub const SWATCH_STRIP_ID: &str = "swatches";
+pub const SWATCH_COUNT: usize = 8;
+
+pub const PALETTE_METHOD_BUTTON: &str =
+    "<button class='palette_button' id='palette_method'>Palette: median cut</button>";
+pub const PALETTE_METHOD_ID: &str = "palette_method";
+
+// Channel gain at full temperature or tint.
+pub const WHITE_BALANCE_RANGE: f32 = 0.3;
+pub const WHITE_PATCH_PERCENTILE: f32 = 0.99;
+
+// Previous frames each temporal step keeps by default.
+pub const FRAME_HISTORY_DEPTH: usize = 8;
+
+// Luma change that counts as motion, and the morphology that cleans it up.
+pub const MOTION_THRESHOLD: u8 = 24;
+pub const MOTION_OPEN_RADIUS: u32 = 1;
+pub const MOTION_CLOSE_RADIUS: u32 = 3;
+pub const MOTION_MIN_AREA: usize = 64;
+pub const MOTION_BOX_COLOR: &str = "#FF3B30";
+
+// Gaussian mixture background model, with the defaults of OpenCV's MOG2.
+// A learning rate of zero lets the model pick it from the frame count.
+pub const MOG2_MAX_MODES: usize = 4;
+pub const MOG2_HISTORY: u32 = 200;
+pub const MOG2_BACKGROUND_RATIO: f32 = 0.9;
+pub const MOG2_VARIANCE_THRESHOLD: f32 = 16.0;
+pub const MOG2_VARIANCE_THRESHOLD_GENERATE: f32 = 9.0;
+pub const MOG2_VARIANCE_INIT: f32 = 15.0;
+pub const MOG2_VARIANCE_MIN: f32 = 4.0;
+pub const MOG2_VARIANCE_MAX: f32 = 75.0;
+pub const MOG2_COMPLEXITY_REDUCTION: f32 = 0.05;
+pub const MOG2_SHADOW_THRESHOLD: f32 = 0.5;
+
+// Components are labeled on the frame thresholded at Otsu's level.
+pub const COMPONENTS_ALPHA: u8 = 160;
+pub const COMPONENTS_LOGGED: usize = 5;
+
+// Contours enclosing less than this area are not drawn.
+pub const CONTOUR_MIN_AREA: f64 = 64.0;
+pub const CONTOUR_EPSILON: f32 = 1.5;
+pub const CONTOUR_COLOR: &str = "#34C759";
+pub const HOLE_COLOR: &str = "#FFCC00";
+pub const HULL_COLOR: &str = "#5AC8FA";
+pub const MIN_RECT_COLOR: &str = "#AF52DE";
+
+// Edges for the Hough transforms come from Canny on a blurred luma.
+pub const CANNY_SIGMA: f32 = 1.4;
+pub const CANNY_LOW: f32 = 50.0;
+pub const CANNY_HIGH: f32 = 150.0;
+pub const HOUGH_THETA_STEPS: usize = 180;
+pub const HOUGH_LINE_VOTES: u32 = 120;
+pub const HOUGH_SEGMENT_VOTES: u32 = 40;
+pub const HOUGH_MIN_SEGMENT: u32 = 30;
+pub const HOUGH_MAX_GAP: u32 = 4;
+pub const HOUGH_MAX_LINES: usize = 24;
+pub const HOUGH_RADII: (u32, u32) = (8, 120);
+pub const HOUGH_CENTER_VOTES: u32 = 40;
+// Fraction of the circumference a circle's edge pixels must cover.
+pub const HOUGH_CIRCLE_SUPPORT: f32 = 0.5;
+pub const HOUGH_MAX_CIRCLES: usize = 12;
+pub const HOUGH_SEED: u64 = 0xD1B5_4A32_D192_ED03;
+pub const HOUGH_COLOR: &str = "#FF9500";
+// Longest side of the accumulator drawn next to the detections.
+pub const ACCUMULATOR_SIZE: f64 = 300.0;
+
+// Corner responses are kept above this fraction of the strongest one.
+pub const CORNER_QUALITY: f32 = 0.01;
+pub const CORNER_MIN_DISTANCE: f32 = 8.0;
+pub const CORNER_WINDOW_SIGMA: f32 = 1.5;
+pub const HARRIS_K: f32 = 0.04;
+pub const FAST_THRESHOLD: f32 = 20.0;
+pub const MAX_KEYPOINTS: usize = 200;
+pub const KEYPOINT_RADIUS: f64 = 3.0;
+pub const KEYPOINT_COLOR: &str = "#FFD60A";
+
+// ORB features compared between the frame and a second image.
+pub const ORB_FEATURES: usize = 500;
+pub const ORB_PATCH_RADIUS: i32 = 15;
+pub const ORB_BLUR_SIGMA: f32 = 2.0;
+pub const ORB_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+pub const MATCH_RATIO: f32 = 0.8;
+pub const MATCH_COLOR: &str = "#30D158";
+pub const OUTLIER_COLOR: &str = "#FF453A";
+
+// Reprojection error in pixels under which a match supports a homography.
+pub const RANSAC_THRESHOLD: f64 = 3.0;
+pub const RANSAC_CONFIDENCE: f64 = 0.995;
+pub const RANSAC_MAX_ITERATIONS: usize = 2000;
+pub const RANSAC_SEED: u64 = 0x94D0_49BB_1331_11EB;
+pub const ALIGNED_LAYER_OPACITY: f32 = 0.5;
+
+// Synthetic footage made from the loaded image.
+pub const SOURCE_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+// Side of the moving square, as a share of the shorter side of the frame.
+pub const SOURCE_OBJECT_FRACTION: f64 = 0.15;
+pub const SOURCE_OBJECT_COLOR: [u8; 4] = [255, 149, 0, 255];
+// Radians of its path covered per frame.
+pub const SOURCE_OBJECT_SPEED: f64 = 0.05;
+// Largest jolt per frame of the handheld camera, and how much of its offset
+// survives to the next frame.
+pub const SOURCE_SHAKE_SHIFT: f64 = 4.0;
+pub const SOURCE_SHAKE_ANGLE: f64 = 0.01;
+pub const SOURCE_SHAKE_DAMPING: f64 = 0.9;
+
+// Frames averaged into the smoothed camera path.
+pub const STABILIZATION_WINDOW: usize = 30;
+pub const STABILIZATION_FEATURES: usize = 300;
+pub const STABILIZATION_MAX_ZOOM: f64 = 1.5;
+// How much the zoom may shrink back per frame.
+pub const STABILIZATION_ZOOM_RELAX: f64 = 0.005;
+
+// Optical flow between consecutive frames.
+pub const FLOW_LEVELS: usize = 3;
+// Smallest side of the coarsest pyramid level.
+pub const FLOW_MIN_SIZE: u32 = 16;
+pub const FLOW_MAX_TRACKS: usize = 200;
+pub const LUCAS_KANADE_RADIUS: i32 = 7;
+pub const LUCAS_KANADE_ITERATIONS: usize = 20;
+// Smallest eigenvalue of the gradient matrix, per window pixel, of a window
+// worth tracking.
+pub const FLOW_MIN_EIGENVALUE: f32 = 1.0;
+pub const FARNEBACK_ITERATIONS: usize = 3;
+pub const FARNEBACK_POLY_RADIUS: i32 = 5;
+pub const FARNEBACK_POLY_SIGMA: f32 = 1.1;
+pub const FARNEBACK_WINDOW_SIGMA: f32 = 4.0;
+pub const FLOW_ARROW_STEP: u32 = 16;
+pub const FLOW_MIN_ARROW: f32 = 0.5;
+pub const FLOW_ARROW_SCALE: f32 = 3.0;
+pub const FLOW_COLOR: &str = "#FFFFFF";
+
+// Bands of a multi-band blend, each twice as coarse as the one before.
+pub const PYRAMID_LEVELS: usize = 6;
+
+pub const PANORAMA_FEATURES: usize = 500;
+// Inliers a pair of images needs before it is trusted to overlap.
+pub const PANORAMA_MIN_INLIERS: usize = 12;
+pub const PANORAMA_MAX_SIDE: u32 = 2400;
+
+// Energy the mask adds to, or takes from, each pixel, far above any gradient
+// so that seams always avoid or seek the masked pixels.
+pub const CARVE_MASK_ENERGY: f32 = 1.0e6;
+// Share of the carved side that one press of "Add seams" inserts.
+pub const CARVE_INSERT_FRACTION: f64 = 0.1;
@@ -0,0 +1,484 @@
+use crate::binary::BinaryImage;
+
+// Neighbours in clockwise order on screen, starting east.
+const DIRECTIONS: [(i32, i32); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+#[derive(Clone, Debug)]
+pub struct Contour {
+    pub points: Vec<(i32, i32)>,
+    // Holes are the inner borders of a region, outer borders enclose one.
+    pub hole: bool,
+    // Index of the border directly enclosing this one.
+    pub parent: Option<usize>,
+}
+
+// Suzuki and Abe's border following: every border pixel is marked with the
+// number of the border it belongs to, so the number last crossed along a row
+// tells which border encloses a new one and the borders form a tree.
+pub fn find_contours(binary: &BinaryImage) -> Vec<Contour> {
+    // A frame of background around the image keeps every walk inside.
+    let (width, height) = (binary.width() as i32 + 2, binary.height() as i32 + 2);
+    let mut labels = vec![0i32; (width * height) as usize];
+    for (index, &value) in binary.values().iter().enumerate() {
+        let (x, y) = (index as i32 % (width - 2), index as i32 / (width - 2));
+        labels[((y + 1) * width + x + 1) as usize] = value as i32;
+    }
+    let at = |x: i32, y: i32| (y * width + x) as usize;
+
+    let mut contours: Vec<Contour> = vec![];
+    for y in 1..height - 1 {
+        // Border 1 is the frame, which behaves as a hole without parent.
+        let mut last = 1;
+        for x in 1..width - 1 {
+            let value = labels[at(x, y)];
+            let start = if value == 1 && labels[at(x - 1, y)] == 0 {
+                Some((x - 1, false))
+            } else if value >= 1 && labels[at(x + 1, y)] == 0 {
+                if value > 1 {
+                    last = value;
+                }
+                Some((x + 1, true))
+            } else {
+                None
+            };
+
+            if let Some((from, hole)) = start {
+                let number = contours.len() as i32 + 2;
+                let enclosing = match last {
+                    1 => (true, None),
+                    last => (contours[last as usize - 2].hole, Some(last as usize - 2)),
+                };
+                // Borders of the same kind are siblings, otherwise the last
+                // border crossed is the parent.
+                let parent = match enclosing {
+                    (enclosing_hole, index) if enclosing_hole != hole => index,
+                    (_, index) => index.and_then(|index| contours[index].parent),
+                };
+                let points = follow(&mut labels, width, (x, y), (from, y), number);
+                contours.push(Contour {
+                    points,
+                    hole,
+                    parent,
+                });
+            }
+
+            let value = labels[at(x, y)];
+            if value != 0 && value != 1 {
+                last = value.abs();
+            }
+        }
+    }
+    contours
+}
+
+fn direction(from: (i32, i32), to: (i32, i32)) -> usize {
+    let offset = (to.0 - from.0, to.1 - from.1);
+    DIRECTIONS
+        .iter()
+        .position(|&direction| direction == offset)
+        .unwrap_or(0)
+}
+
+fn follow(
+    labels: &mut [i32],
+    width: i32,
+    start: (i32, i32),
+    from: (i32, i32),
+    number: i32,
+) -> Vec<(i32, i32)> {
+    let at = |(x, y): (i32, i32)| (y * width + x) as usize;
+    let step = |(x, y): (i32, i32), d: usize| (x + DIRECTIONS[d].0, y + DIRECTIONS[d].1);
+
+    // Look clockwise around the start for the first set neighbour.
+    let first = direction(start, from);
+    let found = (0..8)
+        .map(|k| (first + k) % 8)
+        .find(|&d| labels[at(step(start, d))] != 0);
+    let Some(found) = found else {
+        labels[at(start)] = -number;
+        return vec![(start.0 - 1, start.1 - 1)];
+    };
+
+    let second = step(start, found);
+    let (mut previous, mut current) = (second, start);
+    let mut points = vec![];
+    loop {
+        points.push((current.0 - 1, current.1 - 1));
+        // Then counterclockwise around the current pixel, starting after the
+        // one we came from.
+        let came = direction(current, previous);
+        let mut east_examined = false;
+        let mut next = current;
+        for k in 1..=8 {
+            let d = (came + 8 - k) % 8;
+            let candidate = step(current, d);
+            if labels[at(candidate)] != 0 {
+                next = candidate;
+                break;
+            }
+            east_examined |= d == 0;
+        }
+
+        if east_examined {
+            labels[at(current)] = -number;
+        } else if labels[at(current)] == 1 {
+            labels[at(current)] = number;
+        }
+        if next == start && current == second {
+            break;
+        }
+        previous = current;
+        current = next;
+    }
+    points
+}
+
+// Douglas–Peucker on a closed contour: it is split at the point farthest from
+// the first one and each half keeps only the points farther than epsilon from
+// the chord they are simplified to.
+pub fn simplify(points: &[(i32, i32)], epsilon: f32) -> Vec<(i32, i32)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let farthest = (1..points.len())
+        .max_by_key(|&i| squared_distance(points[0], points[i]))
+        .unwrap_or(0);
+    let mut keep = vec![false; points.len() + 1];
+    keep[0] = true;
+    keep[farthest] = true;
+
+    let point = |i: usize| points[i % points.len()];
+    let mut stack = vec![(0, farthest), (farthest, points.len())];
+    while let Some((first, last)) = stack.pop() {
+        let chord = (point(first), point(last));
+        let split = (first + 1..last)
+            .map(|i| (i, segment_distance(point(i), chord)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, distance)) = split {
+            if distance > epsilon {
+                keep[i] = true;
+                stack.push((first, i));
+                stack.push((i, last));
+            }
+        }
+    }
+    (0..points.len())
+        .filter(|&i| keep[i])
+        .map(|i| points[i])
+        .collect()
+}
+
+fn squared_distance(a: (i32, i32), b: (i32, i32)) -> i64 {
+    let (dx, dy) = ((a.0 - b.0) as i64, (a.1 - b.1) as i64);
+    dx * dx + dy * dy
+}
+
+fn segment_distance(point: (i32, i32), (a, b): ((i32, i32), (i32, i32))) -> f32 {
+    let length = (squared_distance(a, b) as f32).sqrt();
+    if length == 0.0 {
+        return (squared_distance(point, a) as f32).sqrt();
+    }
+    let cross = cross(a, b, point);
+    cross.abs() as f32 / length
+}
+
+fn cross(o: (i32, i32), a: (i32, i32), b: (i32, i32)) -> i64 {
+    (a.0 - o.0) as i64 * (b.1 - o.1) as i64 - (a.1 - o.1) as i64 * (b.0 - o.0) as i64
+}
+
+// Andrew's monotone chain, without collinear points.
+pub fn convex_hull(points: &[(i32, i32)]) -> Vec<(i32, i32)> {
+    let mut sorted = points.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+    if sorted.len() < 3 {
+        return sorted;
+    }
+    let mut hull: Vec<(i32, i32)> = vec![];
+    for pass in [
+        &sorted[..],
+        &sorted.iter().rev().copied().collect::<Vec<_>>()[..],
+    ] {
+        let floor = hull.len();
+        for &point in pass {
+            while hull.len() >= floor + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0
+            {
+                hull.pop();
+            }
+            hull.push(point);
+        }
+        // The last point of each chain starts the other one.
+        hull.pop();
+    }
+    hull
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RotatedRect {
+    pub center: (f32, f32),
+    pub size: (f32, f32),
+    // Of the first side, in radians.
+    pub angle: f32,
+}
+
+impl RotatedRect {
+    pub fn corners(&self) -> [(f32, f32); 4] {
+        let (cos, sin) = (self.angle.cos(), self.angle.sin());
+        let (half_width, half_height) = (self.size.0 / 2.0, self.size.1 / 2.0);
+        [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(u, v)| {
+            let (u, v) = (u * half_width, v * half_height);
+            (
+                self.center.0 + u * cos - v * sin,
+                self.center.1 + u * sin + v * cos,
+            )
+        })
+    }
+}
+
+// The minimum-area rectangle has a side along an edge of the convex hull, so
+// it is enough to try the hull edges as directions.
+pub fn min_area_rect(points: &[(i32, i32)]) -> Option<RotatedRect> {
+    let hull = convex_hull(points);
+    let first = *hull.first()?;
+    if hull.len() == 1 {
+        return Some(RotatedRect {
+            center: (first.0 as f32, first.1 as f32),
+            size: (0.0, 0.0),
+            angle: 0.0,
+        });
+    }
+
+    let mut best: Option<(f32, RotatedRect)> = None;
+    for (i, &a) in hull.iter().enumerate() {
+        let b = hull[(i + 1) % hull.len()];
+        let (dx, dy) = ((b.0 - a.0) as f32, (b.1 - a.1) as f32);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 {
+            continue;
+        }
+        let (ux, uy) = (dx / length, dy / length);
+        let (mut min_u, mut max_u, mut min_v, mut max_v) = (f32::MAX, f32::MIN, f32::MAX, f32::MIN);
+        for &(x, y) in &hull {
+            let (x, y) = (x as f32, y as f32);
+            let (u, v) = (x * ux + y * uy, -x * uy + y * ux);
+            min_u = min_u.min(u);
+            max_u = max_u.max(u);
+            min_v = min_v.min(v);
+            max_v = max_v.max(v);
+        }
+        let area = (max_u - min_u) * (max_v - min_v);
+        if best.as_ref().is_some_and(|(best, _)| *best <= area) {
+            continue;
+        }
+        let (u, v) = ((min_u + max_u) / 2.0, (min_v + max_v) / 2.0);
+        let rect = RotatedRect {
+            center: (u * ux - v * uy, u * uy + v * ux),
+            size: (max_u - min_u, max_v - min_v),
+            angle: uy.atan2(ux),
+        };
+        best = Some((area, rect));
+    }
+    best.map(|(_, rect)| rect)
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Moments {
+    pub m00: f64,
+    pub m10: f64,
+    pub m01: f64,
+    pub m20: f64,
+    pub m11: f64,
+    pub m02: f64,
+}
+
+impl Moments {
+    pub fn centroid(&self) -> Option<(f64, f64)> {
+        (self.m00 != 0.0).then(|| (self.m10 / self.m00, self.m01 / self.m00))
+    }
+}
+
+// Moments of the polygon enclosed by the contour, from Green's theorem, with
+// the orientation of the walk factored out.
+pub fn moments(points: &[(i32, i32)]) -> Moments {
+    let mut moments = Moments::default();
+    for (i, &(x0, y0)) in points.iter().enumerate() {
+        let (x1, y1) = points[(i + 1) % points.len()];
+        let (x0, y0, x1, y1) = (x0 as f64, y0 as f64, x1 as f64, y1 as f64);
+        let a = x0 * y1 - x1 * y0;
+        moments.m00 += a / 2.0;
+        moments.m10 += (x0 + x1) * a / 6.0;
+        moments.m01 += (y0 + y1) * a / 6.0;
+        moments.m20 += (x0 * x0 + x0 * x1 + x1 * x1) * a / 12.0;
+        moments.m02 += (y0 * y0 + y0 * y1 + y1 * y1) * a / 12.0;
+        moments.m11 += (x0 * y1 + 2.0 * x0 * y0 + 2.0 * x1 * y1 + x1 * y0) * a / 24.0;
+    }
+    if moments.m00 < 0.0 {
+        moments = Moments {
+            m00: -moments.m00,
+            m10: -moments.m10,
+            m01: -moments.m01,
+            m20: -moments.m20,
+            m11: -moments.m11,
+            m02: -moments.m02,
+        };
+    }
+    moments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(rows: &[&str]) -> BinaryImage {
+        let values = rows
+            .iter()
+            .flat_map(|row| row.chars().map(|c| c == '#'))
+            .collect();
+        BinaryImage::from_values(values, rows[0].len() as u32, rows.len() as u32)
+    }
+
+    fn tree(contours: &[Contour]) -> Vec<(bool, Option<usize>)> {
+        contours.iter().map(|c| (c.hole, c.parent)).collect()
+    }
+
+    #[test]
+    fn ring_has_an_outer_border_and_a_hole() {
+        let rows = [
+            ".......", ".#####.", ".#...#.", ".#...#.", ".#...#.", ".#####.", ".......",
+        ];
+        let contours = find_contours(&binary(&rows));
+        assert_eq!(tree(&contours), [(false, None), (true, Some(0))]);
+        for contour in &contours {
+            assert!(contour
+                .points
+                .iter()
+                .all(|&(x, y)| rows[y as usize].as_bytes()[x as usize] == b'#'));
+        }
+        assert_eq!(contours[0].points.len(), 16);
+        assert_eq!(contours[0].points[0], (1, 1));
+    }
+
+    #[test]
+    fn nested_shapes_form_a_tree() {
+        let contours = find_contours(&binary(&[
+            "#######..##",
+            "#.....#..##",
+            "#.###.#....",
+            "#.#.#.#....",
+            "#.###.#....",
+            "#.....#....",
+            "#######....",
+        ]));
+        // Outer ring, the separate block, the ring's hole, the island inside
+        // it and the island's own hole, in the order their first pixels are
+        // met.
+        assert_eq!(
+            tree(&contours),
+            [
+                (false, None),
+                (false, None),
+                (true, Some(0)),
+                (false, Some(2)),
+                (true, Some(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn filled_shapes_follow_their_border() {
+        let contours = find_contours(&binary(&["....", ".###", ".###", ".###", ".###"]));
+        assert_eq!(tree(&contours), [(false, None)]);
+        assert_eq!(
+            contours[0].points,
+            [
+                (1, 1),
+                (1, 2),
+                (1, 3),
+                (1, 4),
+                (2, 4),
+                (3, 4),
+                (3, 3),
+                (3, 2),
+                (3, 1),
+                (2, 1)
+            ]
+        );
+        let single = find_contours(&binary(&["...", ".#.", "..."]));
+        assert_eq!(single[0].points, [(1, 1)]);
+    }
+
+    #[test]
+    fn simplify_keeps_the_corners() {
+        let mut points = vec![];
+        points.extend((0..10).map(|y| (0, y)));
+        points.extend((0..20).map(|x| (x, 10)));
+        points.extend((1..=10).rev().map(|y| (20, y)));
+        points.extend((1..=20).rev().map(|x| (x, 0)));
+        let mut corners = simplify(&points, 0.5);
+        corners.sort_unstable();
+        assert_eq!(corners, [(0, 0), (0, 10), (20, 0), (20, 10)]);
+        assert_eq!(simplify(&points[..2], 0.5), &points[..2]);
+    }
+
+    #[test]
+    fn hull_drops_inner_and_collinear_points() {
+        let points = [
+            (0, 0),
+            (2, 0),
+            (4, 0),
+            (4, 4),
+            (0, 4),
+            (2, 2),
+            (1, 3),
+            (0, 2),
+        ];
+        let mut hull = convex_hull(&points);
+        hull.sort_unstable();
+        assert_eq!(hull, [(0, 0), (0, 4), (4, 0), (4, 4)]);
+    }
+
+    #[test]
+    fn min_area_rect_fits_a_rotated_square() {
+        let rect = min_area_rect(&[(5, 0), (10, 5), (5, 10), (0, 5), (5, 5)]).unwrap();
+        assert!((rect.center.0 - 5.0).abs() < 1e-4 && (rect.center.1 - 5.0).abs() < 1e-4);
+        assert!((rect.size.0 * rect.size.1 - 50.0).abs() < 1e-3);
+        assert!(
+            (rect.angle.abs() % std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4).abs()
+                < 1e-4
+        );
+        for (x, y) in rect.corners() {
+            let corner = [(5.0, 0.0), (10.0, 5.0), (5.0, 10.0), (0.0, 5.0)]
+                .iter()
+                .any(|&(cx, cy): &(f32, f32)| (cx - x).hypot(cy - y) < 1e-3);
+            assert!(corner, "{:?}", (x, y));
+        }
+
+        let upright = min_area_rect(&[(1, 2), (7, 2), (7, 5), (1, 5), (3, 3)]).unwrap();
+        assert!((upright.size.0 * upright.size.1 - 18.0).abs() < 1e-4);
+        assert!((upright.center.0 - 4.0).abs() < 1e-4 && (upright.center.1 - 3.5).abs() < 1e-4);
+        assert!(min_area_rect(&[]).is_none());
+    }
+
+    #[test]
+    fn moments_do_not_depend_on_the_walk_direction() {
+        let mut points = vec![(0, 0), (4, 0), (4, 3), (0, 3)];
+        for _ in 0..2 {
+            let moments = moments(&points);
+            assert_eq!(moments.m00, 12.0);
+            assert_eq!(moments.centroid(), Some((2.0, 1.5)));
+            // The integral of xy over the rectangle.
+            assert!((moments.m11 - 36.0).abs() < 1e-9);
+            points.reverse();
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use crate::constants::{LUT_MAX_1D_SIZE, LUT_MAX_3D_SIZE};
+use crate::image::RawImage;
+use anyhow::{anyhow, bail, Context, Result};
+use web_sys::File;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Trilinear,
+    Tetrahedral,
+}
+
+impl Interpolation {
+    pub fn next(self) -> Self {
+        match self {
+            Interpolation::Trilinear => Interpolation::Tetrahedral,
+            Interpolation::Tetrahedral => Interpolation::Trilinear,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Interpolation::Trilinear => "LUT: trilinear",
+            Interpolation::Tetrahedral => "LUT: tetrahedral",
+        }
+    }
+}
+
+// A `.cube` file can hold a 1D table, a 3D table or both, in which case the
+// 1D table is a shaper applied before the cube.
+#[derive(Clone, Debug)]
+pub struct CubeLut {
+    title: Option<String>,
+    shaper: Option<Table>,
+    cube: Option<Table>,
+}
+
+#[derive(Clone, Debug)]
+struct Table {
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    values: Vec<[f32; 3]>,
+}
+
+impl CubeLut {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut title = None;
+        let (mut size_1d, mut size_3d) = (None, None);
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut range_1d = None;
+        let mut range_3d = None;
+        let mut values = vec![];
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let context = || format!("line {}: {}", number + 1, line);
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match keyword {
+                "TITLE" => title = Some(rest.trim().trim_matches('"').to_string()),
+                "LUT_1D_SIZE" => {
+                    size_1d = Some(parse_size(rest, LUT_MAX_1D_SIZE).with_context(context)?)
+                }
+                "LUT_3D_SIZE" => {
+                    size_3d = Some(parse_size(rest, LUT_MAX_3D_SIZE).with_context(context)?)
+                }
+                "DOMAIN_MIN" => domain_min = parse_triplet(rest).with_context(context)?,
+                "DOMAIN_MAX" => domain_max = parse_triplet(rest).with_context(context)?,
+                "LUT_1D_INPUT_RANGE" => range_1d = Some(parse_range(rest).with_context(context)?),
+                "LUT_3D_INPUT_RANGE" => range_3d = Some(parse_range(rest).with_context(context)?),
+                _ if keyword.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+                    values.push(parse_triplet(line).with_context(context)?)
+                }
+                // Unknown keywords are vendor extensions and safe to skip.
+                _ => {}
+            }
+        }
+
+        let expected_1d = size_1d.unwrap_or(0);
+        let expected_3d = match size_3d {
+            Some(size) => size
+                .checked_mul(size)
+                .and_then(|square| square.checked_mul(size))
+                .ok_or_else(|| anyhow!("LUT_3D_SIZE {} is too large", size))?,
+            None => 0,
+        };
+        let expected = expected_1d
+            .checked_add(expected_3d)
+            .ok_or_else(|| anyhow!("LUT has too many entries"))?;
+        if expected == 0 {
+            bail!("LUT has neither LUT_1D_SIZE nor LUT_3D_SIZE");
+        }
+        if values.len() != expected {
+            bail!("LUT has {} entries, expected {}", values.len(), expected);
+        }
+
+        let cube_values = values.split_off(expected_1d);
+        let table = |size, range: Option<(f32, f32)>, values| Table {
+            size,
+            domain_min: range.map_or(domain_min, |(min, _)| [min; 3]),
+            domain_max: range.map_or(domain_max, |(_, max)| [max; 3]),
+            values,
+        };
+        Ok(CubeLut {
+            title,
+            shaper: size_1d.map(|size| table(size, range_1d, values)),
+            cube: size_3d.map(|size| table(size, range_3d, cube_values)),
+        })
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    // Strength mixes the graded colors with the originals.
+    pub fn apply(&self, image: &mut RawImage, interpolation: Interpolation, strength: f32) {
+        let strength = strength.clamp(0.0, 1.0);
+        for pixel in image.pixels_mut().chunks_exact_mut(4) {
+            let original = [pixel[0], pixel[1], pixel[2]].map(|value| value as f32 / 255.0);
+            let mut color = original;
+            if let Some(shaper) = &self.shaper {
+                color = shaper.sample_1d(color);
+            }
+            if let Some(cube) = &self.cube {
+                color = cube.sample_3d(color, interpolation);
+            }
+            for c in 0..3 {
+                let mixed = original[c] + (color[c] - original[c]) * strength;
+                pixel[c] = (mixed * 255.0).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+// Native builds have no file picker, so LUTs are read from disk instead. The
+// library only runs in the browser so far, which leaves it to the tests.
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(dead_code)]
+pub fn load(path: impl AsRef<std::path::Path>) -> Result<CubeLut> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read LUT {}", path.display()))?;
+    CubeLut::parse(&text)
+}
+
+pub async fn load_file(file: &File) -> Result<CubeLut> {
+    let text = wasm_bindgen_futures::JsFuture::from(file.text())
+        .await
+        .map_err(|err| anyhow!("could not read LUT file {:#?}", err))?
+        .as_string()
+        .ok_or_else(|| anyhow!("LUT file {} is not text", file.name()))?;
+    CubeLut::parse(&text).with_context(|| format!("could not parse LUT {}", file.name()))
+}
+
+impl Table {
+    // Maps a color into the table's grid coordinates, 0..=size - 1.
+    fn coordinates(&self, color: [f32; 3]) -> [f32; 3] {
+        let last = (self.size - 1) as f32;
+        [0, 1, 2].map(|c| {
+            let span = (self.domain_max[c] - self.domain_min[c]).max(f32::EPSILON);
+            ((color[c] - self.domain_min[c]) / span * last).clamp(0.0, last)
+        })
+    }
+
+    fn sample_1d(&self, color: [f32; 3]) -> [f32; 3] {
+        let coordinates = self.coordinates(color);
+        [0, 1, 2].map(|c| {
+            let position = coordinates[c];
+            let low = position.floor() as usize;
+            let high = (low + 1).min(self.size - 1);
+            let t = position - low as f32;
+            self.values[low][c] * (1.0 - t) + self.values[high][c] * t
+        })
+    }
+
+    // Entries are ordered with red changing fastest.
+    fn at(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.values[r + self.size * (g + self.size * b)]
+    }
+
+    fn sample_3d(&self, color: [f32; 3], interpolation: Interpolation) -> [f32; 3] {
+        let coordinates = self.coordinates(color);
+        let low = coordinates.map(|position| position.floor() as usize);
+        let high = low.map(|index| (index + 1).min(self.size - 1));
+        let [fr, fg, fb] = [0, 1, 2].map(|c| coordinates[c] - low[c] as f32);
+        let corner = |r: bool, g: bool, b: bool| {
+            self.at(
+                if r { high[0] } else { low[0] },
+                if g { high[1] } else { low[1] },
+                if b { high[2] } else { low[2] },
+            )
+        };
+
+        match interpolation {
+            Interpolation::Trilinear => {
+                let lerp =
+                    |a: [f32; 3], b: [f32; 3], t: f32| [0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * t);
+                let c00 = lerp(corner(false, false, false), corner(true, false, false), fr);
+                let c10 = lerp(corner(false, true, false), corner(true, true, false), fr);
+                let c01 = lerp(corner(false, false, true), corner(true, false, true), fr);
+                let c11 = lerp(corner(false, true, true), corner(true, true, true), fr);
+                lerp(lerp(c00, c10, fg), lerp(c01, c11, fg), fb)
+            }
+            // The unit cube is split into six tetrahedra along its diagonal and
+            // only the four corners of the one holding the color are blended.
+            Interpolation::Tetrahedral => {
+                let c000 = corner(false, false, false);
+                let c111 = corner(true, true, true);
+                let (weights, first, second) = if fr > fg {
+                    if fg > fb {
+                        (
+                            [1.0 - fr, fr - fg, fg - fb, fb],
+                            corner(true, false, false),
+                            corner(true, true, false),
+                        )
+                    } else if fr > fb {
+                        (
+                            [1.0 - fr, fr - fb, fb - fg, fg],
+                            corner(true, false, false),
+                            corner(true, false, true),
+                        )
+                    } else {
+                        (
+                            [1.0 - fb, fb - fr, fr - fg, fg],
+                            corner(false, false, true),
+                            corner(true, false, true),
+                        )
+                    }
+                } else if fb > fg {
+                    (
+                        [1.0 - fb, fb - fg, fg - fr, fr],
+                        corner(false, false, true),
+                        corner(false, true, true),
+                    )
+                } else if fb > fr {
+                    (
+                        [1.0 - fg, fg - fb, fb - fr, fr],
+                        corner(false, true, false),
+                        corner(false, true, true),
+                    )
+                } else {
+                    (
+                        [1.0 - fg, fg - fr, fr - fb, fb],
+                        corner(false, true, false),
+                        corner(true, true, false),
+                    )
+                };
+                [0, 1, 2].map(|c| {
+                    weights[0] * c000[c]
+                        + weights[1] * first[c]
+                        + weights[2] * second[c]
+                        + weights[3] * c111[c]
+                })
+            }
+        }
+    }
+}
+
+fn parse_size(text: &str, max: usize) -> Result<usize> {
+    let size: usize = text.trim().parse().context("invalid size")?;
+    if size < 2 {
+        bail!("size must be at least 2");
+    }
+    if size > max {
+        bail!("size must be at most {}", max);
+    }
+    Ok(size)
+}
+
+fn parse_floats<const N: usize>(text: &str) -> Result<[f32; N]> {
+    let numbers = text
+        .split_whitespace()
+        .map(|number| number.parse::<f32>().context("invalid number"))
+        .collect::<Result<Vec<f32>>>()?;
+    numbers
+        .try_into()
+        .map_err(|numbers: Vec<f32>| anyhow!("expected {} numbers, found {}", N, numbers.len()))
+}
+
+fn parse_triplet(text: &str) -> Result<[f32; 3]> {
+    parse_floats(text)
+}
+
+fn parse_range(text: &str) -> Result<(f32, f32)> {
+    let [min, max] = parse_floats(text)?;
+    Ok((min, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: &str = "TITLE \"identity\"
+LUT_3D_SIZE 2
+0 0 0
+1 0 0
+0 1 0
+1 1 0
+0 0 1
+1 0 1
+0 1 1
+1 1 1
+";
+
+    fn colors() -> RawImage {
+        let pixels = (0..=255u8)
+            .step_by(5)
+            .flat_map(|r| [r, 255 - r, r / 3 + 40, 255])
+            .chain([0, 0, 0, 255, 255, 255, 255, 255, 200, 10, 90, 128])
+            .collect::<Vec<u8>>();
+        let width = pixels.len() as u32 / 4;
+        RawImage::from_raw(pixels, width, 1)
+    }
+
+    // A cube of the given size for a color map. Entries run with red changing
+    // fastest.
+    fn cube_text(size: usize, map: impl Fn([f32; 3]) -> [f32; 3]) -> String {
+        let last = (size - 1) as f32;
+        let mut text = format!("LUT_3D_SIZE {}\n", size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let [x, y, z] = map([r as f32 / last, g as f32 / last, b as f32 / last]);
+                    text.push_str(&format!("{} {} {}\n", x, y, z));
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn identity_cube_leaves_colors_unchanged() {
+        let lut = CubeLut::parse(IDENTITY).unwrap();
+        assert_eq!(lut.title(), Some("identity"));
+        for interpolation in [Interpolation::Trilinear, Interpolation::Tetrahedral] {
+            let mut image = colors();
+            lut.apply(&mut image, interpolation, 1.0);
+            assert_eq!(image.pixels(), colors().pixels(), "{:?}", interpolation);
+        }
+    }
+
+    #[test]
+    fn both_interpolations_reproduce_a_linear_map() {
+        let lut = CubeLut::parse(&cube_text(5, |[r, g, b]| [b, r, 1.0 - g])).unwrap();
+        for interpolation in [Interpolation::Trilinear, Interpolation::Tetrahedral] {
+            let mut image = colors();
+            lut.apply(&mut image, interpolation, 1.0);
+            for (out, original) in image
+                .pixels()
+                .chunks_exact(4)
+                .zip(colors().pixels().chunks_exact(4))
+            {
+                assert_eq!(
+                    out,
+                    [original[2], original[0], 255 - original[1], original[3]]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn shaper_runs_before_the_cube_and_strength_mixes() {
+        let text = format!("LUT_1D_SIZE 2\n1 1 1\n0 0 0\n{}", IDENTITY);
+        let lut = CubeLut::parse(&text).unwrap();
+        let mut image = colors();
+        lut.apply(&mut image, Interpolation::Tetrahedral, 1.0);
+        for (out, original) in image
+            .pixels()
+            .chunks_exact(4)
+            .zip(colors().pixels().chunks_exact(4))
+        {
+            assert_eq!(
+                &out[..3],
+                original[..3].iter().map(|c| 255 - c).collect::<Vec<_>>()
+            );
+        }
+
+        let mut image = colors();
+        lut.apply(&mut image, Interpolation::Trilinear, 0.0);
+        assert_eq!(image.pixels(), colors().pixels());
+    }
+
+    #[test]
+    fn domain_rescales_the_input() {
+        let text = format!("DOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 2\n{}", IDENTITY);
+        let lut = CubeLut::parse(&text).unwrap();
+        let mut image = RawImage::from_raw(vec![200, 100, 0, 255], 1, 1);
+        lut.apply(&mut image, Interpolation::Trilinear, 1.0);
+        assert_eq!(image.pixels(), [100, 50, 0, 255]);
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        assert!(CubeLut::parse("# nothing\n").is_err());
+        assert!(CubeLut::parse("LUT_3D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+        assert!(CubeLut::parse("LUT_3D_SIZE 1\n0 0 0\n").is_err());
+        assert!(CubeLut::parse(&IDENTITY.replace("1 1 0", "1 x 0")).is_err());
+        assert!(CubeLut::parse(&IDENTITY.replace("1 1 0", "1 1")).is_err());
+        assert!(CubeLut::parse(&format!("VENDOR_KEY 3\n{}", IDENTITY)).is_ok());
+    }
+
+    #[test]
+    fn oversized_tables_are_rejected_before_counting_entries() {
+        let error = CubeLut::parse("LUT_3D_SIZE 4294967296\n0 0 0\n").unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("at most 256"),
+            "{:#}",
+            error
+        );
+        assert!(CubeLut::parse("LUT_3D_SIZE 257\n0 0 0\n").is_err());
+        assert!(CubeLut::parse("LUT_1D_SIZE 65537\n0 0 0\n").is_err());
+        assert!(CubeLut::parse(&cube_text(2, |color| color)).is_ok());
+    }
+
+    #[test]
+    fn load_reads_a_cube_from_disk() {
+        let path = std::env::temp_dir().join(format!("cube-{}.cube", std::process::id()));
+        let text = cube_text(3, |[r, g, b]| [g, b, r]);
+        std::fs::write(&path, &text).unwrap();
+        let loaded = load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let (mut from_disk, mut parsed) = (colors(), colors());
+        loaded
+            .unwrap()
+            .apply(&mut from_disk, Interpolation::Tetrahedral, 1.0);
+        CubeLut::parse(&text)
+            .unwrap()
+            .apply(&mut parsed, Interpolation::Tetrahedral, 1.0);
+        assert_eq!(from_disk.pixels(), parsed.pixels());
+        assert!(load(&path).is_err());
+    }
+}
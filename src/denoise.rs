@@ -0,0 +1,356 @@
+use crate::constants::MEDIAN_SORT_RADIUS;
+use crate::image::RawImage;
+
+// Only the color channels are filtered, alpha is copied through.
+const CHANNELS: usize = 3;
+
+pub fn median(image: &RawImage, radius: u32) -> RawImage {
+    if radius == 0 || image.is_empty() {
+        return image.clone();
+    }
+    if radius <= MEDIAN_SORT_RADIUS {
+        median_sorted(image, radius)
+    } else {
+        median_histogram(image, radius)
+    }
+}
+
+pub fn bilateral(image: &RawImage, radius: u32, sigma_space: f32, sigma_range: f32) -> RawImage {
+    if radius == 0 || image.is_empty() {
+        return image.clone();
+    }
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels = image.pixels();
+    let mut output = image.clone();
+    let r = radius as i64;
+
+    let spatial: Vec<f32> = (-r..=r)
+        .flat_map(|dy| (-r..=r).map(move |dx| gaussian((dx * dx + dy * dy) as f32, sigma_space)))
+        .collect();
+    // The range distance is the mean absolute channel difference, so it fits a table.
+    let range: Vec<f32> = (0..256)
+        .map(|d| gaussian((d * d) as f32, sigma_range))
+        .collect();
+
+    for y in 0..height {
+        for x in 0..width {
+            let center = (y * width + x) * 4;
+            let mut sum = [0.0f32; CHANNELS];
+            let mut total = 0.0;
+            let mut k = 0;
+            for dy in -r..=r {
+                let row = clamp(y as i64 + dy, height) * width;
+                for dx in -r..=r {
+                    let i = (row + clamp(x as i64 + dx, width)) * 4;
+                    let distance = (0..CHANNELS)
+                        .map(|c| (pixels[i + c] as i32 - pixels[center + c] as i32).unsigned_abs())
+                        .sum::<u32>()
+                        / CHANNELS as u32;
+                    let weight = spatial[k] * range[distance as usize];
+                    k += 1;
+                    for (c, value) in sum.iter_mut().enumerate() {
+                        *value += weight * pixels[i + c] as f32;
+                    }
+                    total += weight;
+                }
+            }
+            let out = output.pixels_mut();
+            for (c, value) in sum.iter().enumerate() {
+                out[center + c] = (value / total).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    output
+}
+
+// Patch distances for every offset of the search window come from an integral
+// image of squared differences, so the cost does not depend on the patch size.
+pub fn non_local_means(
+    image: &RawImage,
+    patch_radius: u32,
+    search_radius: u32,
+    strength: f32,
+) -> RawImage {
+    if search_radius == 0 || image.is_empty() {
+        return image.clone();
+    }
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels = image.pixels();
+    let (p, s) = (patch_radius as usize, search_radius as i64);
+    let filter = (strength * strength).max(f32::EPSILON);
+    let stride = width + 1;
+
+    let mut sums = vec![0.0f32; width * height * CHANNELS];
+    let mut weights = vec![0.0f32; width * height];
+    let mut integral = vec![0u64; stride * (height + 1)];
+
+    for dy in -s..=s {
+        for dx in -s..=s {
+            let neighbor = |x: usize, y: usize| {
+                (clamp(y as i64 + dy, height) * width + clamp(x as i64 + dx, width)) * 4
+            };
+
+            for y in 0..height {
+                let mut row_sum = 0;
+                for x in 0..width {
+                    let (a, b) = ((y * width + x) * 4, neighbor(x, y));
+                    row_sum += (0..CHANNELS)
+                        .map(|c| {
+                            let d = pixels[a + c] as i64 - pixels[b + c] as i64;
+                            (d * d) as u64
+                        })
+                        .sum::<u64>();
+                    integral[(y + 1) * stride + x + 1] = integral[y * stride + x + 1] + row_sum;
+                }
+            }
+
+            for y in 0..height {
+                let (y0, y1) = (y.saturating_sub(p), (y + p + 1).min(height));
+                for x in 0..width {
+                    let (x0, x1) = (x.saturating_sub(p), (x + p + 1).min(width));
+                    let distance = (integral[y1 * stride + x1] + integral[y0 * stride + x0])
+                        - (integral[y0 * stride + x1] + integral[y1 * stride + x0]);
+                    let area = ((x1 - x0) * (y1 - y0) * CHANNELS) as f32;
+                    let weight = (-(distance as f32 / area) / filter).exp();
+
+                    let b = neighbor(x, y);
+                    let i = y * width + x;
+                    for c in 0..CHANNELS {
+                        sums[i * CHANNELS + c] += weight * pixels[b + c] as f32;
+                    }
+                    weights[i] += weight;
+                }
+            }
+        }
+    }
+
+    let mut output = image.clone();
+    let out = output.pixels_mut();
+    for (i, weight) in weights.iter().enumerate() {
+        for c in 0..CHANNELS {
+            out[i * 4 + c] = (sums[i * CHANNELS + c] / weight).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    output
+}
+
+fn median_sorted(image: &RawImage, radius: u32) -> RawImage {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels = image.pixels();
+    let mut output = image.clone();
+    let r = radius as i64;
+    let mut window = Vec::with_capacity(((2 * r + 1) * (2 * r + 1)) as usize);
+
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..CHANNELS {
+                window.clear();
+                for dy in -r..=r {
+                    let row = clamp(y as i64 + dy, height) * width;
+                    for dx in -r..=r {
+                        window.push(pixels[(row + clamp(x as i64 + dx, width)) * 4 + c]);
+                    }
+                }
+                let middle = window.len() / 2;
+                output.pixels_mut()[(y * width + x) * 4 + c] =
+                    *window.select_nth_unstable(middle).1;
+            }
+        }
+    }
+    output
+}
+
+// Perreault and Hébert: one histogram per column is slid down the image and the
+// kernel histogram is slid along each row, so each pixel costs the same whatever
+// the radius.
+fn median_histogram(image: &RawImage, radius: u32) -> RawImage {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels = image.pixels();
+    let mut output = image.clone();
+    let r = radius as i64;
+    let half = ((2 * r + 1) * (2 * r + 1) / 2) as u32;
+
+    for c in 0..CHANNELS {
+        let value = |x: usize, y: usize| pixels[(y * width + x) * 4 + c] as usize;
+        let mut columns = vec![[0u16; 256]; width];
+        for (x, column) in columns.iter_mut().enumerate() {
+            for dy in -r..=r {
+                column[value(x, clamp(dy, height))] += 1;
+            }
+        }
+
+        for y in 0..height {
+            if y > 0 {
+                let old = clamp(y as i64 - r - 1, height);
+                let new = clamp(y as i64 + r, height);
+                for (x, column) in columns.iter_mut().enumerate() {
+                    column[value(x, old)] -= 1;
+                    column[value(x, new)] += 1;
+                }
+            }
+
+            let mut kernel = [0u32; 256];
+            for dx in -r..=r {
+                add_histogram(&mut kernel, &columns[clamp(dx, width)]);
+            }
+            for x in 0..width {
+                if x > 0 {
+                    subtract_histogram(&mut kernel, &columns[clamp(x as i64 - r - 1, width)]);
+                    add_histogram(&mut kernel, &columns[clamp(x as i64 + r, width)]);
+                }
+                output.pixels_mut()[(y * width + x) * 4 + c] = histogram_median(&kernel, half);
+            }
+        }
+    }
+    output
+}
+
+fn add_histogram(kernel: &mut [u32; 256], column: &[u16; 256]) {
+    for (bin, count) in kernel.iter_mut().zip(column) {
+        *bin += *count as u32;
+    }
+}
+
+fn subtract_histogram(kernel: &mut [u32; 256], column: &[u16; 256]) {
+    for (bin, count) in kernel.iter_mut().zip(column) {
+        *bin -= *count as u32;
+    }
+}
+
+fn histogram_median(histogram: &[u32; 256], half: u32) -> u8 {
+    let mut count = 0;
+    for (value, bin) in histogram.iter().enumerate() {
+        count += bin;
+        if count > half {
+            return value as u8;
+        }
+    }
+    u8::MAX
+}
+
+fn gaussian(distance_sq: f32, sigma: f32) -> f32 {
+    let sigma = sigma.max(f32::EPSILON);
+    (-distance_sq / (2.0 * sigma * sigma)).exp()
+}
+
+fn clamp(value: i64, len: usize) -> usize {
+    value.clamp(0, len as i64 - 1) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Xorshift;
+    use std::time::Instant;
+
+    fn noisy(width: u32, height: u32, base: impl Fn(u32, u32) -> u8, noise: u8) -> RawImage {
+        let mut random = Xorshift::new(7);
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let value = base(x, y) as i32;
+                let mut channel = || {
+                    let offset = random.below(2 * noise as usize + 1) as i32 - noise as i32;
+                    (value + offset).clamp(0, 255) as u8
+                };
+                [channel(), channel(), channel(), 200]
+            })
+            .collect();
+        RawImage::from_raw(pixels, width, height)
+    }
+
+    fn flat(width: u32, height: u32, value: u8) -> RawImage {
+        noisy(width, height, |_, _| value, 0)
+    }
+
+    fn mean_error(image: &RawImage, expected: impl Fn(u32, u32) -> u8) -> f64 {
+        let width = image.width();
+        let total: u64 = image
+            .pixels()
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(i, pixel)| {
+                let value = expected(i as u32 % width, i as u32 / width) as i32;
+                pixel[..CHANNELS]
+                    .iter()
+                    .map(|&c| (c as i32 - value).unsigned_abs() as u64)
+                    .sum::<u64>()
+            })
+            .sum();
+        total as f64 / (image.pixels().len() / 4 * CHANNELS) as f64
+    }
+
+    #[test]
+    fn median_paths_agree() {
+        let image = noisy(37, 23, |x, y| ((x * 7 + y * 3) % 256) as u8, 60);
+        for radius in 1..=4 {
+            assert!(
+                median_sorted(&image, radius).pixels() == median_histogram(&image, radius).pixels(),
+                "radius {}",
+                radius
+            );
+        }
+    }
+
+    #[test]
+    fn median_removes_salt_noise() {
+        let mut image = flat(16, 16, 90);
+        for index in [17, 50, 123, 200] {
+            image.pixels_mut()[4 * index..4 * index + 3].copy_from_slice(&[255, 0, 255]);
+        }
+        for radius in [1, 3] {
+            let filtered = median(&image, radius);
+            assert_eq!(filtered.pixels(), flat(16, 16, 90).pixels());
+        }
+    }
+
+    #[test]
+    fn bilateral_smooths_flat_areas_and_keeps_edges() {
+        let edge = |x: u32, _| if x < 16 { 40 } else { 210 };
+        let image = noisy(32, 16, edge, 8);
+        let filtered = bilateral(&image, 3, 3.0, 20.0);
+        assert!(mean_error(&filtered, edge) < mean_error(&image, edge) / 2.0);
+        // Each side of the edge keeps its own level.
+        for pixel in filtered.pixels().chunks_exact(4) {
+            assert!(pixel[..CHANNELS]
+                .iter()
+                .all(|&c| (c as i32 - 40).abs() <= 8 || (c as i32 - 210).abs() <= 8));
+            assert_eq!(pixel[3], 200);
+        }
+        assert_eq!(
+            bilateral(&flat(8, 8, 77), 2, 2.0, 10.0).pixels(),
+            flat(8, 8, 77).pixels()
+        );
+    }
+
+    #[test]
+    fn non_local_means_reduces_noise() {
+        let stripes = |x: u32, _| if x % 16 < 8 { 60 } else { 180 };
+        let image = noisy(32, 32, stripes, 20);
+        let filtered = non_local_means(&image, 1, 3, 12.0);
+        assert!(mean_error(&filtered, stripes) < mean_error(&image, stripes) / 2.0);
+        assert_eq!(
+            non_local_means(&flat(8, 8, 77), 1, 2, 12.0).pixels(),
+            flat(8, 8, 77).pixels()
+        );
+    }
+
+    // cargo test --release denoise_timing -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn denoise_timing() {
+        let image = noisy(1024, 768, |x, y| ((x ^ y) % 256) as u8, 20);
+        let time = |name: &str, filter: &dyn Fn() -> RawImage| {
+            let start = Instant::now();
+            filter();
+            println!("{} on 1024x768: {:?}", name, start.elapsed());
+        };
+        time("median r=1 (sorted)", &|| median(&image, 1));
+        time("median r=3 (histogram)", &|| median(&image, 3));
+        time("median r=8 (histogram)", &|| median(&image, 8));
+        time("bilateral r=3", &|| bilateral(&image, 3, 3.0, 20.0));
+        time("non-local means 3x3/7x7", &|| {
+            non_local_means(&image, 1, 3, 12.0)
+        });
+    }
+}
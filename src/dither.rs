@@ -0,0 +1,277 @@
+use crate::image::RawImage;
+use crate::quantize::{self, Color};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DitherMethod {
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    Sierra,
+    // Ordered dithering with a Bayer matrix of the given size, a power of two
+    // between 2 and 16.
+    Bayer(u32),
+}
+
+pub enum DitherLevels<'a> {
+    // Bits per channel.
+    BitDepth(u8),
+    Palette(&'a [Color]),
+}
+
+// Error diffusion kernels as (dx, dy, weight), with the divisor of the weights.
+const FLOYD_STEINBERG: (&[(i64, i64, f32)], f32) =
+    (&[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)], 16.0);
+// Atkinson only diffuses three quarters of the error, which keeps contrast.
+const ATKINSON: (&[(i64, i64, f32)], f32) = (
+    &[
+        (1, 0, 1.0),
+        (2, 0, 1.0),
+        (-1, 1, 1.0),
+        (0, 1, 1.0),
+        (1, 1, 1.0),
+        (0, 2, 1.0),
+    ],
+    8.0,
+);
+const JARVIS_JUDICE_NINKE: (&[(i64, i64, f32)], f32) = (
+    &[
+        (1, 0, 7.0),
+        (2, 0, 5.0),
+        (-2, 1, 3.0),
+        (-1, 1, 5.0),
+        (0, 1, 7.0),
+        (1, 1, 5.0),
+        (2, 1, 3.0),
+        (-2, 2, 1.0),
+        (-1, 2, 3.0),
+        (0, 2, 5.0),
+        (1, 2, 3.0),
+        (2, 2, 1.0),
+    ],
+    48.0,
+);
+const SIERRA: (&[(i64, i64, f32)], f32) = (
+    &[
+        (1, 0, 5.0),
+        (2, 0, 3.0),
+        (-2, 1, 2.0),
+        (-1, 1, 4.0),
+        (0, 1, 5.0),
+        (1, 1, 4.0),
+        (2, 1, 2.0),
+        (-1, 2, 2.0),
+        (0, 2, 3.0),
+        (1, 2, 2.0),
+    ],
+    32.0,
+);
+
+impl DitherLevels<'_> {
+    fn nearest(&self, color: [f32; 3]) -> Color {
+        let clamped = color.map(|value| value.round().clamp(0.0, 255.0) as u8);
+        match self {
+            DitherLevels::BitDepth(bits) => {
+                let steps = ((1u32 << bits.clamp(&1, &8)) - 1) as f32;
+                clamped.map(|value| {
+                    ((value as f32 * steps / 255.0).round() * 255.0 / steps).round() as u8
+                })
+            }
+            DitherLevels::Palette(palette) => palette[quantize::nearest(palette, &clamped)],
+        }
+    }
+
+    // Distance between neighbouring levels, which scales the ordered threshold.
+    fn spread(&self) -> f32 {
+        match self {
+            DitherLevels::BitDepth(bits) => 255.0 / ((1u32 << bits.clamp(&1, &8)) - 1) as f32,
+            DitherLevels::Palette(palette) => 255.0 / (palette.len() as f32).cbrt().max(1.0),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        matches!(self, DitherLevels::Palette(palette) if palette.is_empty())
+    }
+}
+
+pub fn dither(
+    image: &RawImage,
+    levels: &DitherLevels,
+    method: DitherMethod,
+    serpentine: bool,
+) -> RawImage {
+    if image.is_empty() || levels.is_empty() {
+        return image.clone();
+    }
+    match method {
+        DitherMethod::FloydSteinberg => diffuse(image, levels, FLOYD_STEINBERG, serpentine),
+        DitherMethod::Atkinson => diffuse(image, levels, ATKINSON, serpentine),
+        DitherMethod::JarvisJudiceNinke => diffuse(image, levels, JARVIS_JUDICE_NINKE, serpentine),
+        DitherMethod::Sierra => diffuse(image, levels, SIERRA, serpentine),
+        DitherMethod::Bayer(size) => ordered(image, levels, size),
+    }
+}
+
+// Serpentine scanning walks every other row right to left and mirrors the
+// kernel, which breaks up the diagonal artifacts of a fixed direction.
+fn diffuse(
+    image: &RawImage,
+    levels: &DitherLevels,
+    (kernel, divisor): (&[(i64, i64, f32)], f32),
+    serpentine: bool,
+) -> RawImage {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut values: Vec<f32> = image.pixels().iter().map(|&value| value as f32).collect();
+    let mut output = image.clone();
+
+    for y in 0..height {
+        let reversed = serpentine && y % 2 == 1;
+        let direction = if reversed { -1 } else { 1 };
+        for step in 0..width {
+            let x = if reversed { width - 1 - step } else { step };
+            let i = (y * width + x) * 4;
+            let old = [values[i], values[i + 1], values[i + 2]];
+            let new = levels.nearest(old);
+            output.pixels_mut()[i..i + 3].copy_from_slice(&new);
+
+            let error = [0, 1, 2].map(|c| old[c] - new[c] as f32);
+            for &(dx, dy, weight) in kernel {
+                let (nx, ny) = (x as i64 + dx * direction, y as i64 + dy);
+                if nx < 0 || nx >= width as i64 || ny >= height as i64 {
+                    continue;
+                }
+                let j = (ny as usize * width + nx as usize) * 4;
+                for c in 0..3 {
+                    values[j + c] += error[c] * weight / divisor;
+                }
+            }
+        }
+    }
+    output
+}
+
+fn ordered(image: &RawImage, levels: &DitherLevels, size: u32) -> RawImage {
+    let size = size.clamp(2, 16).next_power_of_two() as usize;
+    let matrix = bayer_matrix(size);
+    let area = (size * size) as f32;
+    let spread = levels.spread();
+    let width = image.width() as usize;
+    let mut output = image.clone();
+
+    for (index, pixel) in output.pixels_mut().chunks_exact_mut(4).enumerate() {
+        let (x, y) = (index % width, index / width);
+        let threshold = (matrix[(y % size) * size + x % size] as f32 + 0.5) / area - 0.5;
+        let color = [0, 1, 2].map(|c| pixel[c] as f32 + threshold * spread);
+        pixel[..3].copy_from_slice(&levels.nearest(color));
+    }
+    output
+}
+
+// Each doubling places the four quadrants as 4M, 4M + 2, 4M + 3 and 4M + 1.
+fn bayer_matrix(size: usize) -> Vec<u32> {
+    let mut matrix = vec![0];
+    let mut n = 1;
+    while n < size {
+        let mut next = vec![0; 4 * n * n];
+        for y in 0..n {
+            for x in 0..n {
+                let value = 4 * matrix[y * n + x];
+                next[y * 2 * n + x] = value;
+                next[y * 2 * n + x + n] = value + 2;
+                next[(y + n) * 2 * n + x] = value + 3;
+                next[(y + n) * 2 * n + x + n] = value + 1;
+            }
+        }
+        matrix = next;
+        n *= 2;
+    }
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [DitherMethod; 5] = [
+        DitherMethod::FloydSteinberg,
+        DitherMethod::Atkinson,
+        DitherMethod::JarvisJudiceNinke,
+        DitherMethod::Sierra,
+        DitherMethod::Bayer(4),
+    ];
+
+    fn flat(value: u8) -> RawImage {
+        RawImage::from_raw([value, value, value, 180].repeat(32 * 32), 32, 32)
+    }
+
+    #[test]
+    fn one_bit_keeps_the_average_gray() {
+        for value in [64, 128, 200] {
+            for method in METHODS {
+                for serpentine in [false, true] {
+                    let image =
+                        dither(&flat(value), &DitherLevels::BitDepth(1), method, serpentine);
+                    let pixels: Vec<&[u8]> = image.pixels().chunks_exact(4).collect();
+                    assert!(pixels
+                        .iter()
+                        .all(|p| matches!(p, [0, 0, 0, 180] | [255, 255, 255, 180])));
+                    let white = pixels.iter().filter(|p| p[0] == 255).count();
+                    let share = white as f32 / pixels.len() as f32;
+                    // Atkinson drops a quarter of the error, which pushes
+                    // shadows and highlights further out, and a Bayer matrix
+                    // only has one level per threshold.
+                    let tolerance = match method {
+                        DitherMethod::Atkinson => 0.1,
+                        DitherMethod::Bayer(size) => 1.0 / (size * size) as f32,
+                        _ => 0.02,
+                    };
+                    assert!(
+                        (share - value as f32 / 255.0).abs() < tolerance,
+                        "{:?} on {}: {}",
+                        method,
+                        value,
+                        share
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn palette_dithering_only_uses_the_palette() {
+        let palette = [[255, 0, 0], [0, 0, 255], [255, 255, 255]];
+        let pixels = (0..32 * 32)
+            .flat_map(|i| [(i % 32 * 8) as u8, 60, (i / 32 * 8) as u8, 255])
+            .collect();
+        let image = RawImage::from_raw(pixels, 32, 32);
+        for method in METHODS {
+            let dithered = dither(&image, &DitherLevels::Palette(&palette), method, true);
+            assert!(dithered
+                .pixels()
+                .chunks_exact(4)
+                .all(|p| palette.iter().any(|color| color[..] == p[..3])));
+        }
+        let empty = dither(&image, &DitherLevels::Palette(&[]), METHODS[0], true);
+        assert_eq!(empty.pixels(), image.pixels());
+    }
+
+    #[test]
+    fn levels_already_on_the_grid_are_kept() {
+        let image = flat(85);
+        for method in METHODS {
+            let dithered = dither(&image, &DitherLevels::BitDepth(2), method, false);
+            assert_eq!(dithered.pixels(), image.pixels(), "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn bayer_matrix_orders_every_threshold_once() {
+        assert_eq!(bayer_matrix(2), [0, 2, 3, 1]);
+        assert_eq!(
+            bayer_matrix(4),
+            [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5]
+        );
+        let mut matrix = bayer_matrix(16);
+        matrix.sort_unstable();
+        assert!(matrix.iter().copied().eq(0..256));
+    }
+}
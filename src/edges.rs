@@ -0,0 +1,67 @@
+use crate::binary::BinaryImage;
+use crate::gray::GrayImage;
+
+// Canny on precomputed gradients: magnitudes that are not a maximum across
+// the edge are suppressed, then weak edges survive only when connected to a
+// strong one.
+pub fn canny(gx: &GrayImage, gy: &GrayImage, low: f32, high: f32) -> BinaryImage {
+    let (width, height) = (gx.width() as i32, gx.height() as i32);
+    let magnitude: Vec<f32> = gx
+        .values()
+        .iter()
+        .zip(gy.values())
+        .map(|(dx, dy)| dx.hypot(*dy))
+        .collect();
+    let at = |x: i32, y: i32| {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            0.0
+        } else {
+            magnitude[(y * width + x) as usize]
+        }
+    };
+
+    let mut strength = vec![0u8; magnitude.len()];
+    let mut stack = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            let index = (y * width + x) as usize;
+            let value = magnitude[index];
+            if value < low {
+                continue;
+            }
+            // The gradient direction rounded to one of four neighbour pairs.
+            let angle = gy.values()[index].atan2(gx.values()[index]).to_degrees();
+            let angle = (angle + 180.0) % 180.0;
+            let (dx, dy) = match angle {
+                a if !(22.5..157.5).contains(&a) => (1, 0),
+                a if a < 67.5 => (1, 1),
+                a if a < 112.5 => (0, 1),
+                _ => (-1, 1),
+            };
+            if value < at(x + dx, y + dy) || value <= at(x - dx, y - dy) {
+                continue;
+            }
+            strength[index] = if value >= high { 2 } else { 1 };
+            if value >= high {
+                stack.push((x, y));
+            }
+        }
+    }
+
+    let mut values = vec![false; magnitude.len()];
+    for &(x, y) in &stack {
+        values[(y * width + x) as usize] = true;
+    }
+    while let Some((x, y)) = stack.pop() {
+        for ny in (y - 1).max(0)..(y + 2).min(height) {
+            for nx in (x - 1).max(0)..(x + 2).min(width) {
+                let neighbor = (ny * width + nx) as usize;
+                if strength[neighbor] == 1 && !values[neighbor] {
+                    values[neighbor] = true;
+                    stack.push((nx, ny));
+                }
+            }
+        }
+    }
+    BinaryImage::from_values(values, gx.width(), gx.height())
+}
@@ -0,0 +1,223 @@
+use crate::browser;
+use crate::button;
+use crate::cube::CubeLut;
+use crate::file_drop;
+use crate::file_picker;
+use crate::keyboard::{self, Keymap};
+use crate::pointer::{self, PointerEvent};
+use crate::slider;
+use anyhow::{anyhow, Result};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use wasm_bindgen::JsCast;
+use web_sys::{Event, EventTarget, File, HtmlElement, HtmlImageElement, HtmlInputElement};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    ToggleRun,
+    Finish,
+    Refresh,
+    Save,
+    Undo,
+    Step,
+    NextSelectionTool,
+    InvertSelection,
+    NextBrushMode,
+    NextPaletteMethod,
+    NextEffect,
+    NextLutInterpolation,
+    AddLayer,
+    NextBlendMode,
+    ToggleLayerVisibility,
+    ToggleSeams,
+    Stitch,
+    ToggleMotion,
+    NextSource,
+    NextBackgroundView,
+    NextConnectivity,
+    ToggleContours,
+    NextHoughMode,
+    ToggleAccumulator,
+    NextDetector,
+    ToggleMatches,
+    AlignMatch,
+    ToggleStabilization,
+    NextFlowMode,
+    NextCarveMode,
+    NextCarveMask,
+    AddSeams,
+    Retry,
+    Reset,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Parameter {
+    Feather,
+    BrushSize,
+    BrushHardness,
+    BrushOpacity,
+    LutStrength,
+    LayerOpacity,
+    HistoryDepth,
+    LearningRate,
+    MaxKeypoints,
+    StabilizationWindow,
+}
+
+pub enum UiEvent {
+    Click(Action),
+    Key(Action),
+    Pointer(PointerEvent),
+    FileDrop(File),
+    ImageLoaded(HtmlImageElement),
+    LutFile(File),
+    LutLoaded(CubeLut),
+    MatchFile(File),
+    MatchLoaded(HtmlImageElement),
+    PanoramaFile(File),
+    PanoramaLoaded(HtmlImageElement),
+    ParameterChange(Parameter, f64),
+    Error(anyhow::Error),
+}
+
+pub type EventSender = UnboundedSender<UiEvent>;
+
+pub fn send(sender: &EventSender, event: UiEvent) {
+    if let Err(err) = sender.unbounded_send(event) {
+        error!("Error sending ui event {:#?}", err);
+    }
+}
+
+// Registered handlers are removed again when the listener is dropped, so a
+// handler lives exactly as long as whatever UI owns it.
+pub struct Listener {
+    target: EventTarget,
+    event_type: &'static str,
+    closure: browser::EventClosure<Event>,
+}
+
+impl Listener {
+    pub fn new(
+        target: &EventTarget,
+        event_type: &'static str,
+        handler: impl FnMut(Event) + 'static,
+    ) -> Result<Self> {
+        let closure = browser::closure_wrap(Box::new(handler) as Box<dyn FnMut(Event)>);
+        target
+            .add_event_listener_with_callback(event_type, closure.as_ref().unchecked_ref())
+            .map_err(|err| anyhow!("Could not add {} listener {:#?}", event_type, err))?;
+        Ok(Listener {
+            target: target.clone(),
+            event_type,
+            closure,
+        })
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Err(err) = self.target.remove_event_listener_with_callback(
+            self.event_type,
+            self.closure.as_ref().unchecked_ref(),
+        ) {
+            error!("Error removing {} listener {:#?}", self.event_type, err);
+        }
+    }
+}
+
+pub struct EventBus {
+    sender: EventSender,
+    receiver: UnboundedReceiver<UiEvent>,
+    _canvas_listeners: Vec<Listener>,
+}
+
+impl EventBus {
+    pub fn new(keymap: Keymap) -> Result<Self> {
+        let (sender, receiver) = unbounded();
+        let canvas: HtmlElement = browser::canvas()?.into();
+
+        let mut canvas_listeners = pointer::add_pointer_handler(&canvas, sender.clone())?;
+        canvas_listeners.extend(keyboard::add_key_handler(&canvas, sender.clone(), keymap)?);
+        canvas_listeners.extend(file_drop::add_drop_handler(&canvas, sender.clone())?);
+
+        Ok(EventBus {
+            sender,
+            receiver,
+            _canvas_listeners: canvas_listeners,
+        })
+    }
+
+    pub fn sender(&self) -> EventSender {
+        self.sender.clone()
+    }
+
+    pub fn ui(&self) -> Ui {
+        Ui {
+            sender: self.sender(),
+            listeners: vec![],
+        }
+    }
+
+    pub fn drain(&mut self) -> Vec<UiEvent> {
+        let mut events = vec![];
+        while let Ok(Some(event)) = self.receiver.try_next() {
+            events.push(event);
+        }
+        events
+    }
+}
+
+pub struct Ui {
+    sender: EventSender,
+    listeners: Vec<Listener>,
+}
+
+impl Ui {
+    pub fn html(self, html: &str) -> Result<Self> {
+        browser::draw_ui(html)?;
+        Ok(self)
+    }
+
+    pub fn button(mut self, html: &str, id: &str, action: Action) -> Result<Self> {
+        browser::draw_ui(html)?;
+        let elem = browser::find_html_element_by_id(id)?;
+        self.listeners.push(button::add_click_handler(
+            elem,
+            self.sender.clone(),
+            action,
+        )?);
+        Ok(self)
+    }
+
+    pub fn slider(
+        mut self,
+        html: &str,
+        id: &str,
+        parameter: Parameter,
+        value: f64,
+    ) -> Result<Self> {
+        browser::draw_ui(html)?;
+        let elem = browser::find_html_element_by_id(id)?
+            .dyn_into::<HtmlInputElement>()
+            .map_err(|err| anyhow!("Could not cast into HtmlInputElement {:#?}", err))?;
+        elem.set_value_as_number(value);
+        self.listeners.push(slider::add_input_handler(
+            elem,
+            self.sender.clone(),
+            parameter,
+        )?);
+        Ok(self)
+    }
+
+    pub fn file_input(mut self, html: &str, id: &str, event: fn(File) -> UiEvent) -> Result<Self> {
+        browser::draw_ui(html)?;
+        let elem = browser::find_html_element_by_id(id)?
+            .dyn_into::<HtmlInputElement>()
+            .map_err(|err| anyhow!("Could not cast into HtmlInputElement {:#?}", err))?;
+        self.listeners.push(file_picker::add_change_handler(
+            elem,
+            self.sender.clone(),
+            event,
+        )?);
+        Ok(self)
+    }
+}
@@ -0,0 +1,269 @@
+use crate::constants::{
+    CORNER_MIN_DISTANCE, CORNER_QUALITY, CORNER_WINDOW_SIGMA, FAST_THRESHOLD, HARRIS_K,
+};
+use crate::gray::GrayImage;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Detector {
+    Harris,
+    ShiTomasi,
+    Fast,
+}
+
+impl Detector {
+    pub fn label(self) -> &'static str {
+        match self {
+            Detector::Harris => "Corners: Harris",
+            Detector::ShiTomasi => "Corners: Shi-Tomasi",
+            Detector::Fast => "Corners: FAST",
+        }
+    }
+
+    pub fn detect(self, gray: &GrayImage, max_count: usize) -> Vec<Keypoint> {
+        match self {
+            Detector::Harris => harris(gray, max_count),
+            Detector::ShiTomasi => shi_tomasi(gray, max_count),
+            Detector::Fast => fast(gray, FAST_THRESHOLD, max_count),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keypoint {
+    pub x: f32,
+    pub y: f32,
+    pub response: f32,
+}
+
+pub fn harris(gray: &GrayImage, max_count: usize) -> Vec<Keypoint> {
+    strongest(gray, &harris_responses(gray), max_count)
+}
+
+pub fn harris_responses(gray: &GrayImage) -> Vec<f32> {
+    structure_tensor(gray, |xx, yy, xy| {
+        let trace = xx + yy;
+        xx * yy - xy * xy - HARRIS_K * trace * trace
+    })
+}
+
+// The smaller eigenvalue of the structure tensor.
+pub fn shi_tomasi(gray: &GrayImage, max_count: usize) -> Vec<Keypoint> {
+    let responses = structure_tensor(gray, |xx, yy, xy| {
+        let half_difference = (xx - yy) / 2.0;
+        (xx + yy) / 2.0 - half_difference.hypot(xy)
+    });
+    strongest(gray, &responses, max_count)
+}
+
+// Products of the derivatives summed over a gaussian window, turned into a
+// cornerness by the detector.
+fn structure_tensor(gray: &GrayImage, response: impl Fn(f32, f32, f32) -> f32) -> Vec<f32> {
+    let (gx, gy) = gray.sobel();
+    let (width, height) = (gray.width(), gray.height());
+    let product = |a: &GrayImage, b: &GrayImage| {
+        let values = a.values().iter().zip(b.values()).map(|(a, b)| a * b);
+        GrayImage::new(values.collect(), width, height).blur(CORNER_WINDOW_SIGMA)
+    };
+    let (xx, yy, xy) = (product(&gx, &gx), product(&gy, &gy), product(&gx, &gy));
+    xx.values()
+        .iter()
+        .zip(yy.values())
+        .zip(xy.values())
+        .map(|((&xx, &yy), &xy)| response(xx, yy, xy))
+        .collect()
+}
+
+// Local maxima above a fraction of the best response, taken strongest first
+// while keeping a minimum distance between them.
+fn strongest(gray: &GrayImage, responses: &[f32], max_count: usize) -> Vec<Keypoint> {
+    let best = responses.iter().copied().fold(0.0, f32::max);
+    let candidates = local_maxima(gray, responses, best * CORNER_QUALITY);
+    let min_distance = CORNER_MIN_DISTANCE * CORNER_MIN_DISTANCE;
+    let mut keypoints: Vec<Keypoint> = vec![];
+    for candidate in candidates {
+        if keypoints.len() == max_count {
+            break;
+        }
+        let crowded = keypoints.iter().any(|keypoint| {
+            let (dx, dy) = (keypoint.x - candidate.x, keypoint.y - candidate.y);
+            dx * dx + dy * dy < min_distance
+        });
+        if !crowded {
+            keypoints.push(candidate);
+        }
+    }
+    keypoints
+}
+
+fn local_maxima(gray: &GrayImage, responses: &[f32], threshold: f32) -> Vec<Keypoint> {
+    let (width, height) = (gray.width() as i32, gray.height() as i32);
+    let at = |x: i32, y: i32| {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            f32::MIN
+        } else {
+            responses[(y * width + x) as usize]
+        }
+    };
+    let mut maxima = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            let response = at(x, y);
+            if response <= threshold {
+                continue;
+            }
+            // Ties go to the first pixel in raster order.
+            let is_maximum = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                .filter(|&offset| offset != (0, 0))
+                .all(|(dx, dy)| {
+                    let neighbor = at(x + dx, y + dy);
+                    neighbor < response || (neighbor == response && (dy, dx) > (0, 0))
+                });
+            if is_maximum {
+                maxima.push(Keypoint {
+                    x: x as f32,
+                    y: y as f32,
+                    response,
+                });
+            }
+        }
+    }
+    maxima.sort_by(|a, b| b.response.total_cmp(&a.response));
+    maxima
+}
+
+// Bresenham circle of radius 3 around the candidate pixel.
+const CIRCLE: [(i32, i32); 16] = [
+    (0, -3),
+    (1, -3),
+    (2, -2),
+    (3, -1),
+    (3, 0),
+    (3, 1),
+    (2, 2),
+    (1, 3),
+    (0, 3),
+    (-1, 3),
+    (-2, 2),
+    (-3, 1),
+    (-3, 0),
+    (-3, -1),
+    (-2, -2),
+    (-1, -3),
+];
+const ARC: usize = 9;
+
+// FAST-9: a pixel is a corner when 9 contiguous pixels of the circle are all
+// brighter or all darker than it by the threshold. The score is the summed
+// excess over the threshold of the best arc, used to suppress neighbours.
+pub fn fast(gray: &GrayImage, threshold: f32, max_count: usize) -> Vec<Keypoint> {
+    let (width, height) = (gray.width() as i32, gray.height() as i32);
+    let mut scores = vec![0.0; (width * height) as usize];
+    for y in 3..height - 3 {
+        for x in 3..width - 3 {
+            let center = gray.get(x, y);
+            let ring = CIRCLE.map(|(dx, dy)| gray.get(x + dx, y + dy) - center);
+            // Any arc of 9 contains at least two of the compass points.
+            let compass = [ring[0], ring[4], ring[8], ring[12]];
+            let bright = compass.iter().filter(|&&d| d > threshold).count();
+            let dark = compass.iter().filter(|&&d| d < -threshold).count();
+            if bright < 2 && dark < 2 {
+                continue;
+            }
+            let score = arc_score(&ring, threshold).max(arc_score(&ring.map(|d| -d), threshold));
+            scores[(y * width + x) as usize] = score;
+        }
+    }
+    local_maxima(gray, &scores, 0.0)
+        .into_iter()
+        .take(max_count)
+        .collect()
+}
+
+fn arc_score(ring: &[f32; 16], threshold: f32) -> f32 {
+    let mut best = 0.0;
+    let (mut run, mut excess) = (0, 0.0);
+    // Going around twice catches arcs that wrap past the first pixel.
+    for i in 0..ring.len() + ARC - 1 {
+        let difference = ring[i % ring.len()];
+        if difference > threshold {
+            run += 1;
+            excess += difference - threshold;
+            if run >= ARC {
+                best = f32::max(best, excess);
+            }
+        } else {
+            run = 0;
+            excess = 0.0;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 64;
+    const CORNERS: [(f32, f32); 4] = [(20.0, 20.0), (43.0, 20.0), (20.0, 43.0), (43.0, 43.0)];
+
+    fn drawn(bright: impl Fn(u32, u32) -> bool) -> GrayImage {
+        let values = (0..SIZE)
+            .flat_map(|y| (0..SIZE).map(move |x| (x, y)))
+            .map(|(x, y)| if bright(x, y) { 200.0 } else { 40.0 })
+            .collect();
+        GrayImage::new(values, SIZE, SIZE)
+    }
+
+    fn square() -> GrayImage {
+        drawn(|x, y| (20..44).contains(&x) && (20..44).contains(&y))
+    }
+
+    // Each corner of the square is claimed by exactly one keypoint nearby.
+    fn assert_at_corners(keypoints: &[Keypoint]) {
+        assert_eq!(keypoints.len(), 4, "{:?}", keypoints);
+        for (x, y) in CORNERS {
+            let near = keypoints
+                .iter()
+                .filter(|keypoint| (keypoint.x - x).abs() <= 2.0 && (keypoint.y - y).abs() <= 2.0)
+                .count();
+            assert_eq!(near, 1, "corner ({}, {}) in {:?}", x, y, keypoints);
+        }
+    }
+
+    #[test]
+    fn every_detector_finds_the_corners_of_a_square() {
+        for detector in [Detector::Harris, Detector::ShiTomasi, Detector::Fast] {
+            assert_at_corners(&detector.detect(&square(), 10));
+        }
+    }
+
+    #[test]
+    fn flat_image_and_straight_edge_have_no_corners() {
+        let edge = drawn(|x, _| x >= 32);
+        for image in [drawn(|_, _| false), edge] {
+            for detector in [Detector::Harris, Detector::ShiTomasi, Detector::Fast] {
+                assert!(detector.detect(&image, 10).is_empty(), "{:?}", detector);
+            }
+        }
+    }
+
+    #[test]
+    fn count_is_capped_keeping_the_strongest() {
+        let all = harris(&square(), 10);
+        let capped = harris(&square(), 2);
+        assert_eq!(capped.len(), 2);
+        assert!(capped
+            .iter()
+            .all(|keypoint| keypoint.response >= all[3].response));
+        assert!(capped[0].response >= capped[1].response);
+    }
+
+    #[test]
+    fn fast_ignores_contrast_below_the_threshold() {
+        // The square is 16 levels brighter than its surroundings.
+        let faint = square().values().iter().map(|value| value / 10.0).collect();
+        let faint = GrayImage::new(faint, SIZE, SIZE);
+        assert!(fast(&faint, FAST_THRESHOLD, 10).is_empty());
+    }
+}
@@ -0,0 +1,24 @@
+use crate::events::{self, EventSender, Listener, UiEvent};
+use anyhow::Result;
+use wasm_bindgen::JsCast;
+use web_sys::{DragEvent, HtmlElement};
+
+// The browser only allows dropping on elements that cancel `dragover`.
+pub fn add_drop_handler(elem: &HtmlElement, sender: EventSender) -> Result<Vec<Listener>> {
+    let on_dragover = Listener::new(elem, "dragover", |event| event.prevent_default())?;
+    let on_drop = Listener::new(elem, "drop", move |event| {
+        event.prevent_default();
+        let file = event
+            .dyn_ref::<DragEvent>()
+            .and_then(|drag_event| drag_event.data_transfer())
+            .and_then(|data_transfer| data_transfer.files())
+            .and_then(|files| files.get(0));
+        match file {
+            Some(file) => events::send(&sender, UiEvent::FileDrop(file)),
+            None => {
+                error!("Dropped data does not contain a file");
+            }
+        }
+    })?;
+    Ok(vec![on_dragover, on_drop])
+}
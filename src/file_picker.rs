@@ -0,0 +1,28 @@
+use crate::events::{self, EventSender, Listener, UiEvent};
+use anyhow::Result;
+use web_sys::{File, HtmlInputElement};
+
+// Every picked file is sent on its own, so inputs that accept several files
+// need nothing else. The value is cleared after each pick so choosing the same
+// file again still fires `change`.
+pub fn add_change_handler(
+    elem: HtmlInputElement,
+    sender: EventSender,
+    event: fn(File) -> UiEvent,
+) -> Result<Listener> {
+    let input = elem.clone();
+    Listener::new(&elem, "change", move |_event| {
+        let files = input.files();
+        match files.as_ref().filter(|files| files.length() > 0) {
+            Some(files) => {
+                for file in (0..files.length()).filter_map(|index| files.get(index)) {
+                    events::send(&sender, event(file));
+                }
+            }
+            None => {
+                error!("No file was picked");
+            }
+        }
+        input.set_value("");
+    })
+}
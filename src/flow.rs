@@ -0,0 +1,529 @@
+use crate::constants::{
+    FARNEBACK_ITERATIONS, FARNEBACK_POLY_RADIUS, FARNEBACK_POLY_SIGMA, FARNEBACK_WINDOW_SIGMA,
+    FLOW_LEVELS, FLOW_MIN_EIGENVALUE, FLOW_MIN_SIZE, LUCAS_KANADE_ITERATIONS, LUCAS_KANADE_RADIUS,
+};
+use crate::gray::GrayImage;
+use crate::image::RawImage;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowMode {
+    Off,
+    Sparse,
+    Dense,
+}
+
+impl FlowMode {
+    pub fn next(self) -> Self {
+        match self {
+            FlowMode::Off => FlowMode::Sparse,
+            FlowMode::Sparse => FlowMode::Dense,
+            FlowMode::Dense => FlowMode::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FlowMode::Off => "Flow: off",
+            FlowMode::Sparse => "Flow: tracks",
+            FlowMode::Dense => "Flow: dense",
+        }
+    }
+}
+
+// Finest level first. Levels stop before the image gets too small to carry
+// any structure.
+pub fn pyramid(gray: &GrayImage, levels: usize) -> Vec<GrayImage> {
+    let mut pyramid = vec![gray.clone()];
+    while pyramid.len() < levels {
+        let last = &pyramid[pyramid.len() - 1];
+        if last.width().min(last.height()) / 2 < FLOW_MIN_SIZE {
+            break;
+        }
+        pyramid.push(last.downsample());
+    }
+    pyramid
+}
+
+// Pyramidal Lucas–Kanade as described by Bouguet: the displacement found at
+// each level is the starting guess of the next finer one. Points whose window
+// has too little texture, or that leave the frame, are lost.
+pub fn lucas_kanade(
+    previous: &GrayImage,
+    current: &GrayImage,
+    points: &[(f32, f32)],
+) -> Vec<Option<(f32, f32)>> {
+    let previous = pyramid(previous, FLOW_LEVELS);
+    let current = pyramid(current, previous.len());
+    let gradients: Vec<_> = previous.iter().map(GrayImage::sobel).collect();
+    points
+        .iter()
+        .map(|&point| {
+            let end = track(&previous, &current, &gradients, point)?;
+            let inside = end.0 >= 0.0
+                && end.1 >= 0.0
+                && end.0 <= (current[0].width() - 1) as f32
+                && end.1 <= (current[0].height() - 1) as f32;
+            inside.then_some(end)
+        })
+        .collect()
+}
+
+fn track(
+    previous: &[GrayImage],
+    current: &[GrayImage],
+    gradients: &[(GrayImage, GrayImage)],
+    (x, y): (f32, f32),
+) -> Option<(f32, f32)> {
+    let radius = LUCAS_KANADE_RADIUS;
+    let area = ((2 * radius + 1) * (2 * radius + 1)) as f32;
+    let mut guess = (0.0, 0.0);
+    for level in (0..previous.len()).rev() {
+        let scale = (1 << level) as f32;
+        let (px, py) = (x / scale, y / scale);
+        let (gx, gy) = &gradients[level];
+        // Sobel weighs the derivative by 8.
+        let window: Vec<(f32, f32, f32, f32, f32)> = (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx as f32, dy as f32)))
+            .map(|(dx, dy)| {
+                let (sx, sy) = (px + dx, py + dy);
+                let value = previous[level].sample(sx, sy);
+                (
+                    dx,
+                    dy,
+                    value,
+                    gx.sample(sx, sy) / 8.0,
+                    gy.sample(sx, sy) / 8.0,
+                )
+            })
+            .collect();
+        let (mut gxx, mut gyy, mut gxy) = (0.0, 0.0, 0.0);
+        for &(_, _, _, ix, iy) in &window {
+            gxx += ix * ix;
+            gyy += iy * iy;
+            gxy += ix * iy;
+        }
+        let smallest = (gxx + gyy) / 2.0 - ((gxx - gyy) / 2.0).hypot(gxy);
+        if smallest / area < FLOW_MIN_EIGENVALUE {
+            return None;
+        }
+        let determinant = gxx * gyy - gxy * gxy;
+
+        let mut flow = (0.0, 0.0);
+        for _ in 0..LUCAS_KANADE_ITERATIONS {
+            let (mut bx, mut by) = (0.0, 0.0);
+            for &(dx, dy, value, ix, iy) in &window {
+                let moved =
+                    current[level].sample(px + dx + guess.0 + flow.0, py + dy + guess.1 + flow.1);
+                let difference = value - moved;
+                bx += difference * ix;
+                by += difference * iy;
+            }
+            let step = (
+                (gyy * bx - gxy * by) / determinant,
+                (gxx * by - gxy * bx) / determinant,
+            );
+            flow = (flow.0 + step.0, flow.1 + step.1);
+            if step.0.hypot(step.1) < 0.01 {
+                break;
+            }
+        }
+        guess = (guess.0 + flow.0, guess.1 + flow.1);
+        if level > 0 {
+            guess = (guess.0 * 2.0, guess.1 * 2.0);
+        }
+    }
+    Some((x + guess.0, y + guess.1))
+}
+
+// Displacement of every pixel from the previous frame to the current one.
+#[derive(Clone)]
+pub struct FlowField {
+    flow: Vec<(f32, f32)>,
+    width: u32,
+    height: u32,
+}
+
+impl FlowField {
+    fn zero(width: u32, height: u32) -> Self {
+        FlowField {
+            flow: vec![(0.0, 0.0); (width * height) as usize],
+            width,
+            height,
+        }
+    }
+
+    pub fn at(&self, x: u32, y: u32) -> (f32, f32) {
+        self.flow[(y * self.width + x) as usize]
+    }
+
+    pub fn mean_magnitude(&self) -> f32 {
+        let total: f32 = self.flow.iter().map(|&(dx, dy)| dx.hypot(dy)).sum();
+        total / self.flow.len().max(1) as f32
+    }
+
+    // The next finer level has twice the size, and so twice the motion.
+    fn upscale(&self, width: u32, height: u32) -> Self {
+        let mut field = FlowField::zero(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let (dx, dy) = self.at((x / 2).min(self.width - 1), (y / 2).min(self.height - 1));
+                field.flow[(y * width + x) as usize] = (dx * 2.0, dy * 2.0);
+            }
+        }
+        field
+    }
+
+    // The usual color wheel: the hue is the direction and the brightness the
+    // magnitude relative to the fastest pixel.
+    pub fn to_colors(&self) -> RawImage {
+        let fastest = self
+            .flow
+            .iter()
+            .map(|&(dx, dy)| dx.hypot(dy))
+            .fold(f32::EPSILON, f32::max);
+        let pixels = self
+            .flow
+            .iter()
+            .flat_map(|&(dx, dy)| {
+                let hue = dy.atan2(dx).to_degrees().rem_euclid(360.0);
+                let [r, g, b] = hsv_to_rgb(hue, 1.0, dx.hypot(dy) / fastest);
+                [r, g, b, 255]
+            })
+            .collect();
+        RawImage::from_raw(pixels, self.width, self.height)
+    }
+
+    // One arrow per cell of the grid, skipping the ones that barely move and
+    // lengthening the others so that they can be seen.
+    pub fn arrows(&self, step: u32, min_length: f32, scale: f32) -> Vec<((f32, f32), (f32, f32))> {
+        (step / 2..self.height)
+            .step_by(step as usize)
+            .flat_map(|y| {
+                (step / 2..self.width)
+                    .step_by(step as usize)
+                    .map(move |x| (x, y))
+            })
+            .filter_map(|(x, y)| {
+                let (dx, dy) = self.at(x, y);
+                let start = (x as f32, y as f32);
+                let end = (start.0 + dx * scale, start.1 + dy * scale);
+                (dx.hypot(dy) >= min_length).then_some((start, end))
+            })
+            .collect()
+    }
+}
+
+fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> [u8; 3] {
+    let chroma = value * saturation;
+    let sector = hue / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let offset = value - chroma;
+    [r, g, b].map(|channel| ((channel + offset) * 255.0).round().clamp(0.0, 255.0) as u8)
+}
+
+// Farnebäck's two-frame motion estimation: both frames are approximated
+// around every pixel by a quadratic polynomial, and the displacement is the
+// one that best explains how the polynomials shifted, averaged over a
+// gaussian neighbourhood and refined from coarse to fine.
+pub fn farneback(previous: &GrayImage, current: &GrayImage) -> FlowField {
+    let previous = pyramid(previous, FLOW_LEVELS);
+    let current = pyramid(current, previous.len());
+    let mut field: Option<FlowField> = None;
+    for (first, second) in previous.iter().zip(&current).rev() {
+        let (width, height) = (first.width(), first.height());
+        let mut flow = match field {
+            Some(coarse) => coarse.upscale(width, height),
+            None => FlowField::zero(width, height),
+        };
+        let (first, second) = (expand(first), expand(second));
+        for _ in 0..FARNEBACK_ITERATIONS {
+            flow = refine(&first, &second, &flow);
+        }
+        field = Some(flow);
+    }
+    field.unwrap_or_else(|| FlowField::zero(0, 0))
+}
+
+// Coefficients of c + bx x + by y + axx x² + ayy y² + axy xy fitted around
+// each pixel with gaussian weights.
+struct Expansion {
+    coefficients: Vec<[f32; 6]>,
+    width: u32,
+    height: u32,
+}
+
+impl Expansion {
+    // Outside the frame there is nothing to compare against.
+    fn sample(&self, x: f32, y: f32) -> Option<[f32; 6]> {
+        let (width, height) = (self.width as f32, self.height as f32);
+        if x < 0.0 || y < 0.0 || x > width - 1.0 || y > height - 1.0 {
+            return None;
+        }
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let at = |x: u32, y: u32| &self.coefficients[(y * self.width + x) as usize];
+        let mut sample = [0.0; 6];
+        for (i, value) in sample.iter_mut().enumerate() {
+            let top = at(x0, y0)[i] * (1.0 - fx) + at(x1, y0)[i] * fx;
+            let bottom = at(x0, y1)[i] * (1.0 - fx) + at(x1, y1)[i] * fx;
+            *value = top * (1.0 - fy) + bottom * fy;
+        }
+        Some(sample)
+    }
+}
+
+// The weighted least squares fit has the same normal matrix everywhere, so
+// the coefficients are its inverse applied to weighted moments of the
+// neighbourhood, which are separable.
+fn expand(gray: &GrayImage) -> Expansion {
+    let radius = FARNEBACK_POLY_RADIUS;
+    let sigma = FARNEBACK_POLY_SIGMA;
+    let weights: Vec<(f32, f32)> = (-radius..=radius)
+        .map(|i| {
+            let i = i as f32;
+            (i, (-i * i / (2.0 * sigma * sigma)).exp())
+        })
+        .collect();
+
+    let basis = |x: f64, y: f64| [1.0, x, y, x * x, y * y, x * y];
+    let mut normal = [[0.0f64; 6]; 6];
+    for &(y, wy) in &weights {
+        for &(x, wx) in &weights {
+            let terms = basis(x as f64, y as f64);
+            for i in 0..6 {
+                for j in 0..6 {
+                    normal[i][j] += (wx * wy) as f64 * terms[i] * terms[j];
+                }
+            }
+        }
+    }
+    let inverse = invert(normal);
+
+    let (width, height) = (gray.width() as i32, gray.height() as i32);
+    // Horizontal pass with the weights times 1, x and x².
+    let mut rows = vec![[0.0f32; 3]; (width * height) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            let row = &mut rows[(y * width + x) as usize];
+            for (offset, &(i, weight)) in (-radius..).zip(&weights) {
+                let value = weight * gray.get(x + offset, y);
+                row[0] += value;
+                row[1] += value * i;
+                row[2] += value * i * i;
+            }
+        }
+    }
+    let row_at = |x: i32, y: i32| rows[(y.clamp(0, height - 1) * width + x) as usize];
+    let mut coefficients = Vec::with_capacity(rows.len());
+    for y in 0..height {
+        for x in 0..width {
+            // m00, m10, m01, m20, m02, m11 in the order of the basis.
+            let mut moments = [0.0f64; 6];
+            for (offset, &(j, weight)) in (-radius..).zip(&weights) {
+                let row = row_at(x, y + offset);
+                let (w, wj, wjj) = (weight, weight * j, weight * j * j);
+                moments[0] += (w * row[0]) as f64;
+                moments[1] += (w * row[1]) as f64;
+                moments[2] += (wj * row[0]) as f64;
+                moments[3] += (w * row[2]) as f64;
+                moments[4] += (wjj * row[0]) as f64;
+                moments[5] += (wj * row[1]) as f64;
+            }
+            let mut fit = [0.0f32; 6];
+            for (i, value) in fit.iter_mut().enumerate() {
+                *value = (0..6).map(|k| inverse[i][k] * moments[k]).sum::<f64>() as f32;
+            }
+            coefficients.push(fit);
+        }
+    }
+    Expansion {
+        coefficients,
+        width: gray.width(),
+        height: gray.height(),
+    }
+}
+
+// Gauss–Jordan elimination with partial pivoting on a well conditioned
+// matrix.
+fn invert(mut matrix: [[f64; 6]; 6]) -> [[f64; 6]; 6] {
+    let mut inverse = [[0.0; 6]; 6];
+    for (i, row) in inverse.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    for column in 0..6 {
+        let pivot = (column..6)
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))
+            .unwrap_or(column);
+        matrix.swap(column, pivot);
+        inverse.swap(column, pivot);
+        let scale = matrix[column][column];
+        for k in 0..6 {
+            matrix[column][k] /= scale;
+            inverse[column][k] /= scale;
+        }
+        for row in 0..6 {
+            if row != column {
+                let factor = matrix[row][column];
+                let (pivot_row, pivot_inverse) = (matrix[column], inverse[column]);
+                for k in 0..6 {
+                    matrix[row][k] -= factor * pivot_row[k];
+                    inverse[row][k] -= factor * pivot_inverse[k];
+                }
+            }
+        }
+    }
+    inverse
+}
+
+// With A the mean of both quadratic parts and Δb how much the linear part
+// moved beyond the current guess, the displacement solves A d = Δb in the
+// least squares sense over the window.
+fn refine(first: &Expansion, second: &Expansion, flow: &FlowField) -> FlowField {
+    let (width, height) = (first.width, first.height);
+    let mut products = [(); 5].map(|_| Vec::with_capacity((width * height) as usize));
+    for y in 0..height {
+        for x in 0..width {
+            let r1 = first.coefficients[(y * width + x) as usize];
+            let (dx, dy) = flow.at(x, y);
+            let r2 = second.sample(x as f32 + dx, y as f32 + dy).unwrap_or(r1);
+            let (a11, a22, a12) = (
+                (r1[3] + r2[3]) / 2.0,
+                (r1[4] + r2[4]) / 2.0,
+                (r1[5] + r2[5]) / 4.0,
+            );
+            let b1 = -0.5 * (r2[1] - r1[1]) + a11 * dx + a12 * dy;
+            let b2 = -0.5 * (r2[2] - r1[2]) + a12 * dx + a22 * dy;
+            let values = [
+                a11 * a11 + a12 * a12,
+                a12 * (a11 + a22),
+                a12 * a12 + a22 * a22,
+                a11 * b1 + a12 * b2,
+                a12 * b1 + a22 * b2,
+            ];
+            for (product, value) in products.iter_mut().zip(values) {
+                product.push(value);
+            }
+        }
+    }
+    let [g11, g12, g22, h1, h2] =
+        products.map(|values| GrayImage::new(values, width, height).blur(FARNEBACK_WINDOW_SIGMA));
+
+    let mut refined = FlowField::zero(width, height);
+    for (i, displacement) in refined.flow.iter_mut().enumerate() {
+        let (g11, g12, g22) = (g11.values()[i], g12.values()[i], g22.values()[i]);
+        let (h1, h2) = (h1.values()[i], h2.values()[i]);
+        // The small bias keeps flat regions, where nothing can be told, still.
+        let determinant = g11 * g22 - g12 * g12 + 1e-3;
+        *displacement = (
+            (g22 * h1 - g12 * h2) / determinant,
+            (g11 * h2 - g12 * h1) / determinant,
+        );
+    }
+    refined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 96;
+    const HEIGHT: u32 = 72;
+    const SHIFT: (f32, f32) = (2.5, -1.5);
+
+    // Smooth texture from a few waves, seen with the content moved by an
+    // offset.
+    fn waves(offset: (f32, f32)) -> GrayImage {
+        let values = (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| (x as f32 - offset.0, y as f32 - offset.1)))
+            .map(|(x, y)| {
+                128.0
+                    + 40.0 * (0.21 * x + 0.05 * y).sin()
+                    + 30.0 * (0.17 * y - 0.08 * x).cos()
+                    + 20.0 * (0.11 * (x + y)).sin()
+            })
+            .collect();
+        GrayImage::new(values, WIDTH, HEIGHT)
+    }
+
+    #[test]
+    fn pyramid_stops_before_the_minimum_size() {
+        let levels = pyramid(&waves((0.0, 0.0)), 10);
+        let sizes: Vec<_> = levels
+            .iter()
+            .map(|level| (level.width(), level.height()))
+            .collect();
+        assert_eq!(sizes, [(96, 72), (48, 36), (24, 18)]);
+    }
+
+    #[test]
+    fn tracks_follow_a_known_translation() {
+        let points = [(30.0, 30.0), (48.0, 36.0), (60.0, 44.0)];
+        let tracks = lucas_kanade(&waves((0.0, 0.0)), &waves(SHIFT), &points);
+        for (&(x, y), track) in points.iter().zip(tracks) {
+            let (tx, ty) = track.unwrap();
+            assert!((tx - x - SHIFT.0).abs() < 0.1, "{:?}", (tx, ty));
+            assert!((ty - y - SHIFT.1).abs() < 0.1, "{:?}", (tx, ty));
+        }
+    }
+
+    #[test]
+    fn untextured_and_departing_points_are_lost() {
+        let flat = GrayImage::new(vec![100.0; (WIDTH * HEIGHT) as usize], WIDTH, HEIGHT);
+        assert_eq!(lucas_kanade(&flat, &flat, &[(48.0, 36.0)]), [None]);
+
+        // The same motion is followed away from the border.
+        let right = (4.0, 0.0);
+        let tracks = lucas_kanade(
+            &waves((0.0, 0.0)),
+            &waves(right),
+            &[(80.0, 36.0), (92.0, 36.0)],
+        );
+        let (x, y) = tracks[0].unwrap();
+        assert!((x - 84.0).abs() < 0.1 && (y - 36.0).abs() < 0.1);
+        assert_eq!(tracks[1], None);
+    }
+
+    #[test]
+    fn dense_flow_matches_a_known_translation() {
+        let field = farneback(&waves((0.0, 0.0)), &waves(SHIFT));
+        for y in (16..HEIGHT - 16).step_by(8) {
+            for x in (16..WIDTH - 16).step_by(8) {
+                let (dx, dy) = field.at(x, y);
+                assert!(
+                    (dx - SHIFT.0).abs() < 0.25,
+                    "{:?} at {:?}",
+                    (dx, dy),
+                    (x, y)
+                );
+                assert!(
+                    (dy - SHIFT.1).abs() < 0.25,
+                    "{:?} at {:?}",
+                    (dx, dy),
+                    (x, y)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn still_frames_have_no_dense_flow() {
+        let frame = waves((0.0, 0.0));
+        let field = farneback(&frame, &frame);
+        assert!(field.mean_magnitude() < 1e-3);
+        assert!(field.arrows(16, 0.5, 3.0).is_empty());
+        assert!(field
+            .to_colors()
+            .pixels()
+            .chunks_exact(4)
+            .all(|pixel| pixel == [0, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::constants::{
+    SOURCE_OBJECT_COLOR, SOURCE_OBJECT_FRACTION, SOURCE_OBJECT_SPEED, SOURCE_SEED,
+    SOURCE_SHAKE_ANGLE, SOURCE_SHAKE_DAMPING, SOURCE_SHAKE_SHIFT,
+};
+use crate::homography;
+use crate::image::RawImage;
+use crate::random::Xorshift;
+use crate::stabilize::Motion;
+
+// Where each simulation step takes its frame from. The still is processed
+// over and over, the other sources play a sequence made from it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceMode {
+    Still,
+    // A fixed camera watching an object cross the scene.
+    Moving,
+    // The same scene filmed by a shaking hand.
+    Handheld,
+}
+
+impl SourceMode {
+    pub fn next(self) -> Self {
+        match self {
+            SourceMode::Still => SourceMode::Moving,
+            SourceMode::Moving => SourceMode::Handheld,
+            SourceMode::Handheld => SourceMode::Still,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SourceMode::Still => "Source: still",
+            SourceMode::Moving => "Source: moving",
+            SourceMode::Handheld => "Source: handheld",
+        }
+    }
+}
+
+// Synthetic footage of the loaded image. The image the sequence starts from is
+// kept so that going back to the still restores it.
+pub struct FrameSource {
+    mode: SourceMode,
+    still: RawImage,
+    time: u32,
+    shake: Motion,
+    random: Xorshift,
+}
+
+impl FrameSource {
+    pub fn new() -> Self {
+        FrameSource {
+            mode: SourceMode::Still,
+            still: RawImage::new(),
+            time: 0,
+            shake: Motion::default(),
+            random: Xorshift::new(SOURCE_SEED),
+        }
+    }
+
+    pub fn mode(&self) -> SourceMode {
+        self.mode
+    }
+
+    pub fn next_mode(&mut self) -> SourceMode {
+        self.mode = self.mode.next();
+        self.rewind();
+        self.mode
+    }
+
+    // The image the sequence was made from, if one has started.
+    pub fn take_still(&mut self) -> Option<RawImage> {
+        self.rewind();
+        let still = std::mem::replace(&mut self.still, RawImage::new());
+        (!still.is_empty()).then_some(still)
+    }
+
+    // The first frame asked for takes the current image as the scene.
+    pub fn next_frame(&mut self, current: &RawImage) -> Option<RawImage> {
+        if self.mode == SourceMode::Still {
+            return None;
+        }
+        if self.still.is_empty() {
+            if current.is_empty() {
+                return None;
+            }
+            self.still = current.clone();
+        }
+        self.time += 1;
+        let mut frame = self.still.clone();
+        draw_object(&mut frame, self.time);
+        if self.mode == SourceMode::Handheld {
+            let mut step = |scale: f64| (2.0 * self.random.next_f64() - 1.0) * scale;
+            let (dx, dy, angle) = (
+                step(SOURCE_SHAKE_SHIFT),
+                step(SOURCE_SHAKE_SHIFT),
+                step(SOURCE_SHAKE_ANGLE),
+            );
+            self.shake = Motion {
+                dx: self.shake.dx * SOURCE_SHAKE_DAMPING + dx,
+                dy: self.shake.dy * SOURCE_SHAKE_DAMPING + dy,
+                angle: self.shake.angle * SOURCE_SHAKE_DAMPING + angle,
+            };
+            let (width, height) = (frame.width(), frame.height());
+            let center = (width as f64 / 2.0, height as f64 / 2.0);
+            frame = homography::warp(&frame, &self.shake.homography(center, 1.0), width, height);
+        }
+        Some(frame)
+    }
+
+    fn rewind(&mut self) {
+        self.time = 0;
+        self.shake = Motion::default();
+        self.random = Xorshift::new(SOURCE_SEED);
+    }
+}
+
+// A square on a Lissajous path, so that it keeps changing direction without
+// ever leaving the frame.
+fn draw_object(frame: &mut RawImage, time: u32) {
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    let side = ((width.min(height) as f64 * SOURCE_OBJECT_FRACTION) as usize).max(1);
+    let phase = time as f64 * SOURCE_OBJECT_SPEED;
+    let travel =
+        |room: usize, phase: f64| ((room - side) as f64 * (0.5 + 0.5 * phase.sin())) as usize;
+    let (left, top) = (
+        travel(width.max(side), phase),
+        travel(height.max(side), 0.7 * phase + 1.0),
+    );
+    let pixels = frame.pixels_mut();
+    for y in top..(top + side).min(height) {
+        for x in left..(left + side).min(width) {
+            let index = 4 * (y * width + x);
+            pixels[index..index + 4].copy_from_slice(&SOURCE_OBJECT_COLOR);
+        }
+    }
+}
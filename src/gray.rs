@@ -0,0 +1,120 @@
+use crate::binary::luma;
+use crate::image::RawImage;
+
+// Single channel image in the 0-255 range, kept as floats so that filters
+// can be chained without rounding in between.
+#[derive(Clone)]
+pub struct GrayImage {
+    values: Vec<f32>,
+    width: u32,
+    height: u32,
+}
+
+impl GrayImage {
+    pub fn new(values: Vec<f32>, width: u32, height: u32) -> Self {
+        GrayImage {
+            values,
+            width,
+            height,
+        }
+    }
+
+    pub fn from_raw(image: &RawImage) -> Self {
+        let values = image
+            .pixels()
+            .chunks_exact(4)
+            .map(|pixel| luma(pixel) as f32)
+            .collect();
+        GrayImage::new(values, image.width(), image.height())
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    // Coordinates outside the image are clamped to its border.
+    pub fn get(&self, x: i32, y: i32) -> f32 {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+        self.values[y * self.width as usize + x]
+    }
+
+    // Bilinear interpolation between the four nearest pixels.
+    pub fn sample(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        let top = self.get(x0, y0) * (1.0 - fx) + self.get(x0 + 1, y0) * fx;
+        let bottom = self.get(x0, y0 + 1) * (1.0 - fx) + self.get(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    // Half the size, blurred first so that the dropped pixels don't alias.
+    pub fn downsample(&self) -> Self {
+        let smooth = self.blur(1.0);
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let values = (0..height as i32)
+            .flat_map(|y| (0..width as i32).map(move |x| (x, y)))
+            .map(|(x, y)| smooth.get(2 * x, 2 * y))
+            .collect();
+        GrayImage::new(values, width, height)
+    }
+
+    pub fn blur(&self, sigma: f32) -> Self {
+        if sigma <= 0.0 {
+            return self.clone();
+        }
+        let radius = (3.0 * sigma).ceil() as i32;
+        let mut kernel: Vec<f32> = (-radius..=radius)
+            .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let total: f32 = kernel.iter().sum();
+        kernel.iter_mut().for_each(|weight| *weight /= total);
+
+        let horizontal = self.convolve(|image, x, y| {
+            (-radius..=radius)
+                .zip(&kernel)
+                .map(|(i, weight)| weight * image.get(x + i, y))
+                .sum()
+        });
+        horizontal.convolve(|image, x, y| {
+            (-radius..=radius)
+                .zip(&kernel)
+                .map(|(i, weight)| weight * image.get(x, y + i))
+                .sum()
+        })
+    }
+
+    // Horizontal and vertical derivatives.
+    pub fn sobel(&self) -> (Self, Self) {
+        let gx = self.convolve(|image, x, y| {
+            image.get(x + 1, y - 1) + 2.0 * image.get(x + 1, y) + image.get(x + 1, y + 1)
+                - image.get(x - 1, y - 1)
+                - 2.0 * image.get(x - 1, y)
+                - image.get(x - 1, y + 1)
+        });
+        let gy = self.convolve(|image, x, y| {
+            image.get(x - 1, y + 1) + 2.0 * image.get(x, y + 1) + image.get(x + 1, y + 1)
+                - image.get(x - 1, y - 1)
+                - 2.0 * image.get(x, y - 1)
+                - image.get(x + 1, y - 1)
+        });
+        (gx, gy)
+    }
+
+    fn convolve(&self, filter: impl Fn(&Self, i32, i32) -> f32) -> Self {
+        let values = (0..self.height as i32)
+            .flat_map(|y| (0..self.width as i32).map(move |x| (x, y)))
+            .map(|(x, y)| filter(self, x, y))
+            .collect();
+        GrayImage::new(values, self.width, self.height)
+    }
+}
@@ -0,0 +1,375 @@
+use crate::constants::RANSAC_SEED;
+use crate::image::RawImage;
+use crate::random::Xorshift;
+
+pub type Correspondence = ((f64, f64), (f64, f64));
+
+// Projective transform in row-major order, mapping source points to
+// destination points.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Homography(pub [f64; 9]);
+
+impl Homography {
+    pub fn identity() -> Self {
+        Homography([1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0])
+    }
+
+    pub fn apply(&self, (x, y): (f64, f64)) -> Option<(f64, f64)> {
+        let h = &self.0;
+        let w = h[6] * x + h[7] * y + h[8];
+        if w.abs() < f64::EPSILON {
+            return None;
+        }
+        Some((
+            (h[0] * x + h[1] * y + h[2]) / w,
+            (h[3] * x + h[4] * y + h[5]) / w,
+        ))
+    }
+
+    pub fn then(&self, next: &Homography) -> Homography {
+        let (a, b) = (&next.0, &self.0);
+        let mut product = [0.0; 9];
+        for row in 0..3 {
+            for column in 0..3 {
+                product[row * 3 + column] =
+                    (0..3).map(|k| a[row * 3 + k] * b[k * 3 + column]).sum();
+            }
+        }
+        Homography(product)
+    }
+
+    pub fn inverse(&self) -> Option<Homography> {
+        let h = &self.0;
+        let cofactors = [
+            h[4] * h[8] - h[5] * h[7],
+            h[2] * h[7] - h[1] * h[8],
+            h[1] * h[5] - h[2] * h[4],
+            h[5] * h[6] - h[3] * h[8],
+            h[0] * h[8] - h[2] * h[6],
+            h[2] * h[3] - h[0] * h[5],
+            h[3] * h[7] - h[4] * h[6],
+            h[1] * h[6] - h[0] * h[7],
+            h[0] * h[4] - h[1] * h[3],
+        ];
+        let determinant = h[0] * cofactors[0] + h[1] * cofactors[3] + h[2] * cofactors[6];
+        if determinant.abs() < f64::EPSILON {
+            return None;
+        }
+        Some(Homography(cofactors.map(|value| value / determinant)))
+    }
+}
+
+// Hartley's normalization: the points are centered and scaled to an average
+// distance of sqrt(2), which keeps the DLT system well conditioned.
+fn normalization(points: impl Iterator<Item = (f64, f64)> + Clone) -> Homography {
+    let count = points.clone().count().max(1) as f64;
+    let (sum_x, sum_y) = points
+        .clone()
+        .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+    let (cx, cy) = (sum_x / count, sum_y / count);
+    let spread = points.map(|(x, y)| (x - cx).hypot(y - cy)).sum::<f64>() / count;
+    let scale = if spread > 0.0 {
+        std::f64::consts::SQRT_2 / spread
+    } else {
+        1.0
+    };
+    Homography([
+        scale,
+        0.0,
+        -scale * cx,
+        0.0,
+        scale,
+        -scale * cy,
+        0.0,
+        0.0,
+        1.0,
+    ])
+}
+
+// Normalized direct linear transform: the homography is the null vector of
+// the stacked constraints, taken as the eigenvector of AᵀA with the smallest
+// eigenvalue.
+pub fn from_correspondences(correspondences: &[Correspondence]) -> Option<Homography> {
+    if correspondences.len() < 4 {
+        return None;
+    }
+    let source = normalization(correspondences.iter().map(|&(a, _)| a));
+    let destination = normalization(correspondences.iter().map(|&(_, b)| b));
+
+    let mut normal = [[0.0; 9]; 9];
+    for &(a, b) in correspondences {
+        let (x, y) = source.apply(a)?;
+        let (u, v) = destination.apply(b)?;
+        let rows = [
+            [-x, -y, -1.0, 0.0, 0.0, 0.0, u * x, u * y, u],
+            [0.0, 0.0, 0.0, -x, -y, -1.0, v * x, v * y, v],
+        ];
+        for row in &rows {
+            for i in 0..9 {
+                for j in 0..9 {
+                    normal[i][j] += row[i] * row[j];
+                }
+            }
+        }
+    }
+    let (values, vectors) = jacobi_eigen(normal);
+    let smallest = (0..9).min_by(|&a, &b| values[a].total_cmp(&values[b]))?;
+    let mut h = [0.0; 9];
+    for (i, value) in h.iter_mut().enumerate() {
+        *value = vectors[i][smallest];
+    }
+
+    let denormalized = source.then(&Homography(h)).then(&destination.inverse()?);
+    let scale = denormalized.0[8];
+    if scale.abs() < f64::EPSILON {
+        return Some(denormalized);
+    }
+    Some(Homography(denormalized.0.map(|value| value / scale)))
+}
+
+// Cyclic Jacobi rotations on a symmetric matrix. Returns the eigenvalues and
+// the eigenvectors as columns.
+fn jacobi_eigen(mut matrix: [[f64; 9]; 9]) -> ([f64; 9], [[f64; 9]; 9]) {
+    let mut vectors = [[0.0; 9]; 9];
+    for (i, row) in vectors.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    for _ in 0..64 {
+        let off_diagonal: f64 = (0..9)
+            .flat_map(|i| (0..9).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| matrix[i][j] * matrix[i][j])
+            .sum();
+        if off_diagonal < 1e-22 {
+            break;
+        }
+        for p in 0..9 {
+            for q in p + 1..9 {
+                if matrix[p][q].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (matrix[q][q] - matrix[p][p]) / (2.0 * matrix[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let t = if theta == 0.0 { 1.0 } else { t };
+                let (c, s) = (1.0 / (t * t + 1.0).sqrt(), t / (t * t + 1.0).sqrt());
+                for row in matrix.iter_mut() {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = c * kp - s * kq;
+                    row[q] = s * kp + c * kq;
+                }
+                let (row_p, row_q) = (matrix[p], matrix[q]);
+                matrix[p] = std::array::from_fn(|k| c * row_p[k] - s * row_q[k]);
+                matrix[q] = std::array::from_fn(|k| s * row_p[k] + c * row_q[k]);
+                for row in vectors.iter_mut() {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = c * kp - s * kq;
+                    row[q] = s * kp + c * kq;
+                }
+            }
+        }
+    }
+    let mut values = [0.0; 9];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = matrix[i][i];
+    }
+    (values, vectors)
+}
+
+pub struct Estimate {
+    pub homography: Homography,
+    pub inliers: Vec<bool>,
+}
+
+impl Estimate {
+    pub fn inlier_count(&self) -> usize {
+        self.inliers.iter().filter(|&&inlier| inlier).count()
+    }
+}
+
+// RANSAC over minimal samples of four correspondences. The number of rounds
+// shrinks as the inlier ratio of the best model grows, and the winner is
+// refitted on all of its inliers.
+pub fn ransac(
+    correspondences: &[Correspondence],
+    threshold: f64,
+    confidence: f64,
+    max_iterations: usize,
+) -> Option<Estimate> {
+    let count = correspondences.len();
+    if count < 4 {
+        return None;
+    }
+    let mut random = Xorshift::new(RANSAC_SEED);
+    let mut best: Option<Estimate> = None;
+    let mut iterations = max_iterations;
+    let mut round = 0;
+    while round < iterations {
+        round += 1;
+        let mut sample = [0; 4];
+        for i in 0..4 {
+            sample[i] = loop {
+                let candidate = random.below(count);
+                if !sample[..i].contains(&candidate) {
+                    break candidate;
+                }
+            };
+        }
+        let minimal = sample.map(|i| correspondences[i]);
+        let Some(homography) = from_correspondences(&minimal) else {
+            continue;
+        };
+        let inliers = inliers(&homography, correspondences, threshold);
+        let estimate = Estimate {
+            homography,
+            inliers,
+        };
+        let found = estimate.inlier_count();
+        if best.as_ref().is_none_or(|best| found > best.inlier_count()) {
+            let ratio = found as f64 / count as f64;
+            let all_inliers = 1.0 - ratio.powi(4);
+            if all_inliers <= f64::EPSILON {
+                iterations = round;
+            } else {
+                let needed = (1.0 - confidence).ln() / all_inliers.ln();
+                iterations = iterations.min(needed.ceil().max(1.0) as usize);
+            }
+            best = Some(estimate);
+        }
+    }
+
+    let best = best?;
+    let chosen: Vec<Correspondence> = correspondences
+        .iter()
+        .zip(&best.inliers)
+        .filter(|(_, &inlier)| inlier)
+        .map(|(&correspondence, _)| correspondence)
+        .collect();
+    match from_correspondences(&chosen) {
+        Some(homography) => {
+            let inliers = inliers(&homography, correspondences, threshold);
+            Some(Estimate {
+                homography,
+                inliers,
+            })
+        }
+        None => Some(best),
+    }
+}
+
+fn inliers(
+    homography: &Homography,
+    correspondences: &[Correspondence],
+    threshold: f64,
+) -> Vec<bool> {
+    correspondences
+        .iter()
+        .map(|&(a, b)| {
+            homography
+                .apply(a)
+                .is_some_and(|(x, y)| (x - b.0).hypot(y - b.1) <= threshold)
+        })
+        .collect()
+}
+
+// Every destination pixel is mapped back into the source and sampled
+// bilinearly. Pixels that fall outside the source are left transparent.
+pub fn warp(image: &RawImage, homography: &Homography, width: u32, height: u32) -> RawImage {
+    let mut pixels = vec![0; (width * height * 4) as usize];
+    let Some(inverse) = homography.inverse() else {
+        return RawImage::from_raw(pixels, width, height);
+    };
+    for (index, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+        let (x, y) = ((index as u32 % width) as f64, (index as u32 / width) as f64);
+        if let Some((sx, sy)) = inverse.apply((x, y)) {
+            if let Some(sample) = sample(image, sx, sy) {
+                pixel.copy_from_slice(&sample);
+            }
+        }
+    }
+    RawImage::from_raw(pixels, width, height)
+}
+
+fn sample(image: &RawImage, x: f64, y: f64) -> Option<[u8; 4]> {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    if x < 0.0 || y < 0.0 || x > width - 1.0 || y > height - 1.0 {
+        return None;
+    }
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = (
+        (x0 + 1).min(image.width() as usize - 1),
+        (y0 + 1).min(image.height() as usize - 1),
+    );
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+    let pixels = image.pixels();
+    let at = |x: usize, y: usize, c: usize| pixels[(y * image.width() as usize + x) * 4 + c] as f64;
+    Some([0, 1, 2, 3].map(|c| {
+        let top = at(x0, y0, c) * (1.0 - fx) + at(x1, y0, c) * fx;
+        let bottom = at(x0, y1, c) * (1.0 - fx) + at(x1, y1, c) * fx;
+        (top * (1.0 - fy) + bottom * fy).round() as u8
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KNOWN: Homography = Homography([1.2, 0.1, 15.0, -0.05, 0.9, -8.0, 0.0004, -0.0002, 1.0]);
+
+    fn assert_close(a: &Homography, b: &Homography) {
+        for (x, y) in a.0.iter().zip(&b.0) {
+            assert!((x - y).abs() < 1e-6, "{:?} != {:?}", a, b);
+        }
+    }
+
+    fn grid() -> Vec<Correspondence> {
+        (0..8)
+            .flat_map(|i| (0..6).map(move |j| (i as f64 * 40.0, j as f64 * 30.0)))
+            .map(|point| (point, KNOWN.apply(point).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn dlt_recovers_a_known_homography_from_four_points() {
+        let correspondences: Vec<Correspondence> =
+            [(0.0, 0.0), (300.0, 10.0), (280.0, 220.0), (20.0, 200.0)]
+                .iter()
+                .map(|&point| (point, KNOWN.apply(point).unwrap()))
+                .collect();
+        assert_close(&from_correspondences(&correspondences).unwrap(), &KNOWN);
+        assert!(from_correspondences(&correspondences[..3]).is_none());
+    }
+
+    #[test]
+    fn inverse_undoes_the_homography() {
+        let round_trip = KNOWN.then(&KNOWN.inverse().unwrap());
+        assert_close(&round_trip, &Homography::identity());
+    }
+
+    #[test]
+    fn ransac_rejects_planted_outliers() {
+        let mut correspondences = grid();
+        let outliers = [3, 10, 17, 22, 31, 40];
+        for (n, &i) in outliers.iter().enumerate() {
+            let (x, y) = correspondences[i].1;
+            correspondences[i].1 = (x + 50.0 + 13.0 * n as f64, y - 70.0);
+        }
+        let estimate = ransac(&correspondences, 1.0, 0.995, 2000).unwrap();
+        assert_close(&estimate.homography, &KNOWN);
+        for (i, &inlier) in estimate.inliers.iter().enumerate() {
+            assert_eq!(inlier, !outliers.contains(&i), "correspondence {}", i);
+        }
+    }
+
+    #[test]
+    fn warp_moves_pixels_by_the_homography() {
+        let mut pixels = [0, 0, 0, 255].repeat(16 * 16);
+        pixels[4 * (5 * 16 + 4)..4 * (5 * 16 + 5)].copy_from_slice(&[255, 0, 0, 255]);
+        let image = RawImage::from_raw(pixels, 16, 16);
+        let shift = Homography([1.0, 0.0, 3.0, 0.0, 1.0, 2.0, 0.0, 0.0, 1.0]);
+        let warped = warp(&image, &shift, 16, 16);
+        let at = |x: usize, y: usize| &warped.pixels()[4 * (y * 16 + x)..4 * (y * 16 + x) + 4];
+        assert_eq!(at(7, 7), &[255, 0, 0, 255]);
+        assert_eq!(at(4, 5), &[0, 0, 0, 255]);
+        // Nothing maps back into the source from the first columns and rows.
+        assert_eq!(at(1, 1)[3], 0);
+    }
+}
@@ -0,0 +1,490 @@
+use crate::binary::BinaryImage;
+use crate::constants::HOUGH_SEED;
+use crate::gray::GrayImage;
+use crate::image::RawImage;
+use crate::random::Xorshift;
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HoughMode {
+    Off,
+    Lines,
+    Segments,
+    Circles,
+}
+
+impl HoughMode {
+    pub fn next(self) -> Self {
+        match self {
+            HoughMode::Off => HoughMode::Lines,
+            HoughMode::Lines => HoughMode::Segments,
+            HoughMode::Segments => HoughMode::Circles,
+            HoughMode::Circles => HoughMode::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            HoughMode::Off => "Hough: off",
+            HoughMode::Lines => "Hough: lines",
+            HoughMode::Segments => "Hough: segments",
+            HoughMode::Circles => "Hough: circles",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Line {
+    // Distance from the origin and angle of the normal: x cos + y sin = rho.
+    pub rho: f32,
+    pub theta: f32,
+    pub votes: u32,
+}
+
+impl Line {
+    // Where the line crosses the border of a width by height image.
+    pub fn endpoints(&self, width: u32, height: u32) -> Option<((f32, f32), (f32, f32))> {
+        let (cos, sin) = (self.theta.cos(), self.theta.sin());
+        let (right, bottom) = ((width - 1) as f32, (height - 1) as f32);
+        let mut points = vec![];
+        if sin.abs() > f32::EPSILON {
+            for x in [0.0, right] {
+                points.push((x, (self.rho - x * cos) / sin));
+            }
+        }
+        if cos.abs() > f32::EPSILON {
+            for y in [0.0, bottom] {
+                points.push(((self.rho - y * sin) / cos, y));
+            }
+        }
+        let mut inside = points.into_iter().filter(|&(x, y)| {
+            (-0.5..=right + 0.5).contains(&x) && (-0.5..=bottom + 0.5).contains(&y)
+        });
+        Some((inside.next()?, inside.next()?))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+    pub start: (i32, i32),
+    pub end: (i32, i32),
+    pub votes: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Circle {
+    pub center: (i32, i32),
+    pub radius: u32,
+    pub votes: u32,
+}
+
+pub struct Accumulator {
+    values: Vec<u32>,
+    width: u32,
+    height: u32,
+}
+
+impl Accumulator {
+    fn new(width: u32, height: u32) -> Self {
+        Accumulator {
+            values: vec![0; (width * height) as usize],
+            width,
+            height,
+        }
+    }
+
+    fn at(&self, x: i32, y: i32) -> u32 {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            0
+        } else {
+            self.values[(y * self.width as i32 + x) as usize]
+        }
+    }
+
+    // Bins at or above the threshold that no neighbour beats, strongest first.
+    fn peaks(&self, threshold: u32) -> Vec<(i32, i32, u32)> {
+        let mut peaks = vec![];
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let votes = self.at(x, y);
+                if votes < threshold.max(1) {
+                    continue;
+                }
+                let is_peak = (-1..=1)
+                    .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                    .filter(|&offset| offset != (0, 0))
+                    .all(|(dx, dy)| {
+                        let neighbor = self.at(x + dx, y + dy);
+                        // Ties go to the first bin in raster order.
+                        neighbor < votes || (neighbor == votes && (dy, dx) > (0, 0))
+                    });
+                if is_peak {
+                    peaks.push((x, y, votes));
+                }
+            }
+        }
+        peaks.sort_by_key(|&(_, _, votes)| std::cmp::Reverse(votes));
+        peaks
+    }
+
+    // Votes on a square root scale so that weak structure stays visible.
+    pub fn to_image(&self) -> RawImage {
+        let max = self.values.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let pixels = self
+            .values
+            .iter()
+            .flat_map(|&votes| {
+                let value = ((votes as f32 / max).sqrt() * 255.0).round() as u8;
+                [value, value, value, 255]
+            })
+            .collect();
+        RawImage::from_raw(pixels, self.width, self.height)
+    }
+}
+
+// Rho is quantized to whole pixels and offset so negative distances get
+// bins, theta to steps over half a turn.
+struct LineSpace {
+    steps: usize,
+    offset: i32,
+    cos: Vec<f32>,
+    sin: Vec<f32>,
+}
+
+impl LineSpace {
+    fn new(edges: &BinaryImage, steps: usize) -> Self {
+        let diagonal = (edges.width() as f32).hypot(edges.height() as f32).ceil() as i32;
+        let angles = (0..steps).map(|t| t as f32 * PI / steps as f32);
+        LineSpace {
+            steps,
+            offset: diagonal,
+            cos: angles.clone().map(f32::cos).collect(),
+            sin: angles.map(f32::sin).collect(),
+        }
+    }
+
+    fn accumulator(&self) -> Accumulator {
+        Accumulator::new(self.steps as u32, 2 * self.offset as u32 + 1)
+    }
+
+    fn bins(&self, (x, y): (i32, i32)) -> impl Iterator<Item = usize> + '_ {
+        (0..self.steps).map(move |t| {
+            let rho = (x as f32 * self.cos[t] + y as f32 * self.sin[t]).round() as i32;
+            (rho + self.offset) as usize * self.steps + t
+        })
+    }
+
+    fn line(&self, t: i32, r: i32, votes: u32) -> Line {
+        Line {
+            rho: (r - self.offset) as f32,
+            theta: t as f32 * PI / self.steps as f32,
+            votes,
+        }
+    }
+}
+
+fn edge_points(edges: &BinaryImage) -> Vec<(i32, i32)> {
+    let width = edges.width() as usize;
+    edges
+        .values()
+        .iter()
+        .enumerate()
+        .filter(|(_, &set)| set)
+        .map(|(index, _)| ((index % width) as i32, (index / width) as i32))
+        .collect()
+}
+
+// The standard transform: every edge pixel votes for all the lines through it.
+pub fn lines(
+    edges: &BinaryImage,
+    steps: usize,
+    threshold: u32,
+    max_count: usize,
+) -> (Vec<Line>, Accumulator) {
+    let space = LineSpace::new(edges, steps);
+    let mut accumulator = space.accumulator();
+    for point in edge_points(edges) {
+        for bin in space.bins(point) {
+            accumulator.values[bin] += 1;
+        }
+    }
+    let lines = accumulator
+        .peaks(threshold)
+        .into_iter()
+        .take(max_count)
+        .map(|(t, r, votes)| space.line(t, r, votes))
+        .collect();
+    (lines, accumulator)
+}
+
+// Matas' progressive probabilistic transform: edge pixels vote in random
+// order and as soon as a bin reaches the threshold the line is walked from
+// the pixel to find its segment, whose pixels then withdraw their votes.
+pub fn segments(
+    edges: &BinaryImage,
+    steps: usize,
+    threshold: u32,
+    min_length: u32,
+    max_gap: u32,
+    max_count: usize,
+) -> (Vec<Segment>, Accumulator) {
+    let (width, height) = (edges.width() as i32, edges.height() as i32);
+    let space = LineSpace::new(edges, steps);
+    let mut accumulator = space.accumulator();
+    let mut pending = edges.values().to_vec();
+    let mut voted = vec![false; pending.len()];
+
+    let mut points = edge_points(edges);
+    let mut random = Xorshift::new(HOUGH_SEED);
+    for i in (1..points.len()).rev() {
+        points.swap(i, random.below(i + 1));
+    }
+
+    let mut segments = vec![];
+    for point in points {
+        let index = (point.1 * width + point.0) as usize;
+        if !pending[index] {
+            continue;
+        }
+        voted[index] = true;
+        let mut best = (0, 0);
+        for bin in space.bins(point) {
+            accumulator.values[bin] += 1;
+            if accumulator.values[bin] > best.1 {
+                best = (bin, accumulator.values[bin]);
+            }
+        }
+        if best.1 < threshold {
+            continue;
+        }
+
+        // Step one pixel along the major axis of the line's direction.
+        let t = best.0 % steps;
+        let (dx, dy) = (-space.sin[t], space.cos[t]);
+        let scale = dx.abs().max(dy.abs());
+        let (dx, dy) = (dx / scale, dy / scale);
+        let walk = |sign: f32, pending: &[bool]| {
+            let (mut end, mut gap, mut k) = (point, 0, 1);
+            loop {
+                let x = (point.0 as f32 + sign * k as f32 * dx).round() as i32;
+                let y = (point.1 as f32 + sign * k as f32 * dy).round() as i32;
+                if x < 0 || y < 0 || x >= width || y >= height {
+                    break;
+                }
+                if pending[(y * width + x) as usize] {
+                    end = (x, y);
+                    gap = 0;
+                } else {
+                    gap += 1;
+                    if gap > max_gap {
+                        break;
+                    }
+                }
+                k += 1;
+            }
+            end
+        };
+        let (start, end) = (walk(-1.0, &pending), walk(1.0, &pending));
+        let length = ((end.0 - start.0) as f32).hypot((end.1 - start.1) as f32);
+        let good = length >= min_length as f32;
+
+        // Whatever lies on the walked stretch is used up either way.
+        let count = (end.0 - start.0).abs().max((end.1 - start.1).abs());
+        for k in 0..=count {
+            let along = k as f32 / count.max(1) as f32;
+            let x = (start.0 as f32 + along * (end.0 - start.0) as f32).round() as i32;
+            let y = (start.1 as f32 + along * (end.1 - start.1) as f32).round() as i32;
+            let index = (y * width + x) as usize;
+            if !pending[index] {
+                continue;
+            }
+            pending[index] = false;
+            if good && voted[index] {
+                for bin in space.bins((x, y)) {
+                    accumulator.values[bin] -= 1;
+                }
+            }
+        }
+        if good {
+            segments.push(Segment {
+                start,
+                end,
+                votes: best.1,
+            });
+            if segments.len() == max_count {
+                break;
+            }
+        }
+    }
+    (segments, accumulator)
+}
+
+// The gradient method: each edge pixel votes for centers along its gradient
+// on both sides, then every strong enough center picks the radius that most
+// of the edge pixels around it agree on.
+pub fn circles(
+    edges: &BinaryImage,
+    gradients: (&GrayImage, &GrayImage),
+    radii: (u32, u32),
+    threshold: u32,
+    support: f32,
+    max_count: usize,
+) -> (Vec<Circle>, Accumulator) {
+    let (width, height) = (edges.width() as i32, edges.height() as i32);
+    let (gx, gy) = gradients;
+    let (min_radius, max_radius) = (radii.0.max(1), radii.1.max(radii.0.max(1)));
+    let mut accumulator = Accumulator::new(edges.width(), edges.height());
+    let points = edge_points(edges);
+
+    for &(x, y) in &points {
+        let index = (y * width + x) as usize;
+        let (dx, dy) = (gx.values()[index], gy.values()[index]);
+        let magnitude = dx.hypot(dy);
+        if magnitude == 0.0 {
+            continue;
+        }
+        let (ux, uy) = (dx / magnitude, dy / magnitude);
+        for radius in min_radius..=max_radius {
+            for sign in [-1.0, 1.0] {
+                let cx = (x as f32 + sign * radius as f32 * ux).round() as i32;
+                let cy = (y as f32 + sign * radius as f32 * uy).round() as i32;
+                if cx >= 0 && cy >= 0 && cx < width && cy < height {
+                    accumulator.values[(cy * width + cx) as usize] += 1;
+                }
+            }
+        }
+    }
+
+    let mut circles: Vec<Circle> = vec![];
+    for (cx, cy, _) in accumulator.peaks(threshold) {
+        if circles.len() == max_count {
+            break;
+        }
+        let crowded = circles.iter().any(|circle| {
+            let (dx, dy) = (circle.center.0 - cx, circle.center.1 - cy);
+            dx * dx + dy * dy < (min_radius * min_radius) as i32
+        });
+        if crowded {
+            continue;
+        }
+
+        let mut histogram = vec![0u32; max_radius as usize + 2];
+        for &(x, y) in &points {
+            let distance = ((x - cx) as f32).hypot((y - cy) as f32).round() as u32;
+            if (min_radius..=max_radius).contains(&distance) {
+                histogram[distance as usize] += 1;
+            }
+        }
+        // Neighbouring radii are summed since a circle's pixels are spread
+        // over about one pixel of distance.
+        let best = (min_radius..=max_radius)
+            .map(|radius| {
+                let r = radius as usize;
+                let votes = histogram[r - 1] + histogram[r] + histogram[r + 1];
+                (radius, votes, votes as f32 / (2.0 * PI * radius as f32))
+            })
+            .max_by(|a, b| a.2.total_cmp(&b.2));
+        if let Some((radius, votes, ratio)) = best {
+            if ratio >= support {
+                circles.push(Circle {
+                    center: (cx, cy),
+                    radius,
+                    votes,
+                });
+            }
+        }
+    }
+    (circles, accumulator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{CANNY_HIGH, CANNY_LOW, CANNY_SIGMA, HOUGH_MAX_GAP, HOUGH_THETA_STEPS};
+    use crate::edges;
+
+    const WIDTH: u32 = 80;
+    const HEIGHT: u32 = 60;
+
+    fn drawn(set: impl Fn(i32, i32) -> bool) -> BinaryImage {
+        let values = (0..HEIGHT as i32)
+            .flat_map(|y| (0..WIDTH as i32).map(move |x| (x, y)))
+            .map(|(x, y)| set(x, y))
+            .collect();
+        BinaryImage::from_values(values, WIDTH, HEIGHT)
+    }
+
+    #[test]
+    fn horizontal_line_is_the_strongest_peak() {
+        let edges = drawn(|_, y| y == 20);
+        let (found, _) = lines(&edges, HOUGH_THETA_STEPS, 40, 1);
+        let line = found[0];
+        assert_eq!((line.rho, line.votes), (20.0, WIDTH));
+        assert!((line.theta - PI / 2.0).abs() < 1e-6);
+        let (start, end) = line.endpoints(WIDTH, HEIGHT).unwrap();
+        for (x, y) in [start, end] {
+            assert!(x == 0.0 || x == (WIDTH - 1) as f32);
+            assert!((y - 20.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn diagonal_line_has_its_normal_at_three_eighths_of_a_turn() {
+        let edges = drawn(|x, y| x == y);
+        let (found, _) = lines(&edges, HOUGH_THETA_STEPS, 40, 1);
+        assert_eq!(found[0].rho, 0.0);
+        assert!((found[0].theta - 0.75 * PI).abs() < 1e-6);
+        assert_eq!(found[0].votes, HEIGHT);
+    }
+
+    #[test]
+    fn no_line_reaches_a_threshold_above_the_pixel_count() {
+        let edges = drawn(|_, y| y == 20);
+        assert!(lines(&edges, HOUGH_THETA_STEPS, WIDTH + 1, 4).0.is_empty());
+    }
+
+    #[test]
+    fn segments_bridge_small_gaps_but_not_large_ones() {
+        let small = HOUGH_MAX_GAP as i32;
+        let edges =
+            drawn(|x, y| y == 30 && (10..=70).contains(&x) && !(40..40 + small).contains(&x));
+        let (found, _) = segments(&edges, HOUGH_THETA_STEPS, 20, 20, HOUGH_MAX_GAP, 4);
+        assert_eq!(found.len(), 1);
+        let mut ends = [found[0].start, found[0].end];
+        ends.sort();
+        assert_eq!(ends, [(10, 30), (70, 30)]);
+
+        let large = small + 2;
+        let edges =
+            drawn(|x, y| y == 30 && (10..=70).contains(&x) && !(40..40 + large).contains(&x));
+        let (found, _) = segments(&edges, HOUGH_THETA_STEPS, 20, 20, HOUGH_MAX_GAP, 4);
+        assert_eq!(found.len(), 2);
+    }
+
+    // Through the same blur and edge detection as the analysis uses.
+    #[test]
+    fn circle_is_found_with_its_center_and_radius() {
+        let disk = GrayImage::new(
+            (0..HEIGHT as i32)
+                .flat_map(|y| (0..WIDTH as i32).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let inside = ((x - 40) as f32).hypot((y - 30) as f32) <= 15.0;
+                    if inside {
+                        255.0
+                    } else {
+                        0.0
+                    }
+                })
+                .collect(),
+            WIDTH,
+            HEIGHT,
+        )
+        .blur(CANNY_SIGMA);
+        let (gx, gy) = disk.sobel();
+        let edges = edges::canny(&gx, &gy, CANNY_LOW, CANNY_HIGH);
+        let (found, _) = circles(&edges, (&gx, &gy), (8, 25), 20, 0.5, 1);
+        let circle = found[0];
+        assert!((circle.center.0 - 40).abs() <= 1 && (circle.center.1 - 30).abs() <= 1);
+        assert!((14..=16).contains(&circle.radius), "{:?}", circle);
+    }
+}
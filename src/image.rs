@@ -18,6 +18,34 @@ impl RawImage {
         }
     }
 
+    pub fn from_raw(raw_pixels: Vec<u8>, width: u32, height: u32) -> Self {
+        RawImage {
+            raw_pixels,
+            width,
+            height,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw_pixels.is_empty()
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.raw_pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.raw_pixels
+    }
+
     pub fn to_image_data(&self) -> Result<ImageData> {
         let mut raw_pixels = &self.raw_pixels;
         let width = self.width;
@@ -78,3 +106,25 @@ impl From<ImageData> for RawImage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn red_shift_clamps_and_leaves_the_other_channels() {
+        let pixels = vec![0, 1, 2, 3, 100, 101, 102, 103, 240, 241, 242, 243];
+        let mut image = RawImage::from_raw(pixels.clone(), 3, 1);
+        image.alter_red_channel(24);
+        assert_eq!(
+            image.pixels(),
+            [24, 1, 2, 3, 124, 101, 102, 103, 255, 241, 242, 243]
+        );
+        let mut image = RawImage::from_raw(pixels, 3, 1);
+        image.alter_red_channel(-50);
+        assert_eq!(
+            image.pixels(),
+            [0, 1, 2, 3, 50, 101, 102, 103, 190, 241, 242, 243]
+        );
+    }
+}
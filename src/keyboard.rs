@@ -0,0 +1,113 @@
+use crate::events::{self, Action, EventSender, Listener, UiEvent};
+use anyhow::Result;
+use std::collections::HashMap;
+use wasm_bindgen::JsCast;
+use web_sys::{HtmlElement, KeyboardEvent};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct KeyBinding {
+    pub key: String,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl KeyBinding {
+    pub fn new(key: &str) -> Self {
+        KeyBinding {
+            key: normalize_key(key),
+            ctrl: false,
+            shift: false,
+            alt: false,
+        }
+    }
+
+    pub fn ctrl(key: &str) -> Self {
+        KeyBinding {
+            ctrl: true,
+            ..KeyBinding::new(key)
+        }
+    }
+
+    // Cmd on macOS is treated as Ctrl so the usual shortcuts work there too.
+    fn from_event(event: &KeyboardEvent) -> Self {
+        KeyBinding {
+            key: normalize_key(&event.key()),
+            ctrl: event.ctrl_key() || event.meta_key(),
+            shift: event.shift_key(),
+            alt: event.alt_key(),
+        }
+    }
+}
+
+fn normalize_key(key: &str) -> String {
+    if key.chars().count() == 1 {
+        key.to_lowercase()
+    } else {
+        key.to_string()
+    }
+}
+
+#[derive(Clone)]
+pub struct Keymap {
+    bindings: HashMap<KeyBinding, Action>,
+}
+
+impl Keymap {
+    pub fn empty() -> Self {
+        Keymap {
+            bindings: HashMap::new(),
+        }
+    }
+
+    pub fn bind(&mut self, binding: KeyBinding, action: Action) {
+        self.bindings.insert(binding, action);
+    }
+
+    pub fn action(&self, binding: &KeyBinding) -> Option<Action> {
+        self.bindings.get(binding).copied()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap::empty();
+        keymap.bind(KeyBinding::new(" "), Action::ToggleRun);
+        keymap.bind(KeyBinding::new("Enter"), Action::Finish);
+        keymap.bind(KeyBinding::new("r"), Action::Refresh);
+        keymap.bind(KeyBinding::ctrl("s"), Action::Save);
+        keymap.bind(KeyBinding::ctrl("z"), Action::Undo);
+        keymap.bind(KeyBinding::new("ArrowLeft"), Action::Undo);
+        keymap.bind(KeyBinding::new("ArrowRight"), Action::Step);
+        keymap
+    }
+}
+
+// Bound keys have their default behaviour suppressed, so Space doesn't
+// scroll the page and Ctrl+S doesn't open the browser's save dialog.
+// Auto-repeated keydowns only trigger actions that make sense to repeat,
+// e.g. holding the right arrow keeps stepping the simulation. The browser
+// flags those itself, so a key released while the page had no focus never
+// counts as held.
+pub fn add_key_handler(
+    elem: &HtmlElement,
+    sender: EventSender,
+    keymap: Keymap,
+) -> Result<Vec<Listener>> {
+    let on_keydown = Listener::new(elem, "keydown", move |event| {
+        if let Some(key_event) = event.dyn_ref::<KeyboardEvent>() {
+            let binding = KeyBinding::from_event(key_event);
+            if let Some(action) = keymap.action(&binding) {
+                event.prevent_default();
+                if !key_event.repeat() || repeats(action) {
+                    events::send(&sender, UiEvent::Key(action));
+                }
+            }
+        }
+    })?;
+    Ok(vec![on_keydown])
+}
+
+fn repeats(action: Action) -> bool {
+    matches!(action, Action::Undo | Action::Step)
+}
@@ -0,0 +1,455 @@
+use crate::constants::PYRAMID_LEVELS;
+use crate::image::RawImage;
+use crate::mask::Mask;
+use crate::pyramid;
+use anyhow::{bail, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    SoftLight,
+    Difference,
+    Add,
+    Subtract,
+    ColorDodge,
+    ColorBurn,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
+impl BlendMode {
+    pub fn next(self) -> Self {
+        match self {
+            BlendMode::Normal => BlendMode::Multiply,
+            BlendMode::Multiply => BlendMode::Screen,
+            BlendMode::Screen => BlendMode::Overlay,
+            BlendMode::Overlay => BlendMode::SoftLight,
+            BlendMode::SoftLight => BlendMode::Difference,
+            BlendMode::Difference => BlendMode::Add,
+            BlendMode::Add => BlendMode::Subtract,
+            BlendMode::Subtract => BlendMode::ColorDodge,
+            BlendMode::ColorDodge => BlendMode::ColorBurn,
+            BlendMode::ColorBurn => BlendMode::Hue,
+            BlendMode::Hue => BlendMode::Saturation,
+            BlendMode::Saturation => BlendMode::Color,
+            BlendMode::Color => BlendMode::Luminosity,
+            BlendMode::Luminosity => BlendMode::Normal,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BlendMode::Normal => "Blend: normal",
+            BlendMode::Multiply => "Blend: multiply",
+            BlendMode::Screen => "Blend: screen",
+            BlendMode::Overlay => "Blend: overlay",
+            BlendMode::SoftLight => "Blend: soft light",
+            BlendMode::Difference => "Blend: difference",
+            BlendMode::Add => "Blend: add",
+            BlendMode::Subtract => "Blend: subtract",
+            BlendMode::ColorDodge => "Blend: color dodge",
+            BlendMode::ColorBurn => "Blend: color burn",
+            BlendMode::Hue => "Blend: hue",
+            BlendMode::Saturation => "Blend: saturation",
+            BlendMode::Color => "Blend: color",
+            BlendMode::Luminosity => "Blend: luminosity",
+        }
+    }
+
+    // Blended color of a backdrop and a source, both straight and in 0..=1.
+    fn blend(self, backdrop: [f32; 3], source: [f32; 3]) -> [f32; 3] {
+        let separable = |f: fn(f32, f32) -> f32| [0, 1, 2].map(|c| f(backdrop[c], source[c]));
+        match self {
+            BlendMode::Normal => source,
+            BlendMode::Multiply => separable(|b, s| b * s),
+            BlendMode::Screen => separable(screen),
+            BlendMode::Overlay => separable(|b, s| hard_light(s, b)),
+            BlendMode::SoftLight => separable(soft_light),
+            BlendMode::Difference => separable(|b, s| (b - s).abs()),
+            BlendMode::Add => separable(|b, s| (b + s).min(1.0)),
+            BlendMode::Subtract => separable(|b, s| (b - s).max(0.0)),
+            BlendMode::ColorDodge => separable(color_dodge),
+            BlendMode::ColorBurn => separable(color_burn),
+            BlendMode::Hue => set_lum(set_sat(source, sat(backdrop)), lum(backdrop)),
+            BlendMode::Saturation => set_lum(set_sat(backdrop, sat(source)), lum(backdrop)),
+            BlendMode::Color => set_lum(source, lum(backdrop)),
+            BlendMode::Luminosity => set_lum(backdrop, lum(source)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Layer {
+    pub image: RawImage,
+    pub opacity: f32,
+    pub visible: bool,
+    pub blend: BlendMode,
+    // Blended band by band through its alpha instead of over it.
+    pub seamless: bool,
+}
+
+impl Layer {
+    pub fn new(image: RawImage) -> Self {
+        Layer {
+            image,
+            opacity: 1.0,
+            visible: true,
+            blend: BlendMode::Normal,
+            seamless: false,
+        }
+    }
+}
+
+// Layers are ordered bottom to top.
+#[derive(Clone, Default)]
+pub struct LayerStack {
+    layers: Vec<Layer>,
+}
+
+impl LayerStack {
+    pub fn push(&mut self, layer: Layer) {
+        self.layers.push(layer);
+    }
+
+    pub fn top_mut(&mut self) -> Option<&mut Layer> {
+        self.layers.last_mut()
+    }
+
+    pub fn clear(&mut self) {
+        self.layers.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    // Compositing follows the W3C compositing model with source-over, carried
+    // out on premultiplied values and converted back to straight alpha for
+    // `ImageData` at the end.
+    pub fn composite_over(&self, backdrop: &RawImage) -> Result<RawImage> {
+        let (width, height) = (backdrop.width(), backdrop.height());
+        if let Some(layer) = self
+            .layers
+            .iter()
+            .find(|layer| layer.image.width() != width || layer.image.height() != height)
+        {
+            bail!(
+                "layer of {}x{} does not match the {}x{} backdrop",
+                layer.image.width(),
+                layer.image.height(),
+                width,
+                height
+            );
+        }
+
+        let mut buffer = premultiply(backdrop);
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            if layer.seamless {
+                buffer = premultiply(&seamless(&buffer, layer, width, height)?);
+                continue;
+            }
+            let opacity = layer.opacity.clamp(0.0, 1.0);
+            for (out, pixel) in buffer.iter_mut().zip(layer.image.pixels().chunks_exact(4)) {
+                let source_alpha = pixel[3] as f32 / 255.0 * opacity;
+                if source_alpha <= 0.0 {
+                    continue;
+                }
+                let backdrop_alpha = out[3];
+                let source = [0, 1, 2].map(|c| pixel[c] as f32 / 255.0);
+                let backdrop = [0, 1, 2].map(|c| {
+                    if backdrop_alpha > 0.0 {
+                        out[c] / backdrop_alpha
+                    } else {
+                        0.0
+                    }
+                });
+                let blended = layer.blend.blend(backdrop, source);
+                for c in 0..3 {
+                    let mixed = (1.0 - backdrop_alpha) * source[c] + backdrop_alpha * blended[c];
+                    out[c] = source_alpha * mixed + (1.0 - source_alpha) * out[c];
+                }
+                out[3] = source_alpha + backdrop_alpha * (1.0 - source_alpha);
+            }
+        }
+
+        Ok(unpremultiply(&buffer, width, height))
+    }
+}
+
+fn premultiply(image: &RawImage) -> Vec<[f32; 4]> {
+    image
+        .pixels()
+        .chunks_exact(4)
+        .map(|pixel| {
+            let alpha = pixel[3] as f32 / 255.0;
+            [0, 1, 2, 3].map(|c| {
+                if c == 3 {
+                    alpha
+                } else {
+                    pixel[c] as f32 / 255.0 * alpha
+                }
+            })
+        })
+        .collect()
+}
+
+fn unpremultiply(buffer: &[[f32; 4]], width: u32, height: u32) -> RawImage {
+    let pixels = buffer
+        .iter()
+        .flat_map(|pixel| {
+            let alpha = pixel[3];
+            [0, 1, 2, 3].map(|c| {
+                let value = if c == 3 {
+                    alpha
+                } else if alpha > 0.0 {
+                    pixel[c] / alpha
+                } else {
+                    0.0
+                };
+                (value * 255.0).round().clamp(0.0, 255.0) as u8
+            })
+        })
+        .collect();
+    RawImage::from_raw(pixels, width, height)
+}
+
+// The layer, already blended with the backdrop by its mode, is mixed in
+// through a multi-band blend with its alpha times the opacity as the mask.
+// Where the layer is fully transparent the backdrop stands in for it, so
+// that no color bleeds in from outside the layer.
+fn seamless(buffer: &[[f32; 4]], layer: &Layer, width: u32, height: u32) -> Result<RawImage> {
+    let backdrop = unpremultiply(buffer, width, height);
+    let opacity = layer.opacity.clamp(0.0, 1.0);
+    let mut mask = Mask::new(width, height);
+    let mut overlay = backdrop.clone();
+    for ((weight, out), (pixel, under)) in mask
+        .values_mut()
+        .iter_mut()
+        .zip(overlay.pixels_mut().chunks_exact_mut(4))
+        .zip(layer.image.pixels().chunks_exact(4).zip(buffer))
+    {
+        *weight = (pixel[3] as f32 * opacity).round() as u8;
+        if pixel[3] == 0 {
+            continue;
+        }
+        let backdrop_alpha = under[3];
+        let source = [0, 1, 2].map(|c| pixel[c] as f32 / 255.0);
+        let backdrop = [0, 1, 2].map(|c| out[c] as f32 / 255.0);
+        let blended = layer.blend.blend(backdrop, source);
+        for c in 0..3 {
+            let mixed = (1.0 - backdrop_alpha) * source[c] + backdrop_alpha * blended[c];
+            out[c] = (mixed * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+        out[3] = 255;
+    }
+    pyramid::blend(&backdrop, &overlay, &mask, PYRAMID_LEVELS)
+}
+
+fn screen(backdrop: f32, source: f32) -> f32 {
+    backdrop + source - backdrop * source
+}
+
+fn hard_light(backdrop: f32, source: f32) -> f32 {
+    if source <= 0.5 {
+        backdrop * 2.0 * source
+    } else {
+        screen(backdrop, 2.0 * source - 1.0)
+    }
+}
+
+fn soft_light(backdrop: f32, source: f32) -> f32 {
+    if source <= 0.5 {
+        backdrop - (1.0 - 2.0 * source) * backdrop * (1.0 - backdrop)
+    } else {
+        let d = if backdrop <= 0.25 {
+            ((16.0 * backdrop - 12.0) * backdrop + 4.0) * backdrop
+        } else {
+            backdrop.sqrt()
+        };
+        backdrop + (2.0 * source - 1.0) * (d - backdrop)
+    }
+}
+
+fn color_dodge(backdrop: f32, source: f32) -> f32 {
+    if backdrop <= 0.0 {
+        0.0
+    } else if source >= 1.0 {
+        1.0
+    } else {
+        (backdrop / (1.0 - source)).min(1.0)
+    }
+}
+
+fn color_burn(backdrop: f32, source: f32) -> f32 {
+    if backdrop >= 1.0 {
+        1.0
+    } else if source <= 0.0 {
+        0.0
+    } else {
+        1.0 - ((1.0 - backdrop) / source).min(1.0)
+    }
+}
+
+fn lum(color: [f32; 3]) -> f32 {
+    0.3 * color[0] + 0.59 * color[1] + 0.11 * color[2]
+}
+
+fn clip_color(color: [f32; 3]) -> [f32; 3] {
+    let l = lum(color);
+    let min = color[0].min(color[1]).min(color[2]);
+    let max = color[0].max(color[1]).max(color[2]);
+    let mut color = color;
+    if min < 0.0 {
+        color = color.map(|c| l + (c - l) * l / (l - min));
+    }
+    if max > 1.0 {
+        color = color.map(|c| l + (c - l) * (1.0 - l) / (max - l));
+    }
+    color
+}
+
+fn set_lum(color: [f32; 3], l: f32) -> [f32; 3] {
+    let d = l - lum(color);
+    clip_color(color.map(|c| c + d))
+}
+
+fn sat(color: [f32; 3]) -> f32 {
+    color[0].max(color[1]).max(color[2]) - color[0].min(color[1]).min(color[2])
+}
+
+fn set_sat(color: [f32; 3], s: f32) -> [f32; 3] {
+    let mut order = [0, 1, 2];
+    order.sort_by(|&a, &b| color[a].total_cmp(&color[b]));
+    let [min, mid, max] = order;
+    let mut result = [0.0; 3];
+    if color[max] > color[min] {
+        result[mid] = (color[mid] - color[min]) * s / (color[max] - color[min]);
+        result[max] = s;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flat(pixel: [u8; 4]) -> RawImage {
+        RawImage::from_raw(pixel.repeat(4), 2, 2)
+    }
+
+    fn composite(backdrop: &RawImage, layers: Vec<Layer>) -> RawImage {
+        let mut stack = LayerStack::default();
+        for layer in layers {
+            stack.push(layer);
+        }
+        stack.composite_over(backdrop).unwrap()
+    }
+
+    fn assert_close(actual: &[u8], expected: &[u8], tolerance: u8) {
+        assert_eq!(actual.len(), expected.len());
+        for (index, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                a.abs_diff(*e) <= tolerance,
+                "byte {}: {} instead of {}",
+                index,
+                a,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn opaque_source_replaces_the_backdrop() {
+        let source = flat([200, 30, 90, 255]);
+        for backdrop in [[10, 250, 40, 255], [10, 250, 40, 60], [0, 0, 0, 0]] {
+            let out = composite(&flat(backdrop), vec![Layer::new(source.clone())]);
+            assert_eq!(out.pixels(), source.pixels(), "{:?}", backdrop);
+        }
+    }
+
+    #[test]
+    fn transparent_source_leaves_the_backdrop() {
+        let backdrop = RawImage::from_raw(
+            [[10, 250, 40, 255], [90, 20, 200, 128]].concat().repeat(2),
+            2,
+            2,
+        );
+        let hidden = Layer::new(flat([200, 30, 90, 0]));
+        let mut faded = Layer::new(flat([200, 30, 90, 255]));
+        faded.opacity = 0.0;
+        let mut invisible = Layer::new(flat([200, 30, 90, 255]));
+        invisible.visible = false;
+        let out = composite(&backdrop, vec![hidden, faded, invisible]);
+        assert_eq!(out.pixels(), backdrop.pixels());
+    }
+
+    // Half of the blue layer shows, and the red one covers half of that.
+    #[test]
+    fn half_over_half_covers_three_quarters() {
+        let mut blue = Layer::new(flat([0, 0, 255, 255]));
+        blue.opacity = 0.5;
+        let mut red = Layer::new(flat([255, 0, 0, 255]));
+        red.opacity = 0.5;
+        let out = composite(&flat([0, 0, 0, 0]), vec![blue, red]);
+        assert_close(out.pixels(), &[170, 0, 85, 191].repeat(4), 1);
+    }
+
+    #[test]
+    fn multiply_and_screen_identities() {
+        let color = [0.8, 0.35, 0.1];
+        let (white, black) = ([1.0; 3], [0.0; 3]);
+        for (blend, source, expected) in [
+            (BlendMode::Multiply, white, color),
+            (BlendMode::Multiply, black, black),
+            (BlendMode::Screen, black, color),
+            (BlendMode::Screen, white, white),
+        ] {
+            let blended = blend.blend(color, source);
+            for c in 0..3 {
+                assert!((blended[c] - expected[c]).abs() < 1e-6, "{:?}", blend);
+            }
+        }
+
+        let backdrop = flat([200, 90, 25, 255]);
+        for (blend, source, expected) in [
+            (
+                BlendMode::Multiply,
+                [255, 255, 255, 255],
+                [200, 90, 25, 255],
+            ),
+            (BlendMode::Multiply, [0, 0, 0, 255], [0, 0, 0, 255]),
+            (BlendMode::Screen, [0, 0, 0, 255], [200, 90, 25, 255]),
+            (
+                BlendMode::Screen,
+                [255, 255, 255, 255],
+                [255, 255, 255, 255],
+            ),
+        ] {
+            let mut layer = Layer::new(flat(source));
+            layer.blend = blend;
+            let out = composite(&backdrop, vec![layer]);
+            assert_eq!(out.pixels(), expected.repeat(4), "{:?}", blend);
+        }
+    }
+
+    #[test]
+    fn seamless_blend_of_the_backdrop_reproduces_it() {
+        let (width, height) = (48, 40);
+        let pixels = (0..width * height)
+            .flat_map(|index| {
+                let (x, y) = (index % width, index / width);
+                [(x * 5) as u8, (y * 6) as u8, ((x + y) * 3) as u8, 255]
+            })
+            .collect();
+        let backdrop = RawImage::from_raw(pixels, width, height);
+        let mut layer = Layer::new(backdrop.clone());
+        layer.seamless = true;
+        let out = composite(&backdrop, vec![layer]);
+        assert_close(out.pixels(), backdrop.pixels(), 1);
+    }
+}
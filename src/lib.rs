@@ -1,13 +1,49 @@
 #[macro_use]
 mod browser;
+mod adjust;
+mod analysis;
+mod background;
+mod binary;
+mod brush;
 mod button;
 mod canvas;
+mod components;
 mod constants;
+mod contours;
+mod cube;
+mod denoise;
+mod dither;
+mod edges;
+mod events;
+mod features;
+mod file_drop;
+mod file_picker;
+mod flow;
+mod frames;
+mod gray;
+mod homography;
+mod hough;
 mod image;
+mod keyboard;
+mod layers;
+mod mask;
+mod motion;
+mod orb;
+mod panorama;
+mod pipeline;
 mod plot;
 mod plot_machine;
 mod plot_states;
+mod pointer;
+mod pyramid;
+mod quantize;
+mod random;
+mod seams;
+mod selection;
 mod simulation_loop;
+mod slider;
+mod stabilize;
+mod temporal;
 
 use browser::spawn_local;
 use plot::SimulationPlot;
@@ -0,0 +1,336 @@
+use crate::canvas::Point;
+use crate::image::RawImage;
+use anyhow::{anyhow, Result};
+
+pub enum Shape {
+    Rectangle { from: Point, to: Point },
+    Ellipse { from: Point, to: Point },
+    Polygon(Vec<Point>),
+}
+
+#[derive(Clone)]
+pub struct Mask {
+    values: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+impl Mask {
+    pub fn new(width: u32, height: u32) -> Self {
+        Mask {
+            values: vec![0; (width * height) as usize],
+            width,
+            height,
+        }
+    }
+
+    pub fn from_shape(shape: &Shape, width: u32, height: u32) -> Self {
+        let mut mask = Mask::new(width, height);
+        match shape {
+            Shape::Rectangle { from, to } => mask.fill_rectangle(from, to),
+            Shape::Ellipse { from, to } => mask.fill_ellipse(from, to),
+            Shape::Polygon(points) => mask.fill_polygon(points),
+        }
+        mask
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn values(&self) -> &[u8] {
+        &self.values
+    }
+
+    pub fn values_mut(&mut self) -> &mut [u8] {
+        &mut self.values
+    }
+
+    pub fn invert(&mut self) {
+        for value in self.values.iter_mut() {
+            *value = 255 - *value;
+        }
+    }
+
+    // Two box blur passes per axis give a smooth, roughly gaussian falloff
+    // at the border of the selection.
+    pub fn feather(&mut self, radius: u32) {
+        if radius == 0 {
+            return;
+        }
+        for _pass in 0..2 {
+            self.values = box_blur(&self.values, self.width, self.height, radius, true);
+            self.values = box_blur(&self.values, self.width, self.height, radius, false);
+        }
+    }
+
+    pub fn blend(&self, original: &RawImage, processed: &mut RawImage) -> Result<()> {
+        if original.width() != self.width
+            || original.height() != self.height
+            || processed.width() != self.width
+            || processed.height() != self.height
+        {
+            return Err(anyhow!(
+                "Mask of {}x{} does not match image of {}x{}",
+                self.width,
+                self.height,
+                processed.width(),
+                processed.height()
+            ));
+        }
+
+        let pixels = processed.pixels_mut().chunks_exact_mut(4);
+        let original_pixels = original.pixels().chunks_exact(4);
+        for ((pixel, original_pixel), &coverage) in pixels.zip(original_pixels).zip(&self.values) {
+            let coverage = coverage as u32;
+            for c in 0..3 {
+                pixel[c] = ((original_pixel[c] as u32 * (255 - coverage)
+                    + pixel[c] as u32 * coverage
+                    + 127)
+                    / 255) as u8;
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_rectangle(&mut self, from: &Point, to: &Point) {
+        let (x0, x1) = self.clamp_span(from.x.min(to.x) as f64, from.x.max(to.x) as f64);
+        let (y0, y1) = self.clamp_rows(from.y.min(to.y) as f64, from.y.max(to.y) as f64);
+        for y in y0..y1 {
+            self.fill_row(y, x0, x1);
+        }
+    }
+
+    fn fill_ellipse(&mut self, from: &Point, to: &Point) {
+        let cx = (from.x as f64 + to.x as f64) / 2.0;
+        let cy = (from.y as f64 + to.y as f64) / 2.0;
+        let rx = (to.x as f64 - from.x as f64).abs() / 2.0;
+        let ry = (to.y as f64 - from.y as f64).abs() / 2.0;
+        if rx == 0.0 || ry == 0.0 {
+            return;
+        }
+
+        let (y0, y1) = self.clamp_rows(cy - ry, cy + ry);
+        for y in y0..y1 {
+            let dy = (y as f64 + 0.5 - cy) / ry;
+            if dy.abs() > 1.0 {
+                continue;
+            }
+            let half_width = rx * (1.0 - dy * dy).sqrt();
+            let (x0, x1) = self.clamp_span(cx - half_width, cx + half_width);
+            self.fill_row(y, x0, x1);
+        }
+    }
+
+    // Scanline fill with the even-odd rule, so self-intersecting free-hand
+    // polygons still produce a sensible region.
+    fn fill_polygon(&mut self, points: &[Point]) {
+        if points.len() < 3 {
+            return;
+        }
+
+        let mut crossings: Vec<f64> = Vec::new();
+        for y in 0..self.height {
+            let scan_y = y as f64 + 0.5;
+            crossings.clear();
+            for (i, a) in points.iter().enumerate() {
+                let b = &points[(i + 1) % points.len()];
+                let (ay, by) = (a.y as f64, b.y as f64);
+                if (ay <= scan_y) != (by <= scan_y) {
+                    let t = (scan_y - ay) / (by - ay);
+                    crossings.push(a.x as f64 + t * (b.x as f64 - a.x as f64));
+                }
+            }
+            crossings.sort_by(|a, b| a.total_cmp(b));
+            for pair in crossings.chunks_exact(2) {
+                let (x0, x1) = self.clamp_span(pair[0], pair[1]);
+                self.fill_row(y, x0, x1);
+            }
+        }
+    }
+
+    fn fill_row(&mut self, y: u32, x0: u32, x1: u32) {
+        let row = (y * self.width) as usize;
+        self.values[row + x0 as usize..row + x1 as usize].fill(255);
+    }
+
+    // Pixels whose centers fall inside [start, end].
+    fn clamp_span(&self, start: f64, end: f64) -> (u32, u32) {
+        clamp_range(start, end, self.width)
+    }
+
+    fn clamp_rows(&self, start: f64, end: f64) -> (u32, u32) {
+        clamp_range(start, end, self.height)
+    }
+}
+
+fn clamp_range(start: f64, end: f64, size: u32) -> (u32, u32) {
+    let first = (start - 0.5).ceil().clamp(0.0, size as f64) as u32;
+    let last = ((end - 0.5).floor() + 1.0).clamp(0.0, size as f64) as u32;
+    (first, last.max(first))
+}
+
+fn box_blur(values: &[u8], width: u32, height: u32, radius: u32, horizontal: bool) -> Vec<u8> {
+    let (lines, length) = if horizontal {
+        (height as usize, width as usize)
+    } else {
+        (width as usize, height as usize)
+    };
+    // An empty line has no edge pixel to clamp to.
+    if length == 0 {
+        return values.to_vec();
+    }
+    let index = |line: usize, position: usize| {
+        if horizontal {
+            line * width as usize + position
+        } else {
+            position * width as usize + line
+        }
+    };
+
+    let radius = radius as i64;
+    let window = (2 * radius + 1) as u32;
+    let mut blurred = vec![0; values.len()];
+    for line in 0..lines {
+        let sample = |position: i64| {
+            values[index(line, position.clamp(0, length as i64 - 1) as usize)] as u32
+        };
+        let mut sum: u32 = (-radius..=radius).map(sample).sum();
+        for position in 0..length {
+            blurred[index(line, position)] = ((sum + window / 2) / window) as u8;
+            sum += sample(position as i64 + radius + 1);
+            sum -= sample(position as i64 - radius);
+        }
+    }
+    blurred
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: i16, y: i16) -> Point {
+        Point { x, y }
+    }
+
+    fn covered(mask: &Mask) -> Vec<(u32, u32)> {
+        (0..mask.height())
+            .flat_map(|y| (0..mask.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| mask.values()[(y * mask.width() + x) as usize] == 255)
+            .collect()
+    }
+
+    #[test]
+    fn rectangle_covers_the_pixels_between_its_corners() {
+        let rectangle = |from, to| Mask::from_shape(&Shape::Rectangle { from, to }, 8, 6);
+        let mask = rectangle(point(2, 1), point(5, 4));
+        let expected: Vec<_> = (1..4).flat_map(|y| (2..5).map(move |x| (x, y))).collect();
+        assert_eq!(covered(&mask), expected);
+        assert_eq!(rectangle(point(5, 4), point(2, 1)).values(), mask.values());
+        assert!(covered(&rectangle(point(-4, -4), point(-1, 3))).is_empty());
+        assert_eq!(covered(&rectangle(point(-4, -4), point(20, 20))).len(), 48);
+    }
+
+    #[test]
+    fn square_polygon_matches_the_rectangle() {
+        let corners = vec![point(1, 1), point(6, 1), point(6, 5), point(1, 5)];
+        let polygon = Mask::from_shape(&Shape::Polygon(corners), 8, 6);
+        let rectangle = Mask::from_shape(
+            &Shape::Rectangle {
+                from: point(1, 1),
+                to: point(6, 5),
+            },
+            8,
+            6,
+        );
+        assert_eq!(polygon.values(), rectangle.values());
+        let line = Mask::from_shape(&Shape::Polygon(vec![point(0, 0), point(7, 5)]), 8, 6);
+        assert!(covered(&line).is_empty());
+    }
+
+    // The even-odd rule leaves the pentagon in the middle of a star empty.
+    #[test]
+    fn star_polygon_has_an_empty_center() {
+        let tips: Vec<Point> = (0..5)
+            .map(|k| {
+                let angle = (2 * k % 5) as f64 * 72f64.to_radians() - 90f64.to_radians();
+                point(
+                    (20.0 + 18.0 * angle.cos()).round() as i16,
+                    (20.0 + 18.0 * angle.sin()).round() as i16,
+                )
+            })
+            .collect();
+        let mask = Mask::from_shape(&Shape::Polygon(tips), 40, 40);
+        let at = |x: u32, y: u32| mask.values()[(y * 40 + x) as usize];
+        assert_eq!(at(20, 20), 0);
+        assert_eq!(at(20, 6), 255);
+        assert_eq!(at(1, 1), 0);
+    }
+
+    #[test]
+    fn ellipse_fits_inside_its_box() {
+        let (from, to) = (point(2, 4), point(18, 12));
+        let mask = Mask::from_shape(&Shape::Ellipse { from, to }, 20, 16);
+        let pixels = covered(&mask);
+        assert!(pixels.contains(&(10, 8)));
+        assert!(!pixels.contains(&(2, 4)) && !pixels.contains(&(17, 11)));
+        assert!(pixels
+            .iter()
+            .all(|&(x, y)| (2..18).contains(&x) && (4..12).contains(&y)));
+        // Mirrored across both axes of the box.
+        for &(x, y) in &pixels {
+            assert!(pixels.contains(&(19 - x, y)) && pixels.contains(&(x, 15 - y)));
+        }
+    }
+
+    #[test]
+    fn feathering_softens_only_the_border() {
+        let shape = Shape::Rectangle {
+            from: point(5, 5),
+            to: point(15, 15),
+        };
+        let mut mask = Mask::from_shape(&shape, 20, 20);
+        mask.feather(2);
+        let at = |x: u32, y: u32| mask.values()[(y * 20 + x) as usize];
+        assert_eq!(at(10, 10), 255);
+        assert_eq!(at(0, 0), 0);
+        assert!((1..255).contains(&at(5, 10)));
+        assert!(at(4, 10) < at(5, 10) && at(5, 10) < at(6, 10));
+    }
+
+    #[test]
+    fn blend_applies_the_processed_pixels_inside_the_selection() {
+        let shape = Shape::Rectangle {
+            from: point(0, 0),
+            to: point(1, 1),
+        };
+        let mut mask = Mask::from_shape(&shape, 2, 1);
+        let original = RawImage::from_raw(vec![10, 20, 30, 255, 40, 50, 60, 255], 2, 1);
+        let processed = RawImage::from_raw(vec![200, 210, 220, 255, 230, 240, 250, 255], 2, 1);
+
+        let mut inside = processed.clone();
+        mask.blend(&original, &mut inside).unwrap();
+        assert_eq!(inside.pixels(), [200, 210, 220, 255, 40, 50, 60, 255]);
+
+        mask.invert();
+        let mut outside = processed.clone();
+        mask.blend(&original, &mut outside).unwrap();
+        assert_eq!(outside.pixels(), [10, 20, 30, 255, 230, 240, 250, 255]);
+
+        let mut wrong_size = RawImage::from_raw(vec![0; 4], 1, 1);
+        assert!(mask.blend(&original, &mut wrong_size).is_err());
+    }
+
+    #[test]
+    fn feathering_an_empty_mask_does_nothing() {
+        for (width, height) in [(0, 7), (7, 0), (0, 0)] {
+            let mut mask = Mask::new(width, height);
+            mask.feather(3);
+            assert!(mask.values().is_empty());
+        }
+    }
+}
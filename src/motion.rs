@@ -0,0 +1,168 @@
+use crate::binary::{luma, BinaryImage};
+use crate::components::{self, Region};
+use crate::constants::{
+    MOTION_CLOSE_RADIUS, MOTION_MIN_AREA, MOTION_OPEN_RADIUS, MOTION_THRESHOLD,
+};
+use crate::image::RawImage;
+
+// Compares each frame with the one before it: the luma difference is
+// thresholded, opened to drop noise, closed to merge the parts of one moving
+// object and split into connected regions.
+pub struct MotionDetector {
+    previous: Option<RawImage>,
+    threshold: u8,
+    min_area: usize,
+}
+
+impl MotionDetector {
+    pub fn new() -> Self {
+        MotionDetector {
+            previous: None,
+            threshold: MOTION_THRESHOLD,
+            min_area: MOTION_MIN_AREA,
+        }
+    }
+
+    pub fn detect(&mut self, frame: &RawImage) -> Vec<Region> {
+        let previous = match self.previous.replace(frame.clone()) {
+            Some(previous)
+                if previous.width() == frame.width() && previous.height() == frame.height() =>
+            {
+                previous
+            }
+            _ => return vec![],
+        };
+
+        let threshold = self.threshold as i32;
+        let values = frame
+            .pixels()
+            .chunks_exact(4)
+            .zip(previous.pixels().chunks_exact(4))
+            .map(|(now, before)| (luma(now) - luma(before)).abs() > threshold)
+            .collect();
+        let difference = BinaryImage::from_values(values, frame.width(), frame.height());
+        let cleaned = difference
+            .open(MOTION_OPEN_RADIUS)
+            .close(MOTION_CLOSE_RADIUS);
+        components::regions(&cleaned)
+            .into_iter()
+            .filter(|region| region.area >= self.min_area)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::Rect;
+    use crate::constants::SOURCE_OBJECT_COLOR;
+    use crate::frames::FrameSource;
+
+    fn gray_scene() -> RawImage {
+        RawImage::from_raw([90, 90, 90, 255].repeat(160 * 120), 160, 120)
+    }
+
+    // Every tenth frame of a moving source, so that the object travels far
+    // enough between two of them.
+    fn moving_frames(count: usize) -> Vec<RawImage> {
+        let scene = gray_scene();
+        let mut source = FrameSource::new();
+        source.next_mode();
+        (0..10 * count)
+            .map(|_| source.next_frame(&scene).unwrap())
+            .step_by(10)
+            .collect()
+    }
+
+    fn object_bounds(frame: &RawImage) -> Rect {
+        let width = frame.width() as usize;
+        let (mut left, mut top, mut right, mut bottom) = (usize::MAX, usize::MAX, 0, 0);
+        for (index, pixel) in frame.pixels().chunks_exact(4).enumerate() {
+            if pixel == SOURCE_OBJECT_COLOR {
+                let (x, y) = (index % width, index / width);
+                left = left.min(x);
+                top = top.min(y);
+                right = right.max(x + 1);
+                bottom = bottom.max(y + 1);
+            }
+        }
+        Rect {
+            x: left as u32,
+            y: top as u32,
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+        }
+    }
+
+    fn contains(outer: &Rect, inner: &Rect) -> bool {
+        inner.x >= outer.x
+            && inner.y >= outer.y
+            && inner.x + inner.width <= outer.x + outer.width
+            && inner.y + inner.height <= outer.y + outer.height
+    }
+
+    // Closing may fill the gap between an object and the border, so the
+    // bounds are grown by its radius.
+    fn swept(a: &Rect, b: &Rect) -> Rect {
+        let grow = MOTION_CLOSE_RADIUS;
+        let (x, y) = (
+            a.x.min(b.x).saturating_sub(grow),
+            a.y.min(b.y).saturating_sub(grow),
+        );
+        Rect {
+            x,
+            y,
+            width: (a.x + a.width).max(b.x + b.width) + grow - x,
+            height: (a.y + a.height).max(b.y + b.height) + grow - y,
+        }
+    }
+
+    #[test]
+    fn first_frame_and_still_frames_have_no_motion() {
+        let mut detector = MotionDetector::new();
+        let scene = gray_scene();
+        assert!(detector.detect(&scene).is_empty());
+        assert!(detector.detect(&scene).is_empty());
+    }
+
+    #[test]
+    fn moving_object_is_found_where_it_was_and_went() {
+        let frames = moving_frames(5);
+        let mut detector = MotionDetector::new();
+        detector.detect(&frames[0]);
+        for pair in frames.windows(2) {
+            let regions = detector.detect(&pair[1]);
+            assert!(!regions.is_empty());
+            let swept = swept(&object_bounds(&pair[0]), &object_bounds(&pair[1]));
+            for region in regions {
+                assert!(
+                    contains(&swept, &region.bounds),
+                    "{:?} outside {:?}",
+                    region.bounds,
+                    swept
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn differences_smaller_than_the_threshold_are_ignored() {
+        let mut detector = MotionDetector::new();
+        detector.detect(&gray_scene());
+        let mut brighter = gray_scene();
+        for pixel in brighter.pixels_mut().chunks_exact_mut(4) {
+            pixel[..3]
+                .iter_mut()
+                .for_each(|value| *value += MOTION_THRESHOLD / 2);
+        }
+        assert!(detector.detect(&brighter).is_empty());
+    }
+
+    #[test]
+    fn a_change_of_size_restarts_the_comparison() {
+        let mut detector = MotionDetector::new();
+        detector.detect(&gray_scene());
+        let other = RawImage::from_raw([255, 255, 255, 255].repeat(80 * 60), 80, 60);
+        assert!(detector.detect(&other).is_empty());
+    }
+}
@@ -0,0 +1,303 @@
+use crate::constants::{FAST_THRESHOLD, ORB_BLUR_SIGMA, ORB_PATCH_RADIUS, ORB_SEED};
+use crate::features::{self, Keypoint};
+use crate::gray::GrayImage;
+use crate::random::Xorshift;
+
+const BITS: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Descriptor([u64; BITS / 64]);
+
+impl Descriptor {
+    pub fn distance(&self, other: &Descriptor) -> u32 {
+        self.0
+            .iter()
+            .zip(other.0)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Feature {
+    pub keypoint: Keypoint,
+    pub descriptor: Descriptor,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Match {
+    pub query: usize,
+    pub train: usize,
+    pub distance: u32,
+}
+
+// ORB: FAST keypoints ranked by their Harris response, each described by
+// BRIEF comparisons rotated to the keypoint's orientation.
+pub fn orb(gray: &GrayImage, max_count: usize) -> Vec<Feature> {
+    let responses = features::harris_responses(gray);
+    let width = gray.width() as usize;
+    let border = ORB_PATCH_RADIUS as f32 + 1.0;
+    let mut keypoints: Vec<Keypoint> = features::fast(gray, FAST_THRESHOLD, usize::MAX)
+        .into_iter()
+        .filter(|keypoint| {
+            keypoint.x >= border
+                && keypoint.y >= border
+                && keypoint.x < gray.width() as f32 - border
+                && keypoint.y < gray.height() as f32 - border
+        })
+        .map(|keypoint| Keypoint {
+            response: responses[keypoint.y as usize * width + keypoint.x as usize],
+            ..keypoint
+        })
+        .collect();
+    keypoints.sort_by(|a, b| b.response.total_cmp(&a.response));
+    keypoints.truncate(max_count);
+    describe(gray, &keypoints)
+}
+
+pub fn describe(gray: &GrayImage, keypoints: &[Keypoint]) -> Vec<Feature> {
+    let smooth = gray.blur(ORB_BLUR_SIGMA);
+    let pattern = pattern();
+    keypoints
+        .iter()
+        .map(|&keypoint| {
+            let (x, y) = (keypoint.x.round() as i32, keypoint.y.round() as i32);
+            // Pairs are rotated by the angle of the intensity centroid.
+            let angle = orientation(gray, x, y);
+            let (cos, sin) = (angle.cos(), angle.sin());
+            let rotate = |(px, py): (f32, f32)| {
+                let (rx, ry) = (px * cos - py * sin, px * sin + py * cos);
+                smooth.get(x + rx.round() as i32, y + ry.round() as i32)
+            };
+            let mut bits = [0u64; BITS / 64];
+            for (i, &(a, b)) in pattern.iter().enumerate() {
+                if rotate(a) < rotate(b) {
+                    bits[i / 64] |= 1 << (i % 64);
+                }
+            }
+            Feature {
+                keypoint,
+                descriptor: Descriptor(bits),
+            }
+        })
+        .collect()
+}
+
+// The intensity centroid of the circular patch.
+fn orientation(gray: &GrayImage, x: i32, y: i32) -> f32 {
+    let radius = ORB_PATCH_RADIUS;
+    let (mut m10, mut m01) = (0.0, 0.0);
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            if dx * dx + dy * dy <= radius * radius {
+                let value = gray.get(x + dx, y + dy);
+                m10 += dx as f32 * value;
+                m01 += dy as f32 * value;
+            }
+        }
+    }
+    m01.atan2(m10)
+}
+
+// Test pairs drawn from an isotropic gaussian over the patch, as in BRIEF's
+// second sampling strategy, and kept inside the disc so they stay inside the
+// patch once rotated.
+fn pattern() -> Vec<((f32, f32), (f32, f32))> {
+    let mut random = Xorshift::new(ORB_SEED);
+    let radius = ORB_PATCH_RADIUS as f32;
+    let sigma = 2.0 * radius / 5.0;
+    let mut point = || loop {
+        // Box-Muller.
+        let (u, v) = (random.next_f64().max(f64::MIN_POSITIVE), random.next_f64());
+        let length = (-2.0 * u.ln()).sqrt() as f32 * sigma;
+        let angle = (v * std::f64::consts::TAU) as f32;
+        let (x, y) = (length * angle.cos(), length * angle.sin());
+        if x * x + y * y <= radius * radius {
+            return (x, y);
+        }
+    };
+    (0..BITS).map(|_| (point(), point())).collect()
+}
+
+// Brute force Hamming matching. A match is kept when its distance is below
+// ratio times the second best, and with the cross check only when the train
+// feature's best match is the query feature too.
+pub fn match_features(
+    query: &[Feature],
+    train: &[Feature],
+    ratio: f32,
+    cross_check: bool,
+) -> Vec<Match> {
+    let best_two = |feature: &Feature, candidates: &[Feature]| {
+        let mut best = (usize::MAX, u32::MAX);
+        let mut second = u32::MAX;
+        for (index, candidate) in candidates.iter().enumerate() {
+            let distance = feature.descriptor.distance(&candidate.descriptor);
+            if distance < best.1 {
+                second = best.1;
+                best = (index, distance);
+            } else if distance < second {
+                second = distance;
+            }
+        }
+        (best, second)
+    };
+
+    query
+        .iter()
+        .enumerate()
+        .filter_map(|(index, feature)| {
+            let ((train_index, distance), second) = best_two(feature, train);
+            if train_index == usize::MAX {
+                return None;
+            }
+            if second != u32::MAX && distance as f32 >= ratio * second as f32 {
+                return None;
+            }
+            if cross_check && best_two(&train[train_index], query).0 .0 != index {
+                return None;
+            }
+            Some(Match {
+                query: index,
+                train: train_index,
+                distance,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{
+        MATCH_RATIO, ORB_FEATURES, RANSAC_CONFIDENCE, RANSAC_MAX_ITERATIONS, RANSAC_THRESHOLD,
+    };
+    use crate::homography::{self, Correspondence};
+
+    const SHIFT: (i32, i32) = (9, -5);
+
+    // Rectangles of random brightness scattered over the plane, sampled with
+    // the view moved by an offset.
+    fn scene(offset: (i32, i32)) -> GrayImage {
+        let mut random = Xorshift::new(11);
+        let rectangles: Vec<_> = (0..60)
+            .map(|_| {
+                let (x, y) = (random.below(200) as i32 - 20, random.below(160) as i32 - 20);
+                let (w, h) = (6 + random.below(20) as i32, 6 + random.below(20) as i32);
+                (x, y, w, h, 40.0 + random.below(200) as f32)
+            })
+            .collect();
+        let (width, height) = (160, 120);
+        let values = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x - offset.0, y - offset.1)))
+            .map(|(x, y)| {
+                rectangles
+                    .iter()
+                    .rev()
+                    .find(|&&(rx, ry, w, h, _)| {
+                        (rx..rx + w).contains(&x) && (ry..ry + h).contains(&y)
+                    })
+                    .map_or(20.0, |rectangle| rectangle.4)
+            })
+            .collect();
+        GrayImage::new(values, width as u32, height as u32)
+    }
+
+    fn feature(bits: [u64; BITS / 64]) -> Feature {
+        Feature {
+            keypoint: Keypoint {
+                x: 0.0,
+                y: 0.0,
+                response: 0.0,
+            },
+            descriptor: Descriptor(bits),
+        }
+    }
+
+    #[test]
+    fn distance_counts_the_differing_bits() {
+        let a = Descriptor([0b1011, 0, u64::MAX, 1]);
+        let b = Descriptor([0b0001, 0, 0, 1]);
+        assert_eq!(a.distance(&a), 0);
+        assert_eq!(a.distance(&b), 2 + 64);
+    }
+
+    #[test]
+    fn ambiguous_and_one_sided_matches_are_dropped() {
+        let query = [feature([0, 0, 0, 0]), feature([u64::MAX, 0, 0, 0])];
+        // The first query feature is as close to both of these, the second
+        // has a clear best.
+        let train = [
+            feature([0b11, 0, 0, 0]),
+            feature([0b1100, 0, 0, 0]),
+            feature([u64::MAX, 1, 0, 0]),
+        ];
+        let matches = match_features(&query, &train, MATCH_RATIO, false);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(
+            (matches[0].query, matches[0].train, matches[0].distance),
+            (1, 2, 1)
+        );
+
+        // Without the ratio test the first feature picks train 0, whose own
+        // best match it is, and the cross check keeps both.
+        let matches = match_features(&query, &train, f32::INFINITY, true);
+        assert_eq!(matches.len(), 2);
+        // A third query feature as close to train 2 loses the tie to the
+        // second, which only the cross check notices.
+        let query = [query[0], query[1], feature([u64::MAX, 3, 0, 0])];
+        assert_eq!(
+            match_features(&query, &train, f32::INFINITY, false).len(),
+            3
+        );
+        assert_eq!(match_features(&query, &train, f32::INFINITY, true).len(), 2);
+    }
+
+    #[test]
+    fn shifted_view_matches_and_gives_the_shift_back() {
+        let first = orb(&scene((0, 0)), ORB_FEATURES);
+        let second = orb(&scene(SHIFT), ORB_FEATURES);
+        assert!(first.len() > 30, "{} features", first.len());
+        let matches = match_features(&second, &first, MATCH_RATIO, true);
+        let correspondences: Vec<Correspondence> = matches
+            .iter()
+            .map(|m| {
+                let (to, from) = (second[m.query].keypoint, first[m.train].keypoint);
+                ((from.x as f64, from.y as f64), (to.x as f64, to.y as f64))
+            })
+            .collect();
+        let exact = correspondences
+            .iter()
+            .filter(|((x, y), (u, v))| {
+                (u - x - SHIFT.0 as f64).abs() < 0.5 && (v - y - SHIFT.1 as f64).abs() < 0.5
+            })
+            .count();
+        assert!(
+            exact * 10 >= correspondences.len() * 9,
+            "{} of {}",
+            exact,
+            correspondences.len()
+        );
+
+        let estimate = homography::ransac(
+            &correspondences,
+            RANSAC_THRESHOLD,
+            RANSAC_CONFIDENCE,
+            RANSAC_MAX_ITERATIONS,
+        )
+        .unwrap();
+        for point in [(20.0, 20.0), (140.0, 30.0), (80.0, 100.0)] {
+            let (x, y) = estimate.homography.apply(point).unwrap();
+            assert!(
+                (x - point.0 - SHIFT.0 as f64).abs() < 0.5,
+                "{:?}",
+                estimate.homography
+            );
+            assert!(
+                (y - point.1 - SHIFT.1 as f64).abs() < 0.5,
+                "{:?}",
+                estimate.homography
+            );
+        }
+    }
+}
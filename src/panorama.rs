@@ -0,0 +1,388 @@
+use crate::constants::{
+    MATCH_RATIO, PANORAMA_FEATURES, PANORAMA_MAX_SIDE, PANORAMA_MIN_INLIERS, PYRAMID_LEVELS,
+    RANSAC_CONFIDENCE, RANSAC_MAX_ITERATIONS, RANSAC_THRESHOLD,
+};
+use crate::gray::GrayImage;
+use crate::homography::{self, Homography};
+use crate::image::RawImage;
+use crate::mask::Mask;
+use crate::orb::{self, Feature};
+use crate::pyramid;
+use anyhow::{anyhow, bail, Result};
+
+// Homographies between every pair that shares enough inliers, mapping the
+// first image of the pair onto the second.
+struct Edge {
+    from: usize,
+    to: usize,
+    homography: Homography,
+    inliers: usize,
+}
+
+// Every image is matched against every other one, and the best connected
+// image becomes the reference plane. The others are chained to it along the
+// strongest matches, warped into a common canvas and blended band by band.
+pub fn stitch(images: &[RawImage]) -> Result<RawImage> {
+    if images.len() < 2 {
+        bail!("A panorama needs at least two images, got {}", images.len());
+    }
+    let features: Vec<Vec<Feature>> = images
+        .iter()
+        .map(|image| orb::orb(&GrayImage::from_raw(image), PANORAMA_FEATURES))
+        .collect();
+    let edges = pairwise_homographies(&features);
+    let transforms = chain(images.len(), &edges);
+
+    let placed: Vec<(usize, Homography)> = transforms
+        .iter()
+        .enumerate()
+        .filter_map(|(index, transform)| transform.map(|transform| (index, transform)))
+        .collect();
+    if placed.len() < 2 {
+        bail!(
+            "None of the {} images overlap enough to be stitched",
+            images.len()
+        );
+    }
+    for (index, transform) in transforms.iter().enumerate() {
+        if transform.is_none() {
+            log!(
+                "image {} does not overlap the others and is left out",
+                index + 1
+            );
+        }
+    }
+
+    let corners: Vec<(f64, f64)> = placed
+        .iter()
+        .flat_map(|&(index, transform)| {
+            let (width, height) = (images[index].width() as f64, images[index].height() as f64);
+            [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)]
+                .into_iter()
+                .filter_map(move |corner| transform.apply(corner))
+        })
+        .collect();
+    let (min_x, min_y, max_x, max_y) = corners.iter().fold(
+        (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+        |(min_x, min_y, max_x, max_y), &(x, y)| {
+            (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+        },
+    );
+    let (width, height) = ((max_x - min_x).ceil(), (max_y - min_y).ceil());
+    if !(width.is_finite() && height.is_finite()) || width.max(height) > PANORAMA_MAX_SIDE as f64 {
+        bail!("The images are too distorted to be stitched into one panorama");
+    }
+    let (width, height) = (width as u32, height as u32);
+    let shift = Homography([1.0, 0.0, -min_x, 0.0, 1.0, -min_y, 0.0, 0.0, 1.0]);
+
+    let mut warped: Vec<(RawImage, (f64, f64))> = placed
+        .iter()
+        .map(|&(index, transform)| {
+            let transform = transform.then(&shift);
+            let image = &images[index];
+            let center = (image.width() as f64 / 2.0, image.height() as f64 / 2.0);
+            let center = transform.apply(center).unwrap_or_default();
+            (homography::warp(image, &transform, width, height), center)
+        })
+        .collect();
+    let (mut panorama, first_center) = warped.remove(0);
+    let mut centers = vec![first_center];
+    for (image, center) in warped {
+        panorama = blend_seam(&panorama, &centers, &image, center)?;
+        centers.push(center);
+    }
+    log!(
+        "stitched {} of {} images into {}x{}",
+        placed.len(),
+        images.len(),
+        width,
+        height
+    );
+    Ok(panorama)
+}
+
+fn pairwise_homographies(features: &[Vec<Feature>]) -> Vec<Edge> {
+    let mut edges = vec![];
+    for from in 0..features.len() {
+        for to in from + 1..features.len() {
+            let correspondences: Vec<_> =
+                orb::match_features(&features[from], &features[to], MATCH_RATIO, true)
+                    .into_iter()
+                    .map(|m| {
+                        let (a, b) = (
+                            features[from][m.query].keypoint,
+                            features[to][m.train].keypoint,
+                        );
+                        ((a.x as f64, a.y as f64), (b.x as f64, b.y as f64))
+                    })
+                    .collect();
+            let Some(estimate) = homography::ransac(
+                &correspondences,
+                RANSAC_THRESHOLD,
+                RANSAC_CONFIDENCE,
+                RANSAC_MAX_ITERATIONS,
+            ) else {
+                continue;
+            };
+            let inliers = estimate.inlier_count();
+            log!(
+                "images {} and {}: {} matches, {} inliers",
+                from + 1,
+                to + 1,
+                correspondences.len(),
+                inliers
+            );
+            if inliers >= PANORAMA_MIN_INLIERS {
+                edges.push(Edge {
+                    from,
+                    to,
+                    homography: estimate.homography,
+                    inliers,
+                });
+            }
+        }
+    }
+    edges
+}
+
+// Maximum spanning tree grown from the reference, so that every image
+// reaches it through the most reliable homographies. Images that stay out of
+// the tree have no transform.
+fn chain(count: usize, edges: &[Edge]) -> Vec<Option<Homography>> {
+    let mut transforms = vec![None; count];
+    let reference = (0..count)
+        .max_by_key(|&index| {
+            edges
+                .iter()
+                .filter(|edge| edge.from == index || edge.to == index)
+                .map(|edge| edge.inliers)
+                .sum::<usize>()
+        })
+        .unwrap_or(0);
+    transforms[reference] = Some(Homography::identity());
+    loop {
+        // A placed image and a new one, with the new one mapped onto the
+        // placed one.
+        let next = edges
+            .iter()
+            .filter_map(|edge| {
+                let link = match (transforms[edge.from], transforms[edge.to]) {
+                    (Some(placed), None) => (edge.to, edge.homography.inverse()?, placed),
+                    (None, Some(placed)) => (edge.from, edge.homography, placed),
+                    _ => return None,
+                };
+                Some((edge.inliers, link))
+            })
+            .max_by_key(|(inliers, _)| *inliers);
+        match next {
+            Some((_, (index, onto_placed, placed))) => {
+                transforms[index] = Some(onto_placed.then(&placed));
+            }
+            None => return transforms,
+        }
+    }
+}
+
+// Where the images overlap, each pixel goes to the image whose center is
+// nearest, and the seam between them is hidden by a multi-band blend. Pixels
+// covered by only one of them are copied into the other first, so nothing
+// bleeds in from the empty canvas.
+fn blend_seam(
+    panorama: &RawImage,
+    centers: &[(f64, f64)],
+    image: &RawImage,
+    center: (f64, f64),
+) -> Result<RawImage> {
+    let (width, height) = (panorama.width(), panorama.height());
+    let mut base = panorama.clone();
+    let mut overlay = image.clone();
+    let mut mask = Mask::new(width, height);
+    let distance = |(x, y): (f64, f64), (cx, cy): (f64, f64)| (x - cx).hypot(y - cy);
+    for (index, ((weight, below), above)) in mask
+        .values_mut()
+        .iter_mut()
+        .zip(base.pixels_mut().chunks_exact_mut(4))
+        .zip(overlay.pixels_mut().chunks_exact_mut(4))
+        .enumerate()
+    {
+        let point = ((index as u32 % width) as f64, (index as u32 / width) as f64);
+        match (below[3] > 0, above[3] > 0) {
+            (true, true) => {
+                let nearest = centers
+                    .iter()
+                    .map(|&other| distance(point, other))
+                    .fold(f64::MAX, f64::min);
+                if distance(point, center) < nearest {
+                    *weight = 255;
+                }
+            }
+            (true, false) => above.copy_from_slice(below),
+            (false, true) => {
+                below.copy_from_slice(above);
+                *weight = 255;
+            }
+            (false, false) => {}
+        }
+    }
+    pyramid::blend(&base, &overlay, &mask, PYRAMID_LEVELS)
+        .map_err(|err| anyhow!("Could not blend the seam: {:#}", err))
+}
+
+// Scaled down, if needed, to fit inside the given size, and put at the top
+// left of a frame of exactly that size. Large reductions go through the
+// gaussian pyramid first so that the result does not alias.
+pub fn place(image: &RawImage, width: u32, height: u32) -> RawImage {
+    let scale = scaling(image, width, height);
+    let (source, scale) = prefilter(image, scale);
+    let scaling = Homography([scale, 0.0, 0.0, 0.0, scale, 0.0, 0.0, 0.0, 1.0]);
+    homography::warp(&source, &scaling, width, height)
+}
+
+// Scaled down, if needed, to fit inside the given size.
+pub fn shrink(image: &RawImage, width: u32, height: u32) -> RawImage {
+    let scale = scaling(image, width, height);
+    if scale >= 1.0 {
+        return image.clone();
+    }
+    let size = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+    place(image, size(image.width()), size(image.height()))
+}
+
+fn scaling(image: &RawImage, width: u32, height: u32) -> f64 {
+    let horizontal = width as f64 / image.width().max(1) as f64;
+    let vertical = height as f64 / image.height().max(1) as f64;
+    horizontal.min(vertical).min(1.0)
+}
+
+// Each pyramid level halves the size, so the remaining scale stays within a
+// factor of two.
+fn prefilter(image: &RawImage, scale: f64) -> (RawImage, f64) {
+    let halvings = (1.0 / scale).log2().floor().max(0.0) as usize;
+    if halvings == 0 {
+        return (image.clone(), scale);
+    }
+    let levels = pyramid::gaussian(image, halvings + 1);
+    let level = levels[levels.len() - 1].to_raw();
+    let scale = scale * image.width() as f64 / level.width() as f64;
+    (level, scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Xorshift;
+
+    // Discs of random colors over a dark backdrop, wider than one view.
+    fn scene(width: u32, height: u32) -> RawImage {
+        let mut random = Xorshift::new(3);
+        let discs: Vec<_> = (0..90)
+            .map(|_| {
+                let center = (
+                    random.below(width as usize) as f64,
+                    random.below(height as usize) as f64,
+                );
+                let radius = 4.0 + random.below(10) as f64;
+                let color = [0, 0, 0].map(|_| 30 + random.below(220) as u8);
+                (center, radius, color)
+            })
+            .collect();
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let color = discs
+                    .iter()
+                    .rev()
+                    .find(|((cx, cy), radius, _)| (x as f64 - cx).hypot(y as f64 - cy) <= *radius)
+                    .map_or([10, 10, 10], |disc| disc.2);
+                pixels.extend(color);
+                pixels.push(255);
+            }
+        }
+        RawImage::from_raw(pixels, width, height)
+    }
+
+    fn crop(image: &RawImage, left: u32, width: u32) -> RawImage {
+        let pixels = image
+            .pixels()
+            .chunks_exact(4 * image.width() as usize)
+            .flat_map(|row| &row[4 * left as usize..4 * (left + width) as usize])
+            .copied()
+            .collect();
+        RawImage::from_raw(pixels, width, image.height())
+    }
+
+    fn shift(dx: f64) -> Homography {
+        Homography([1.0, 0.0, dx, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0])
+    }
+
+    #[test]
+    fn shrink_keeps_the_aspect_and_small_images() {
+        let image = RawImage::from_raw([50, 60, 70, 255].repeat(200 * 100), 200, 100);
+        let small = shrink(&image, 50, 50);
+        assert_eq!((small.width(), small.height()), (50, 25));
+        assert!(small
+            .pixels()
+            .chunks_exact(4)
+            .all(|pixel| pixel == [50, 60, 70, 255]));
+        assert_eq!(shrink(&image, 400, 400).pixels(), image.pixels());
+    }
+
+    #[test]
+    fn placed_image_fills_the_top_left_of_the_frame() {
+        let image = RawImage::from_raw([50, 60, 70, 255].repeat(200 * 100), 200, 100);
+        let placed = place(&image, 60, 60);
+        assert_eq!((placed.width(), placed.height()), (60, 60));
+        let at = |x: usize, y: usize| &placed.pixels()[4 * (y * 60 + x)..4 * (y * 60 + x) + 4];
+        assert_eq!(at(10, 10), [50, 60, 70, 255]);
+        assert_eq!(at(10, 50)[3], 0);
+    }
+
+    // Image 1 has the most inliers and becomes the reference. Image 3 shares
+    // nothing with the others.
+    #[test]
+    fn chain_follows_the_strongest_edges_to_the_reference() {
+        let edge = |from, to, dx, inliers| Edge {
+            from,
+            to,
+            homography: shift(dx),
+            inliers,
+        };
+        let edges = [
+            edge(0, 1, 10.0, 40),
+            edge(1, 2, 20.0, 30),
+            edge(0, 2, 99.0, 15),
+        ];
+        let transforms = chain(4, &edges);
+        assert_eq!(transforms[1], Some(Homography::identity()));
+        assert_eq!(transforms[0], Some(shift(10.0)));
+        assert_eq!(transforms[2], Some(shift(-20.0)));
+        assert_eq!(transforms[3], None);
+    }
+
+    #[test]
+    fn too_few_images_are_rejected() {
+        let image = scene(40, 40);
+        assert!(stitch(&[]).is_err());
+        assert!(stitch(&[image]).is_err());
+    }
+
+    #[test]
+    fn overlapping_views_are_stitched_back_into_the_scene() {
+        let whole = scene(240, 120);
+        let views = [crop(&whole, 0, 160), crop(&whole, 80, 160)];
+        let panorama = stitch(&views).unwrap();
+        assert!(panorama.width().abs_diff(240) <= 1 && panorama.height().abs_diff(120) <= 1);
+
+        let (mut total, mut count) = (0.0, 0.0);
+        for y in 10..110 {
+            for x in 10..230 {
+                let (a, b) = (4 * (y * 240 + x), 4 * (y * panorama.width() as usize + x));
+                for c in 0..3 {
+                    total += whole.pixels()[a + c].abs_diff(panorama.pixels()[b + c]) as f64;
+                    count += 1.0;
+                }
+            }
+        }
+        assert!(total / count < 3.0, "mean difference {}", total / count);
+    }
+}
@@ -0,0 +1,414 @@
+use crate::adjust::{self, Levels, Lut, WhiteBalance};
+use crate::browser;
+use crate::constants::{FRAME_HISTORY_DEPTH, LUT_STRENGTH, SLOW_OPERATION_MS};
+use crate::cube::{CubeLut, Interpolation};
+use crate::denoise;
+use crate::dither::{self, DitherLevels, DitherMethod};
+use crate::image::RawImage;
+use crate::mask::Mask;
+use crate::quantize::{self, QuantizeMethod};
+use crate::temporal::{FrameHistory, TemporalFilter};
+use anyhow::Result;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Operation {
+    Solarize,
+    Grayscale,
+    AlterRedChannel(i16),
+    Median(u32),
+    Bilateral {
+        radius: u32,
+        sigma_space: f32,
+        sigma_range: f32,
+    },
+    NonLocalMeans {
+        patch_radius: u32,
+        search_radius: u32,
+        strength: f32,
+    },
+    Quantize {
+        method: QuantizeMethod,
+        colors: usize,
+    },
+    Dither {
+        method: DitherMethod,
+        target: DitherTarget,
+        serpentine: bool,
+    },
+    Levels([Levels; 3]),
+    Curves(&'static [(u8, u8)]),
+    Gamma(f32),
+    Exposure(f32),
+    BrightnessContrast {
+        brightness: f32,
+        contrast: f32,
+    },
+    Saturation(f32),
+    Vibrance(f32),
+    WhiteBalance {
+        temperature: f32,
+        tint: f32,
+    },
+    AutoWhiteBalance(WhiteBalance),
+    Temporal(TemporalFilter),
+}
+
+// What a dithering step reduces the image to, a palette is extracted from
+// the frame itself.
+#[derive(Clone, Copy, Debug)]
+pub enum DitherTarget {
+    BitDepth(u8),
+    Palette(QuantizeMethod, usize),
+}
+
+impl Operation {
+    // Steps that map every channel value on its own, as a lookup table.
+    pub fn lut(&self) -> Option<Lut> {
+        match self {
+            Operation::Levels(levels) => Some(Lut::levels(levels)),
+            Operation::Curves(points) => Some(Lut::curve(points)),
+            Operation::Gamma(gamma) => Some(Lut::gamma(*gamma)),
+            Operation::Exposure(stops) => Some(Lut::exposure(*stops)),
+            Operation::BrightnessContrast {
+                brightness,
+                contrast,
+            } => Some(Lut::brightness_contrast(*brightness, *contrast)),
+            Operation::WhiteBalance { temperature, tint } => {
+                Some(Lut::white_balance(*temperature, *tint))
+            }
+            _ => None,
+        }
+    }
+
+    // Every step gets its own frame history, only temporal filters use it.
+    pub fn apply(&self, image: &mut RawImage, history: &mut FrameHistory) {
+        match self {
+            Operation::Solarize => image.solarize(),
+            Operation::Grayscale => image.grayscale(),
+            Operation::AlterRedChannel(amt) => image.alter_red_channel(*amt),
+            Operation::Median(radius) => *image = denoise::median(image, *radius),
+            Operation::Bilateral {
+                radius,
+                sigma_space,
+                sigma_range,
+            } => *image = denoise::bilateral(image, *radius, *sigma_space, *sigma_range),
+            Operation::NonLocalMeans {
+                patch_radius,
+                search_radius,
+                strength,
+            } => *image = denoise::non_local_means(image, *patch_radius, *search_radius, *strength),
+            Operation::Quantize { method, colors } => {
+                *image = quantize::quantize(image, *colors, *method).0
+            }
+            Operation::Dither {
+                method,
+                target,
+                serpentine,
+            } => {
+                *image = match target {
+                    DitherTarget::BitDepth(bits) => {
+                        dither::dither(image, &DitherLevels::BitDepth(*bits), *method, *serpentine)
+                    }
+                    DitherTarget::Palette(quantize_method, colors) => {
+                        let palette = quantize::palette(image, *colors, *quantize_method);
+                        dither::dither(
+                            image,
+                            &DitherLevels::Palette(&palette),
+                            *method,
+                            *serpentine,
+                        )
+                    }
+                }
+            }
+            Operation::Levels(_)
+            | Operation::Curves(_)
+            | Operation::Gamma(_)
+            | Operation::Exposure(_)
+            | Operation::BrightnessContrast { .. }
+            | Operation::WhiteBalance { .. } => {
+                if let Some(lut) = self.lut() {
+                    lut.apply(image)
+                }
+            }
+            Operation::Saturation(amount) => adjust::saturation(image, *amount),
+            Operation::Vibrance(amount) => adjust::vibrance(image, *amount),
+            Operation::AutoWhiteBalance(method) => {
+                Lut::auto_white_balance(image, *method).apply(image)
+            }
+            Operation::Temporal(filter) => filter.apply(image, history),
+        }
+    }
+}
+
+// Effects the UI cycles through, each one a sequence of pipeline steps.
+pub const EFFECTS: &[(&str, &[Operation])] = &[
+    (
+        "Effect: solarize",
+        &[Operation::Solarize, Operation::Grayscale],
+    ),
+    ("Effect: red shift", &[Operation::AlterRedChannel(24)]),
+    ("Effect: median", &[Operation::Median(3)]),
+    (
+        "Effect: bilateral",
+        &[Operation::Bilateral {
+            radius: 3,
+            sigma_space: 3.0,
+            sigma_range: 20.0,
+        }],
+    ),
+    (
+        "Effect: non-local means",
+        &[Operation::NonLocalMeans {
+            patch_radius: 1,
+            search_radius: 3,
+            strength: 12.0,
+        }],
+    ),
+    (
+        "Effect: posterize",
+        &[Operation::Quantize {
+            method: QuantizeMethod::KMeans,
+            colors: 8,
+        }],
+    ),
+    (
+        "Effect: Floyd-Steinberg",
+        &[Operation::Dither {
+            method: DitherMethod::FloydSteinberg,
+            target: DitherTarget::BitDepth(1),
+            serpentine: true,
+        }],
+    ),
+    (
+        "Effect: Atkinson",
+        &[Operation::Dither {
+            method: DitherMethod::Atkinson,
+            target: DitherTarget::BitDepth(1),
+            serpentine: false,
+        }],
+    ),
+    (
+        "Effect: Jarvis-Judice-Ninke",
+        &[Operation::Dither {
+            method: DitherMethod::JarvisJudiceNinke,
+            target: DitherTarget::Palette(QuantizeMethod::MedianCut, 16),
+            serpentine: true,
+        }],
+    ),
+    (
+        "Effect: Sierra",
+        &[Operation::Dither {
+            method: DitherMethod::Sierra,
+            target: DitherTarget::Palette(QuantizeMethod::Octree, 16),
+            serpentine: true,
+        }],
+    ),
+    (
+        "Effect: Bayer",
+        &[Operation::Dither {
+            method: DitherMethod::Bayer(4),
+            target: DitherTarget::BitDepth(2),
+            serpentine: false,
+        }],
+    ),
+    (
+        "Effect: warm film",
+        &[
+            Operation::WhiteBalance {
+                temperature: 0.3,
+                tint: 0.1,
+            },
+            Operation::Curves(&[(0, 20), (64, 58), (192, 206), (255, 240)]),
+            Operation::Vibrance(0.4),
+        ],
+    ),
+    (
+        "Effect: high key",
+        &[
+            Operation::Exposure(0.7),
+            Operation::Levels(
+                [Levels {
+                    input: (16, 240),
+                    gamma: 1.2,
+                    output: (24, 255),
+                }; 3],
+            ),
+            Operation::BrightnessContrast {
+                brightness: 0.05,
+                contrast: -0.2,
+            },
+            Operation::Gamma(1.1),
+            Operation::Saturation(-0.4),
+        ],
+    ),
+    (
+        "Effect: gray world",
+        &[Operation::AutoWhiteBalance(WhiteBalance::GrayWorld)],
+    ),
+    (
+        "Effect: white patch",
+        &[Operation::AutoWhiteBalance(WhiteBalance::WhitePatch)],
+    ),
+    (
+        "Effect: running average",
+        &[Operation::Temporal(TemporalFilter::RunningAverage)],
+    ),
+    (
+        "Effect: smoothing",
+        &[Operation::Temporal(TemporalFilter::ExponentialSmoothing(
+            0.2,
+        ))],
+    ),
+    (
+        "Effect: frame difference",
+        &[Operation::Temporal(TemporalFilter::FrameDifference(4.0))],
+    ),
+    (
+        "Effect: motion trails",
+        &[Operation::Temporal(TemporalFilter::MotionTrails(0.9))],
+    ),
+    (
+        "Effect: background",
+        &[Operation::Temporal(TemporalFilter::TemporalMedian)],
+    ),
+    (
+        "Effect: long exposure",
+        &[Operation::Temporal(TemporalFilter::LongExposure)],
+    ),
+];
+
+// The color grade from a loaded LUT runs after the effect, so it survives
+// switching effects.
+#[derive(Clone)]
+pub struct Pipeline {
+    operations: Vec<Operation>,
+    mask: Option<Mask>,
+    lut: Option<Rc<CubeLut>>,
+    interpolation: Interpolation,
+    strength: f32,
+    histories: Vec<FrameHistory>,
+    history_depth: usize,
+}
+
+impl Pipeline {
+    pub fn new(operations: Vec<Operation>) -> Self {
+        Pipeline {
+            histories: histories(operations.len(), FRAME_HISTORY_DEPTH),
+            history_depth: FRAME_HISTORY_DEPTH,
+            operations,
+            mask: None,
+            lut: None,
+            interpolation: Interpolation::Tetrahedral,
+            strength: LUT_STRENGTH,
+        }
+    }
+
+    pub fn set_operations(&mut self, operations: &[Operation]) {
+        self.operations = operations.to_vec();
+        self.histories = histories(operations.len(), self.history_depth);
+    }
+
+    pub fn history_depth(&self) -> usize {
+        self.history_depth
+    }
+
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history_depth = depth;
+        for history in &mut self.histories {
+            history.set_depth(depth);
+        }
+    }
+
+    pub fn clear_history(&mut self) {
+        for history in &mut self.histories {
+            history.clear();
+        }
+    }
+
+    pub fn set_lut(&mut self, lut: CubeLut) {
+        self.lut = Some(Rc::new(lut));
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    pub fn next_interpolation(&mut self) -> Interpolation {
+        self.interpolation = self.interpolation.next();
+        self.interpolation
+    }
+
+    pub fn strength(&self) -> f32 {
+        self.strength
+    }
+
+    pub fn set_strength(&mut self, strength: f32) {
+        self.strength = strength;
+    }
+
+    pub fn set_mask(&mut self, mask: Option<Mask>) {
+        self.mask = mask;
+    }
+
+    pub fn mask(&self) -> Option<&Mask> {
+        self.mask.as_ref()
+    }
+
+    pub fn run(&mut self, image: &mut RawImage) -> Result<()> {
+        let original = self.mask.as_ref().map(|_| image.clone());
+        self.apply_operations(image);
+        match (&self.mask, original) {
+            (Some(mask), Some(original)) => mask.blend(&original, image),
+            _ => Ok(()),
+        }
+    }
+
+    // Consecutive lookup table steps are fused into a single pass.
+    fn apply_operations(&mut self, image: &mut RawImage) {
+        let mut pending: Option<Lut> = None;
+        for (operation, history) in self.operations.iter().zip(&mut self.histories) {
+            match (operation.lut(), pending.take()) {
+                (Some(lut), Some(previous)) => pending = Some(previous.then(&lut)),
+                (Some(lut), None) => pending = Some(lut),
+                (None, previous) => {
+                    if let Some(previous) = previous {
+                        previous.apply(image);
+                    }
+                    apply_timed(operation, image, history);
+                }
+            }
+        }
+        if let Some(lut) = pending {
+            lut.apply(image);
+        }
+        if let Some(lut) = &self.lut {
+            lut.apply(image, self.interpolation, self.strength);
+        }
+    }
+}
+
+fn histories(count: usize, depth: usize) -> Vec<FrameHistory> {
+    (0..count).map(|_| FrameHistory::new(depth)).collect()
+}
+
+fn apply_timed(operation: &Operation, image: &mut RawImage, history: &mut FrameHistory) {
+    let start = browser::now().unwrap_or_default();
+    operation.apply(image, history);
+    let elapsed = browser::now().unwrap_or_default() - start;
+    if elapsed > SLOW_OPERATION_MS {
+        log!(
+            "{:?} took {:.1}ms on a {}x{} frame",
+            operation,
+            elapsed,
+            image.width(),
+            image.height()
+        );
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline::new(EFFECTS[0].1.to_vec())
+    }
+}
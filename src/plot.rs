@@ -1,7 +1,7 @@
-use crate::browser;
-use crate::button;
 use crate::canvas::{load_image, Image, Renderer};
-use crate::constants::{IMAGE_SOURCE, RUN_SIMULATION_BUTTON, RUN_SIMULATION_ID};
+use crate::constants::IMAGE_SOURCE;
+use crate::events::EventBus;
+use crate::keyboard::Keymap;
 use crate::plot_machine::PlotMachine;
 use crate::simulation_loop::Simulation;
 use anyhow::{anyhow, Result};
@@ -22,12 +22,11 @@ impl Simulation for SimulationPlot {
     async fn initialize(&self) -> Result<Box<dyn Simulation>> {
         match self.machine {
             None => {
-                let button = browser::draw_ui(RUN_SIMULATION_BUTTON)
-                    .and_then(|_unit| browser::find_html_element_by_id(RUN_SIMULATION_ID))
-                    .map(button::add_click_handler)
-                    .unwrap();
-
-                let machine = PlotMachine::new(Image::new(load_image(IMAGE_SOURCE).await?), button);
+                let events = EventBus::new(Keymap::default())?;
+                let machine = match load_image(IMAGE_SOURCE).await {
+                    Ok(element) => PlotMachine::new(Image::new(element), events),
+                    Err(err) => PlotMachine::failed(err, events),
+                };
 
                 Ok(Box::new(SimulationPlot {
                     machine: Some(machine),
@@ -44,9 +43,13 @@ impl Simulation for SimulationPlot {
         assert!(self.machine.is_some());
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        if let Some(machine) = &self.machine {
-            machine.draw(renderer);
-        };
+    fn draw(&mut self, renderer: &Renderer) {
+        if let Some(machine) = self.machine.take() {
+            let machine = match machine.draw(renderer) {
+                Ok(()) => machine,
+                Err(err) => machine.fail(err),
+            };
+            self.machine.replace(machine);
+        }
     }
 }
@@ -1,31 +1,62 @@
+use crate::brush::{Brush, MaskPainter};
 use crate::canvas::{Image, Renderer};
-use crate::plot_states::state_implementations::{End, PlotState, Ready, Simulating};
-use futures::channel::mpsc::UnboundedReceiver;
+use crate::constants::SELECTION_FEATHER_RADIUS;
+use crate::events::EventBus;
+use crate::plot_states::state_implementations::{
+    End, Error, PlotState, Ready, Simulating, TransitionError,
+};
+use crate::selection::Selection;
+use anyhow::Result;
 
 pub enum PlotMachine {
     Ready(PlotState<Ready>),
     Simulating(PlotState<Simulating>),
     End(PlotState<End>),
+    Error(Error),
 }
 
 impl PlotMachine {
-    pub fn new(image: Image, button: UnboundedReceiver<()>) -> Self {
-        PlotMachine::Ready(PlotState::new(image, button, false))
+    pub fn new(image: Image, events: EventBus) -> Self {
+        PlotState::new(
+            image,
+            events,
+            Selection::new(SELECTION_FEATHER_RADIUS),
+            MaskPainter::new(Brush::default()),
+            false,
+        )
+        .map_or_else(|failure| Error::new(*failure).into(), Into::into)
+    }
+
+    pub fn failed(error: anyhow::Error, events: EventBus) -> Self {
+        Error::new(*TransitionError::new(error, None, events)).into()
     }
 
     pub fn update(self, renderer: &Renderer) -> Self {
-        match self {
-            PlotMachine::Ready(state) => state.update(renderer).into(),
-            PlotMachine::Simulating(state) => state.update().into(),
-            PlotMachine::End(state) => state.update().into(),
-        }
+        let transition = match self {
+            PlotMachine::Ready(state) => state.update(renderer).map(Into::into),
+            PlotMachine::Simulating(state) => state.update().map(Into::into),
+            PlotMachine::End(state) => state.update().map(Into::into),
+            PlotMachine::Error(state) => state.update().map(Into::into),
+        };
+        transition.unwrap_or_else(|failure| Error::new(*failure).into())
     }
 
-    pub fn draw(&self, renderer: &Renderer) {
+    pub fn draw(&self, renderer: &Renderer) -> Result<()> {
         match self {
             PlotMachine::Ready(state) => state.draw(renderer),
             PlotMachine::Simulating(state) => state.draw(renderer),
             PlotMachine::End(state) => state.draw(renderer),
+            PlotMachine::Error(state) => state.draw(renderer),
         }
     }
+
+    pub fn fail(self, error: anyhow::Error) -> Self {
+        let failure = match self {
+            PlotMachine::Ready(state) => state.fail(error),
+            PlotMachine::Simulating(state) => state.fail(error),
+            PlotMachine::End(state) => state.fail(error),
+            PlotMachine::Error(state) => state.fail(error),
+        };
+        Error::new(*failure).into()
+    }
 }
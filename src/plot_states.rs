@@ -1,18 +1,32 @@
 pub mod state_implementations {
+    use crate::analysis::Detection;
     use crate::browser;
-    use crate::button;
-    use crate::canvas::{Image, Renderer};
+    use crate::brush::{Brush, MaskPainter};
+    use crate::canvas::{load_image, load_image_file, Image, Point, Renderer};
+    use crate::components::Connectivity;
     use crate::constants::*;
+    use crate::cube;
+    use crate::events::{self, Action, EventBus, EventSender, Parameter, Ui, UiEvent};
+    use crate::features::Detector;
+    use crate::layers::Layer;
+    use crate::mask::Mask;
     use crate::plot_machine::PlotMachine;
-    use futures::channel::mpsc::UnboundedReceiver;
+    use crate::pointer::PointerEvent;
+    use crate::selection::{Selection, SelectionTool};
+    use anyhow::{anyhow, Result};
+    use wasm_bindgen::JsCast;
+    use web_sys::{File, HtmlInputElement};
 
     pub struct PlotState<T> {
         _state: T,
         plot: Image,
+        events: EventBus,
     }
 
     pub struct Ready {
-        start_event: UnboundedReceiver<()>,
+        _ui: Ui,
+        selection: Selection,
+        painter: MaskPainter,
         image_drawn: bool,
     }
 
@@ -23,8 +37,9 @@ pub mod state_implementations {
     }
 
     pub struct Simulating {
-        pause_event: UnboundedReceiver<()>,
-        finish_event: UnboundedReceiver<()>,
+        _ui: Ui,
+        selection: Selection,
+        painter: MaskPainter,
     }
 
     impl From<PlotState<Simulating>> for PlotMachine {
@@ -34,8 +49,7 @@ pub mod state_implementations {
     }
 
     pub struct End {
-        refresh_event: UnboundedReceiver<()>,
-        save_event: UnboundedReceiver<()>,
+        _ui: Ui,
     }
 
     impl From<PlotState<End>> for PlotMachine {
@@ -44,14 +58,252 @@ pub mod state_implementations {
         }
     }
 
+    // A failed transition hands back whatever is left of the plot, so the
+    // error state can offer to retry with it.
+    pub struct TransitionError {
+        error: anyhow::Error,
+        plot: Option<Image>,
+        events: EventBus,
+    }
+
+    pub type TransitionResult<T> = std::result::Result<T, Box<TransitionError>>;
+
+    impl TransitionError {
+        pub fn new(error: anyhow::Error, plot: Option<Image>, events: EventBus) -> Box<Self> {
+            Box::new(TransitionError {
+                error,
+                plot,
+                events,
+            })
+        }
+    }
+
+    impl<T> PlotState<T> {
+        pub fn fail(self, error: anyhow::Error) -> Box<TransitionError> {
+            TransitionError::new(error, Some(self.plot), self.events)
+        }
+    }
+
+    pub struct Error {
+        _ui: Ui,
+        error: anyhow::Error,
+        plot: Option<Image>,
+        events: EventBus,
+    }
+
+    impl From<Error> for PlotMachine {
+        fn from(state: Error) -> Self {
+            PlotMachine::Error(state)
+        }
+    }
+
+    fn set_inner_text(id: &str, text: &str) {
+        match browser::find_html_element_by_id(id) {
+            Ok(element) => element.set_inner_text(text),
+            Err(err) => {
+                error!("Error updating the text of {} {:#?}", id, err);
+            }
+        }
+    }
+
+    fn set_slider_value(id: &str, value: f64) {
+        let input = browser::find_html_element_by_id(id).and_then(|element| {
+            element
+                .dyn_into::<HtmlInputElement>()
+                .map_err(|err| anyhow!("Could not cast into HtmlInputElement {:#?}", err))
+        });
+        match input {
+            Ok(input) => input.set_value_as_number(value),
+            Err(err) => {
+                error!("Error updating the slider {} {:#?}", id, err);
+            }
+        }
+    }
+
+    fn show_layer(layer: &Layer) {
+        set_inner_text(BLEND_MODE_ID, layer.blend.label());
+        set_inner_text(
+            LAYER_VISIBILITY_ID,
+            if layer.visible {
+                "Layer: visible"
+            } else {
+                "Layer: hidden"
+            },
+        );
+        set_inner_text(
+            SEAMS_ID,
+            if layer.seamless {
+                "Seams: blended"
+            } else {
+                "Seams: hard"
+            },
+        );
+        set_slider_value(LAYER_OPACITY_ID, layer.opacity.into());
+    }
+
+    // The strip shows the dominant colors of the current frame.
+    fn show_swatches(plot: &Image) {
+        let swatches: String = plot
+            .palette(SWATCH_COUNT)
+            .iter()
+            .map(|[r, g, b]| {
+                format!(
+                    "<span class='swatch' style='background-color: rgb({}, {}, {})'></span>",
+                    r, g, b
+                )
+            })
+            .collect();
+        match browser::find_html_element_by_id(SWATCH_STRIP_ID) {
+            Ok(element) => element.set_inner_html(&swatches),
+            Err(err) => {
+                error!("Error showing the swatches {:#?}", err);
+            }
+        }
+    }
+
+    fn stabilization_label(plot: &Image) -> &'static str {
+        if plot.analysis().stabilization.is_active() {
+            "Stabilize: on"
+        } else {
+            "Stabilize: off"
+        }
+    }
+
+    // Clicks and shortcuts trigger the same actions.
+    fn actions(events: &mut EventBus) -> Vec<Action> {
+        events
+            .drain()
+            .into_iter()
+            .filter_map(|event| match event {
+                UiEvent::Click(action) | UiEvent::Key(action) => Some(action),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn send_loaded_image(image: Result<web_sys::HtmlImageElement>, sender: &EventSender) {
+        match image {
+            Ok(element) => events::send(sender, UiEvent::ImageLoaded(element)),
+            Err(err) => events::send(sender, UiEvent::Error(err)),
+        }
+    }
+
+    fn load_lut_file(file: File, sender: EventSender) {
+        browser::spawn_local(async move {
+            match cube::load_file(&file).await {
+                Ok(lut) => events::send(&sender, UiEvent::LutLoaded(lut)),
+                Err(err) => events::send(&sender, UiEvent::Error(err)),
+            }
+        });
+    }
+
+    fn load_match_file(file: File, sender: EventSender) {
+        browser::spawn_local(async move {
+            match load_image_file(&file).await {
+                Ok(element) => events::send(&sender, UiEvent::MatchLoaded(element)),
+                Err(err) => events::send(&sender, UiEvent::Error(err)),
+            }
+        });
+    }
+
+    fn load_panorama_file(file: File, sender: EventSender) {
+        browser::spawn_local(async move {
+            match load_image_file(&file).await {
+                Ok(element) => events::send(&sender, UiEvent::PanoramaLoaded(element)),
+                Err(err) => events::send(&sender, UiEvent::Error(err)),
+            }
+        });
+    }
+
+    fn load_dropped_image(file: File, sender: EventSender) {
+        browser::spawn_local(async move {
+            send_loaded_image(load_image_file(&file).await, &sender);
+        });
+    }
+
+    fn load_source_image(sender: EventSender) {
+        browser::spawn_local(async move {
+            send_loaded_image(load_image(IMAGE_SOURCE).await, &sender);
+        });
+    }
+
+    fn hide_ui() {
+        if let Err(err) = browser::hide_ui() {
+            error!("Error hiding the browser {:#?}", err);
+        }
+    }
+
     // ---------------------
     // - implementation of -
     // -      Ready        -
     // ---------------------
 
     impl Ready {
-        fn run_simulation_pressed(&mut self) -> bool {
-            matches!(self.start_event.try_next(), Ok(Some(())))
+        fn next_selection_tool(&mut self) {
+            let tool = self.selection.next_tool();
+            self.painter.clear();
+            set_inner_text(SELECTION_TOOL_ID, tool.label());
+        }
+
+        fn invert_selection(&mut self) {
+            self.selection.toggle_inverted();
+            set_inner_text(INVERT_SELECTION_ID, self.selection.inverted_label());
+        }
+
+        fn next_brush_mode(&mut self) -> Result<()> {
+            let mode = self.painter.next_mode();
+            if self.painter.is_active() && self.painter.mask().is_none() {
+                self.start_painting()?;
+            }
+            set_inner_text(BRUSH_MODE_ID, mode.label());
+            Ok(())
+        }
+
+        fn change_parameter(&mut self, plot: &mut Image, parameter: Parameter, value: f64) {
+            let brush = self.painter.brush_mut();
+            match parameter {
+                Parameter::Feather => self.selection.set_feather(value as u32),
+                Parameter::BrushSize => brush.size = value as u32,
+                Parameter::BrushHardness => brush.hardness = value as f32,
+                Parameter::BrushOpacity => brush.opacity = value as f32,
+                Parameter::LutStrength => plot.set_lut_strength(value as f32),
+                Parameter::HistoryDepth => plot.set_history_depth(value as usize),
+                Parameter::LearningRate => plot
+                    .analysis_mut()
+                    .background
+                    .set_learning_rate(value as f32),
+                Parameter::MaxKeypoints => {
+                    plot.detect(|detection| detection.set_max_keypoints(value as usize))
+                }
+                Parameter::StabilizationWindow => {
+                    plot.analysis_mut().stabilization.set_window(value as usize)
+                }
+                Parameter::LayerOpacity => {
+                    if let Some(layer) = plot.top_layer_mut() {
+                        layer.opacity = value as f32;
+                    }
+                }
+            }
+        }
+
+        fn handle_pointer(&mut self, event: PointerEvent) {
+            if self.painter.is_active() {
+                self.painter.handle_pointer(event);
+            } else {
+                self.selection.handle_pointer(event);
+            }
+        }
+
+        // Painting starts from the current selection, so the brush refines it.
+        fn start_painting(&mut self) -> Result<()> {
+            let canvas = browser::canvas()?;
+            let (width, height) = (canvas.width(), canvas.height());
+            let base = self
+                .selection
+                .to_mask(width, height)
+                .unwrap_or_else(|| Mask::new(width, height));
+            self.painter.start(base);
+            Ok(())
         }
     }
 
@@ -70,56 +322,486 @@ pub mod state_implementations {
     }
 
     impl PlotState<Ready> {
-        pub fn draw(&self, renderer: &Renderer) {
+        pub fn draw(&self, renderer: &Renderer) -> Result<()> {
             log!("drawing from ready");
             if self._state.image_drawn {
-                self.plot.put_image(renderer)
+                self.plot.put_image(renderer)?;
             } else {
-                self.plot.draw(renderer);
+                renderer.clear();
+                self.plot.draw(renderer)?;
+            }
+            if let Some(overlay) = self
+                ._state
+                .painter
+                .overlay(MASK_OVERLAY_COLOR, MASK_OVERLAY_ALPHA)
+            {
+                renderer.draw_overlay(&overlay, &Point { x: 0, y: 0 })?;
             }
+            self._state.selection.draw(renderer)
         }
 
         pub fn new(
-            image: Image,
-            button: UnboundedReceiver<()>,
+            mut image: Image,
+            events: EventBus,
+            selection: Selection,
+            painter: MaskPainter,
             image_drawn: bool,
-        ) -> PlotState<Ready> {
-            PlotState {
-                _state: Ready {
-                    start_event: button,
-                    image_drawn,
-                },
-                plot: image,
+        ) -> TransitionResult<PlotState<Ready>> {
+            match Self::draw_ui(&events, &image, &selection, &painter) {
+                Ok(ui) => {
+                    set_inner_text(PALETTE_METHOD_ID, image.palette_method().label());
+                    set_inner_text(LUT_INTERPOLATION_ID, image.lut_interpolation().label());
+                    set_inner_text(EFFECT_ID, image.effect_label());
+                    set_inner_text(SOURCE_ID, image.source_mode().label());
+                    let analysis = image.analysis();
+                    set_inner_text(BACKGROUND_VIEW_ID, analysis.background.view_mode().label());
+                    set_inner_text(HOUGH_ID, analysis.detection.hough_mode().label());
+                    set_inner_text(FLOW_ID, analysis.flow.mode().label());
+                    set_inner_text(CARVE_ID, analysis.carving.mode().label());
+                    set_inner_text(CARVE_MASK_ID, analysis.carving.mask_role().label());
+                    set_inner_text(
+                        CORNERS_ID,
+                        analysis
+                            .detection
+                            .detector()
+                            .map_or("Corners: off", Detector::label),
+                    );
+                    set_inner_text(
+                        COMPONENTS_ID,
+                        analysis
+                            .detection
+                            .connectivity()
+                            .map_or("Components: off", Connectivity::label),
+                    );
+                    if let Some(layer) = image.top_layer_mut() {
+                        show_layer(layer);
+                    }
+                    show_swatches(&image);
+                    Ok(PlotState {
+                        _state: Ready {
+                            _ui: ui,
+                            selection,
+                            painter,
+                            image_drawn,
+                        },
+                        plot: image,
+                        events,
+                    })
+                }
+                Err(err) => Err(TransitionError::new(err, Some(image), events)),
             }
         }
 
-        pub fn update(mut self, renderer: &Renderer) -> ReadyStateTransition {
-            if self._state.run_simulation_pressed() {
-                ReadyStateTransition::Simulate(self.start_simulation(renderer))
+        fn draw_ui(
+            events: &EventBus,
+            image: &Image,
+            selection: &Selection,
+            painter: &MaskPainter,
+        ) -> Result<Ui> {
+            let brush = painter.brush();
+            let ui = events
+                .ui()
+                .html(SWATCH_STRIP)?
+                .slider(
+                    HISTORY_DEPTH_SLIDER,
+                    HISTORY_DEPTH_ID,
+                    Parameter::HistoryDepth,
+                    image.history_depth() as f64,
+                )?
+                .slider(
+                    LAYER_OPACITY_SLIDER,
+                    LAYER_OPACITY_ID,
+                    Parameter::LayerOpacity,
+                    1.0,
+                )?
+                .button(
+                    LAYER_VISIBILITY_BUTTON,
+                    LAYER_VISIBILITY_ID,
+                    Action::ToggleLayerVisibility,
+                )?
+                .button(SEAMS_BUTTON, SEAMS_ID, Action::ToggleSeams)?
+                .button(BLEND_MODE_BUTTON, BLEND_MODE_ID, Action::NextBlendMode)?
+                .button(ADD_LAYER_BUTTON, ADD_LAYER_ID, Action::AddLayer)?
+                .slider(
+                    LUT_STRENGTH_SLIDER,
+                    LUT_STRENGTH_ID,
+                    Parameter::LutStrength,
+                    image.lut_strength().into(),
+                )?
+                .button(
+                    LUT_INTERPOLATION_BUTTON,
+                    LUT_INTERPOLATION_ID,
+                    Action::NextLutInterpolation,
+                )?
+                .file_input(LUT_INPUT, LUT_INPUT_ID, UiEvent::LutFile)?
+                .button(
+                    PALETTE_METHOD_BUTTON,
+                    PALETTE_METHOD_ID,
+                    Action::NextPaletteMethod,
+                )?
+                .slider(
+                    BRUSH_OPACITY_SLIDER,
+                    BRUSH_OPACITY_ID,
+                    Parameter::BrushOpacity,
+                    brush.opacity.into(),
+                )?
+                .slider(
+                    BRUSH_HARDNESS_SLIDER,
+                    BRUSH_HARDNESS_ID,
+                    Parameter::BrushHardness,
+                    brush.hardness.into(),
+                )?
+                .slider(
+                    BRUSH_SIZE_SLIDER,
+                    BRUSH_SIZE_ID,
+                    Parameter::BrushSize,
+                    brush.size.into(),
+                )?
+                .button(BRUSH_MODE_BUTTON, BRUSH_MODE_ID, Action::NextBrushMode)?
+                .slider(
+                    FEATHER_SLIDER,
+                    FEATHER_ID,
+                    Parameter::Feather,
+                    selection.feather().into(),
+                )?
+                .button(
+                    INVERT_SELECTION_BUTTON,
+                    INVERT_SELECTION_ID,
+                    Action::InvertSelection,
+                )?
+                .slider(
+                    MAX_KEYPOINTS_SLIDER,
+                    MAX_KEYPOINTS_ID,
+                    Parameter::MaxKeypoints,
+                    image.analysis().detection.max_keypoints() as f64,
+                )?
+                .button(CORNERS_BUTTON, CORNERS_ID, Action::NextDetector)?
+                .button(ALIGN_BUTTON, ALIGN_ID, Action::AlignMatch)?
+                .button(MATCHES_BUTTON, MATCHES_ID, Action::ToggleMatches)?
+                .file_input(MATCH_INPUT, MATCH_INPUT_ID, UiEvent::MatchFile)?
+                .button(STITCH_BUTTON, STITCH_ID, Action::Stitch)?
+                .file_input(PANORAMA_INPUT, PANORAMA_INPUT_ID, UiEvent::PanoramaFile)?
+                .button(
+                    ACCUMULATOR_BUTTON,
+                    ACCUMULATOR_ID,
+                    Action::ToggleAccumulator,
+                )?
+                .button(HOUGH_BUTTON, HOUGH_ID, Action::NextHoughMode)?
+                .button(CONTOURS_BUTTON, CONTOURS_ID, Action::ToggleContours)?
+                .button(COMPONENTS_BUTTON, COMPONENTS_ID, Action::NextConnectivity)?
+                .button(FLOW_BUTTON, FLOW_ID, Action::NextFlowMode)?
+                .button(CARVE_BUTTON, CARVE_ID, Action::NextCarveMode)?
+                .button(CARVE_MASK_BUTTON, CARVE_MASK_ID, Action::NextCarveMask)?
+                .button(ADD_SEAMS_BUTTON, ADD_SEAMS_ID, Action::AddSeams)?
+                .button(SOURCE_BUTTON, SOURCE_ID, Action::NextSource)?
+                .button(MOTION_BUTTON, MOTION_ID, Action::ToggleMotion)?
+                .slider(
+                    LEARNING_RATE_SLIDER,
+                    LEARNING_RATE_ID,
+                    Parameter::LearningRate,
+                    image.analysis().background.learning_rate().into(),
+                )?
+                .button(
+                    BACKGROUND_VIEW_BUTTON,
+                    BACKGROUND_VIEW_ID,
+                    Action::NextBackgroundView,
+                )?
+                .button(EFFECT_BUTTON, EFFECT_ID, Action::NextEffect)?
+                .button(
+                    SELECTION_TOOL_BUTTON,
+                    SELECTION_TOOL_ID,
+                    Action::NextSelectionTool,
+                )?
+                .button(RUN_SIMULATION_BUTTON, RUN_SIMULATION_ID, Action::ToggleRun)?;
+            set_inner_text(BRUSH_MODE_ID, painter.mode().label());
+            set_inner_text(INVERT_SELECTION_ID, selection.inverted_label());
+            set_inner_text(SELECTION_TOOL_ID, selection.tool().label());
+            Ok(ui)
+        }
+
+        pub fn update(mut self, renderer: &Renderer) -> TransitionResult<ReadyStateTransition> {
+            let mut start = false;
+            for event in self.events.drain() {
+                match event {
+                    UiEvent::Click(action) | UiEvent::Key(action) => match action {
+                        Action::ToggleRun => start = true,
+                        Action::Step => self = self.step_simulation(renderer)?,
+                        Action::Undo if self.plot.undo() => {
+                            self._state.image_drawn = true;
+                            show_swatches(&self.plot);
+                        }
+                        Action::NextSelectionTool => self._state.next_selection_tool(),
+                        Action::InvertSelection => self._state.invert_selection(),
+                        Action::NextSource => {
+                            self = self.load_plot(renderer)?;
+                            set_inner_text(SOURCE_ID, self.plot.next_source().label());
+                            self._state.image_drawn = true;
+                        }
+                        Action::ToggleMotion => {
+                            let label = if self.plot.analysis_mut().motion.toggle() {
+                                "Motion: on"
+                            } else {
+                                "Motion: off"
+                            };
+                            set_inner_text(MOTION_ID, label);
+                        }
+                        Action::NextFlowMode => {
+                            set_inner_text(
+                                FLOW_ID,
+                                self.plot.analysis_mut().flow.next_mode().label(),
+                            );
+                        }
+                        Action::NextCarveMode => {
+                            set_inner_text(
+                                CARVE_ID,
+                                self.plot.analysis_mut().carving.next_mode().label(),
+                            );
+                        }
+                        Action::NextCarveMask => {
+                            set_inner_text(
+                                CARVE_MASK_ID,
+                                self.plot.analysis_mut().carving.next_mask_role().label(),
+                            );
+                        }
+                        Action::AddSeams => {
+                            self = self.load_plot(renderer)?;
+                            self.plot.checkpoint();
+                            self.plot.add_seams();
+                            self._state.image_drawn = true;
+                            show_swatches(&self.plot);
+                        }
+                        Action::NextConnectivity => {
+                            let label = self
+                                .plot
+                                .detect(Detection::next_connectivity)
+                                .map_or("Components: off", Connectivity::label);
+                            set_inner_text(COMPONENTS_ID, label);
+                        }
+                        Action::AlignMatch => {
+                            if let Some(layer) = self.plot.align_match() {
+                                show_layer(layer);
+                                set_inner_text(MATCHES_ID, "Matches: off");
+                            }
+                        }
+                        Action::ToggleMatches => {
+                            let label = if self.plot.analysis_mut().matching.toggle() {
+                                "Matches: on"
+                            } else {
+                                "Matches: off"
+                            };
+                            set_inner_text(MATCHES_ID, label);
+                        }
+                        Action::NextDetector => {
+                            let label = self
+                                .plot
+                                .detect(Detection::next_detector)
+                                .map_or("Corners: off", Detector::label);
+                            set_inner_text(CORNERS_ID, label);
+                        }
+                        Action::NextHoughMode => {
+                            set_inner_text(
+                                HOUGH_ID,
+                                self.plot.detect(Detection::next_hough_mode).label(),
+                            );
+                        }
+                        Action::ToggleAccumulator => {
+                            let label = if self.plot.analysis_mut().detection.toggle_accumulator() {
+                                "Accumulator: shown"
+                            } else {
+                                "Accumulator: hidden"
+                            };
+                            set_inner_text(ACCUMULATOR_ID, label);
+                        }
+                        Action::ToggleContours => {
+                            let label = if self.plot.detect(Detection::toggle_contours) {
+                                "Contours: on"
+                            } else {
+                                "Contours: off"
+                            };
+                            set_inner_text(CONTOURS_ID, label);
+                        }
+                        Action::NextBackgroundView => {
+                            let view = self.plot.analysis_mut().background.next_view();
+                            set_inner_text(BACKGROUND_VIEW_ID, view.label());
+                        }
+                        Action::AddLayer => {
+                            if let Some(layer) = self.plot.add_layer() {
+                                show_layer(layer);
+                            }
+                        }
+                        Action::NextBlendMode => {
+                            if let Some(layer) = self.plot.top_layer_mut() {
+                                layer.blend = layer.blend.next();
+                                show_layer(layer);
+                            }
+                        }
+                        Action::Stitch => {
+                            self = self.load_plot(renderer)?;
+                            self.plot.checkpoint();
+                            if let Err(err) = self.plot.stitch() {
+                                return Err(self.fail(err));
+                            }
+                            self._state.image_drawn = true;
+                            set_inner_text(STITCH_ID, "Stitch");
+                            show_swatches(&self.plot);
+                        }
+                        Action::ToggleSeams => {
+                            if let Some(layer) = self.plot.top_layer_mut() {
+                                layer.seamless = !layer.seamless;
+                                show_layer(layer);
+                            }
+                        }
+                        Action::ToggleLayerVisibility => {
+                            if let Some(layer) = self.plot.top_layer_mut() {
+                                layer.visible = !layer.visible;
+                                show_layer(layer);
+                            }
+                        }
+                        Action::NextLutInterpolation => {
+                            let interpolation = self.plot.next_lut_interpolation();
+                            set_inner_text(LUT_INTERPOLATION_ID, interpolation.label());
+                        }
+                        Action::NextEffect => set_inner_text(EFFECT_ID, self.plot.next_effect()),
+                        Action::NextPaletteMethod => {
+                            let method = self.plot.next_palette_method();
+                            set_inner_text(PALETTE_METHOD_ID, method.label());
+                            show_swatches(&self.plot);
+                        }
+                        Action::NextBrushMode => {
+                            if let Err(err) = self._state.next_brush_mode() {
+                                return Err(self.fail(err));
+                            }
+                        }
+                        _ => {}
+                    },
+                    UiEvent::Pointer(event) => self._state.handle_pointer(event),
+                    UiEvent::ParameterChange(parameter, value) => {
+                        self._state
+                            .change_parameter(&mut self.plot, parameter, value)
+                    }
+                    UiEvent::FileDrop(file) => load_dropped_image(file, self.events.sender()),
+                    UiEvent::LutFile(file) => load_lut_file(file, self.events.sender()),
+                    UiEvent::MatchFile(file) => load_match_file(file, self.events.sender()),
+                    UiEvent::MatchLoaded(element) => {
+                        self = self.load_plot(renderer)?;
+                        match renderer.read_image(&element) {
+                            Ok(image) => self.plot.set_match_image(image),
+                            Err(err) => return Err(self.fail(err)),
+                        }
+                        self._state.image_drawn = true;
+                        set_inner_text(MATCHES_ID, "Matches: on");
+                    }
+                    UiEvent::PanoramaFile(file) => load_panorama_file(file, self.events.sender()),
+                    UiEvent::PanoramaLoaded(element) => {
+                        self = self.load_plot(renderer)?;
+                        match renderer.read_image(&element) {
+                            Ok(image) => {
+                                let count = self.plot.add_panorama_image(image);
+                                set_inner_text(STITCH_ID, &format!("Stitch {} images", count));
+                            }
+                            Err(err) => return Err(self.fail(err)),
+                        }
+                        self._state.image_drawn = true;
+                    }
+                    UiEvent::LutLoaded(lut) => {
+                        log!("Loaded LUT {}", lut.title().unwrap_or("without title"));
+                        self.plot.set_lut(lut);
+                    }
+                    UiEvent::ImageLoaded(element) => {
+                        self.plot = Image::new(element);
+                        self._state.painter.clear();
+                        self._state.image_drawn = false;
+                    }
+                    UiEvent::Error(err) => return Err(self.fail(err)),
+                }
+            }
+
+            if start {
+                Ok(ReadyStateTransition::Simulate(
+                    self.start_simulation(renderer)?,
+                ))
             } else {
-                ReadyStateTransition::Same(self)
+                Ok(ReadyStateTransition::Same(self))
             }
         }
 
-        fn start_simulation(self, renderer: &Renderer) -> PlotState<Simulating> {
-            if let Err(err) = browser::hide_ui() {
-                error!("Error hiding the browser {:#?}", err);
+        fn load_plot(mut self, renderer: &Renderer) -> TransitionResult<Self> {
+            if let Err(err) = self.plot.load_image(renderer) {
+                return Err(self.fail(err));
             }
-            let pause_event = browser::draw_ui(PAUSE_SIMULATION_BUTTON)
-                .and_then(|_unit| browser::find_html_element_by_id(PAUSE_SIMULATION_ID))
-                .map(button::add_click_handler)
-                .unwrap();
-            let finish_event = browser::draw_ui(FINISH_SIMULATION_BUTTON)
-                .and_then(|_unit| browser::find_html_element_by_id(FINISH_SIMULATION_ID))
-                .map(button::add_click_handler)
-                .unwrap();
-
-            PlotState {
-                _state: Simulating {
-                    pause_event,
-                    finish_event,
+            let (width, height) = (self.plot.width(), self.plot.height());
+            match self._state.painter.mask() {
+                Some(mask) => self.plot.set_mask(Some(mask.clone())),
+                None => match self._state.selection.to_mask(width, height) {
+                    Some(mask) => self.plot.set_mask(Some(mask)),
+                    None if self._state.selection.tool() == SelectionTool::None => {
+                        self.plot.set_mask(None)
+                    }
+                    None => {}
                 },
-                plot: self.plot.load_image(renderer),
+            }
+            Ok(self)
+        }
+
+        fn step_simulation(self, renderer: &Renderer) -> TransitionResult<Self> {
+            let mut state = self.load_plot(renderer)?;
+            state.plot.checkpoint();
+            if let Err(err) = state.plot.run_simulation_step() {
+                return Err(state.fail(err));
+            }
+            state._state.image_drawn = true;
+            show_swatches(&state.plot);
+            Ok(state)
+        }
+
+        fn start_simulation(self, renderer: &Renderer) -> TransitionResult<PlotState<Simulating>> {
+            let mut state = self.load_plot(renderer)?;
+            state.plot.checkpoint();
+
+            hide_ui();
+            let ui = state
+                .events
+                .ui()
+                .html(SWATCH_STRIP)
+                .and_then(|ui| {
+                    ui.button(
+                        FINISH_SIMULATION_BUTTON,
+                        FINISH_SIMULATION_ID,
+                        Action::Finish,
+                    )
+                })
+                .and_then(|ui| {
+                    ui.button(
+                        PAUSE_SIMULATION_BUTTON,
+                        PAUSE_SIMULATION_ID,
+                        Action::ToggleRun,
+                    )
+                })
+                .and_then(|ui| {
+                    ui.button(STABILIZE_BUTTON, STABILIZE_ID, Action::ToggleStabilization)
+                })
+                .and_then(|ui| ui.button(CARVE_BUTTON, CARVE_ID, Action::NextCarveMode))
+                .and_then(|ui| {
+                    ui.slider(
+                        STABILIZATION_WINDOW_SLIDER,
+                        STABILIZATION_WINDOW_ID,
+                        Parameter::StabilizationWindow,
+                        state.plot.analysis().stabilization.window() as f64,
+                    )
+                });
+            set_inner_text(STABILIZE_ID, stabilization_label(&state.plot));
+            set_inner_text(CARVE_ID, state.plot.analysis().carving.mode().label());
+            match ui {
+                Ok(ui) => Ok(PlotState {
+                    _state: Simulating {
+                        _ui: ui,
+                        selection: state._state.selection,
+                        painter: state._state.painter,
+                    },
+                    plot: state.plot,
+                    events: state.events,
+                }),
+                Err(err) => Err(state.fail(err)),
             }
         }
     }
@@ -129,16 +811,6 @@ pub mod state_implementations {
     // -    Simulating     -
     // ---------------------
 
-    impl Simulating {
-        fn pause_simulation_pressed(&mut self) -> bool {
-            matches!(self.pause_event.try_next(), Ok(Some(())))
-        }
-
-        fn finish_simulation_pressed(&mut self) -> bool {
-            matches!(self.finish_event.try_next(), Ok(Some(())))
-        }
-    }
-
     pub enum SimulatingStateTransition {
         Pause(PlotState<Ready>),
         Finish(PlotState<End>),
@@ -156,56 +828,85 @@ pub mod state_implementations {
     }
 
     impl PlotState<Simulating> {
-        pub fn draw(&self, renderer: &Renderer) {
+        pub fn draw(&self, renderer: &Renderer) -> Result<()> {
             log!("draw from simulating");
-            self.plot.put_image(renderer);
+            self.plot.put_image(renderer)
         }
 
-        pub fn update(mut self) -> SimulatingStateTransition {
-            if self._state.pause_simulation_pressed() {
-                SimulatingStateTransition::Pause(self.pause_simulation())
-            } else if self._state.finish_simulation_pressed() {
-                SimulatingStateTransition::Finish(self.finish_simulation())
+        pub fn update(mut self) -> TransitionResult<SimulatingStateTransition> {
+            let mut actions = vec![];
+            for event in self.events.drain() {
+                match event {
+                    UiEvent::Click(action) | UiEvent::Key(action) => actions.push(action),
+                    UiEvent::ParameterChange(Parameter::StabilizationWindow, value) => self
+                        .plot
+                        .analysis_mut()
+                        .stabilization
+                        .set_window(value as usize),
+                    _ => {}
+                }
+            }
+            if actions.contains(&Action::ToggleStabilization) {
+                self.plot.analysis_mut().stabilization.toggle();
+                set_inner_text(STABILIZE_ID, stabilization_label(&self.plot));
+            }
+            if actions.contains(&Action::NextCarveMode) {
+                set_inner_text(
+                    CARVE_ID,
+                    self.plot.analysis_mut().carving.next_mode().label(),
+                );
+            }
+            if actions.contains(&Action::ToggleRun) {
+                Ok(SimulatingStateTransition::Pause(self.pause_simulation()?))
+            } else if actions.contains(&Action::Finish) {
+                Ok(SimulatingStateTransition::Finish(self.finish_simulation()?))
             } else {
-                SimulatingStateTransition::Simulate(self.run_simulation_step())
+                Ok(SimulatingStateTransition::Simulate(
+                    self.run_simulation_step()?,
+                ))
             }
         }
 
-        fn pause_simulation(self) -> PlotState<Ready> {
-            if let Err(err) = browser::hide_ui() {
-                error!("Error hiding the browser {:#?}", err);
-            }
-            let start_event = browser::draw_ui(RUN_SIMULATION_BUTTON)
-                .and_then(|_unit| browser::find_html_element_by_id(RUN_SIMULATION_ID))
-                .map(button::add_click_handler)
-                .unwrap();
-            PlotState::new(self.plot, start_event, true)
+        fn pause_simulation(self) -> TransitionResult<PlotState<Ready>> {
+            hide_ui();
+            PlotState::new(
+                self.plot,
+                self.events,
+                self._state.selection,
+                self._state.painter,
+                true,
+            )
         }
 
-        fn finish_simulation(self) -> PlotState<End> {
-            if let Err(err) = browser::hide_ui() {
-                error!("Error hiding the browser {:#?}", err);
-            }
-            let refresh_event = browser::draw_ui(REFRESH_IMAGE_BUTTON)
-                .and_then(|_unit| browser::find_html_element_by_id(REFRESH_IMAGE_ID))
-                .map(button::add_click_handler)
-                .unwrap();
-            let save_event = browser::draw_ui(SAVE_IMAGE_BUTTON)
-                .and_then(|_unit| browser::find_html_element_by_id(SAVE_IMAGE_ID))
-                .map(button::add_click_handler)
-                .unwrap();
-            PlotState {
-                _state: End {
-                    refresh_event,
-                    save_event,
-                },
-                plot: self.plot,
+        fn finish_simulation(self) -> TransitionResult<PlotState<End>> {
+            hide_ui();
+            let ui = self
+                .events
+                .ui()
+                .html(SWATCH_STRIP)
+                .and_then(|ui| ui.button(SAVE_IMAGE_BUTTON, SAVE_IMAGE_ID, Action::Save))
+                .and_then(|ui| ui.button(REFRESH_IMAGE_BUTTON, REFRESH_IMAGE_ID, Action::Refresh));
+            match ui {
+                Ok(ui) => {
+                    show_swatches(&self.plot);
+                    Ok(PlotState {
+                        _state: End { _ui: ui },
+                        plot: self.plot,
+                        events: self.events,
+                    })
+                }
+                Err(err) => Err(self.fail(err)),
             }
         }
 
-        fn run_simulation_step(mut self) -> PlotState<Simulating> {
-            self.plot.run_simulation_step();
-            self
+        fn run_simulation_step(mut self) -> TransitionResult<PlotState<Simulating>> {
+            match self.plot.run_simulation_step() {
+                Ok(()) => {
+                    show_swatches(&self.plot);
+                    Ok(self)
+                }
+                Err(err) => Err(self.fail(err)),
+            }
         }
     }
 
@@ -214,16 +915,6 @@ pub mod state_implementations {
     // -       End         -
     // ---------------------
 
-    impl End {
-        fn refresh_image_pressed(&mut self) -> bool {
-            matches!(self.refresh_event.try_next(), Ok(Some(())))
-        }
-
-        fn save_image_pressed(&mut self) -> bool {
-            matches!(self.save_event.try_next(), Ok(Some(())))
-        }
-    }
-
     pub enum EndStateTransition {
         Refresh(PlotState<Ready>),
         Save(PlotState<End>),
@@ -241,42 +932,142 @@ pub mod state_implementations {
     }
 
     impl PlotState<End> {
-        pub fn draw(&self, renderer: &Renderer) {
-            self.plot.put_image(renderer);
+        pub fn draw(&self, renderer: &Renderer) -> Result<()> {
+            self.plot.put_image(renderer)
         }
 
-        pub fn update(mut self) -> EndStateTransition {
-            if self._state.refresh_image_pressed() {
-                EndStateTransition::Refresh(self.refresh_image())
-            } else if self._state.save_image_pressed() {
-                EndStateTransition::Save(self.save_image())
+        pub fn update(mut self) -> TransitionResult<EndStateTransition> {
+            let actions = actions(&mut self.events);
+            if actions.contains(&Action::Undo) && self.plot.undo() {
+                show_swatches(&self.plot);
+            }
+
+            if actions.contains(&Action::Refresh) {
+                Ok(EndStateTransition::Refresh(self.refresh_image()?))
+            } else if actions.contains(&Action::Save) {
+                Ok(EndStateTransition::Save(self.save_image()?))
             } else {
-                EndStateTransition::Continue(self)
+                Ok(EndStateTransition::Continue(self))
             }
         }
 
-        fn refresh_image(self) -> PlotState<Ready> {
-            if let Err(err) = browser::hide_ui() {
-                error!("Error hiding the browser {:#?}", err);
+        fn refresh_image(self) -> TransitionResult<PlotState<Ready>> {
+            hide_ui();
+            PlotState::new(
+                self.plot.refresh(),
+                self.events,
+                Selection::new(SELECTION_FEATHER_RADIUS),
+                MaskPainter::new(Brush::default()),
+                false,
+            )
+        }
+
+        // The canvas already shows the finished frame with its layers.
+        fn save_image(self) -> TransitionResult<PlotState<End>> {
+            let saved = browser::canvas()
+                .and_then(|canvas| {
+                    canvas
+                        .to_data_url_with_type("image/png")
+                        .map_err(|err| anyhow!("Could not encode the canvas: {:#?}", err))
+                })
+                .and_then(|url| browser::download(&url, SAVED_IMAGE_NAME));
+            match saved {
+                Ok(()) => Ok(self),
+                Err(err) => Err(self.fail(err)),
             }
-            let start_event = browser::draw_ui(RUN_SIMULATION_BUTTON)
-                .and_then(|_unit| browser::find_html_element_by_id(RUN_SIMULATION_ID))
-                .map(button::add_click_handler)
-                .unwrap();
-            PlotState {
-                _state: Ready {
-                    start_event,
-                    image_drawn: false,
-                },
-                plot: self.plot.refresh(),
+        }
+    }
+
+    // ---------------------
+    // - implementation of -
+    // -      Error        -
+    // ---------------------
+
+    pub enum ErrorStateTransition {
+        Recover(PlotState<Ready>),
+        Same(Error),
+    }
+
+    impl From<ErrorStateTransition> for PlotMachine {
+        fn from(state: ErrorStateTransition) -> Self {
+            match state {
+                ErrorStateTransition::Recover(ready) => ready.into(),
+                ErrorStateTransition::Same(error) => error.into(),
+            }
+        }
+    }
+
+    impl Error {
+        pub fn new(failure: TransitionError) -> Self {
+            error!("Plot failed {:#?}", failure.error);
+            hide_ui();
+            let ui = failure
+                .events
+                .ui()
+                .button(RESET_BUTTON, RESET_ID, Action::Reset)
+                .and_then(|ui| ui.button(RETRY_BUTTON, RETRY_ID, Action::Retry))
+                .and_then(|ui| {
+                    browser::draw_ui(ERROR_MESSAGE)?;
+                    Ok(ui)
+                })
+                .unwrap_or_else(|err| {
+                    error!("Error drawing the error ui {:#?}", err);
+                    failure.events.ui()
+                });
+            set_inner_text(ERROR_MESSAGE_ID, &format!("{:#}", failure.error));
+
+            Error {
+                _ui: ui,
+                error: failure.error,
+                plot: failure.plot,
+                events: failure.events,
+            }
+        }
+
+        pub fn draw(&self, _renderer: &Renderer) -> Result<()> {
+            Ok(())
+        }
+
+        pub fn fail(self, error: anyhow::Error) -> Box<TransitionError> {
+            TransitionError::new(error, self.plot, self.events)
+        }
+
+        // Retrying keeps the current image when there is one, resetting always
+        // starts over from the source image.
+        pub fn update(mut self) -> TransitionResult<ErrorStateTransition> {
+            for event in self.events.drain() {
+                match event {
+                    UiEvent::Click(Action::Retry) | UiEvent::Key(Action::Retry) => {
+                        match self.plot.take() {
+                            Some(plot) => return self.recover(plot),
+                            None => load_source_image(self.events.sender()),
+                        }
+                    }
+                    UiEvent::Click(Action::Reset) | UiEvent::Key(Action::Reset) => {
+                        load_source_image(self.events.sender())
+                    }
+                    UiEvent::ImageLoaded(element) => return self.recover(Image::new(element)),
+                    UiEvent::Error(err) => {
+                        set_inner_text(ERROR_MESSAGE_ID, &format!("{:#}", err));
+                        self.error = err;
+                    }
+                    _ => {}
+                }
             }
+            Ok(ErrorStateTransition::Same(self))
         }
 
-        fn save_image(self) -> PlotState<End> {
-            /*
-            todo: implement save image
-            */
-            self
+        fn recover(self, plot: Image) -> TransitionResult<ErrorStateTransition> {
+            hide_ui();
+            let image_drawn = plot.has_image_data();
+            PlotState::new(
+                plot,
+                self.events,
+                Selection::new(SELECTION_FEATHER_RADIUS),
+                MaskPainter::new(Brush::default()),
+                image_drawn,
+            )
+            .map(ErrorStateTransition::Recover)
         }
     }
 }
@@ -0,0 +1,38 @@
+use crate::canvas::Point;
+use crate::events::{self, EventSender, Listener, UiEvent};
+use anyhow::Result;
+use wasm_bindgen::JsCast;
+use web_sys::{HtmlElement, MouseEvent};
+
+#[derive(Clone, Copy, Debug)]
+pub enum PointerEvent {
+    Down(Point),
+    Move(Point),
+    Up(Point),
+}
+
+pub fn add_pointer_handler(elem: &HtmlElement, sender: EventSender) -> Result<Vec<Listener>> {
+    Ok(vec![
+        pointer_listener(elem, "mousedown", sender.clone(), PointerEvent::Down)?,
+        pointer_listener(elem, "mousemove", sender.clone(), PointerEvent::Move)?,
+        pointer_listener(elem, "mouseup", sender.clone(), PointerEvent::Up)?,
+        pointer_listener(elem, "mouseleave", sender, PointerEvent::Up)?,
+    ])
+}
+
+fn pointer_listener(
+    elem: &HtmlElement,
+    event_type: &'static str,
+    sender: EventSender,
+    pointer_event: fn(Point) -> PointerEvent,
+) -> Result<Listener> {
+    Listener::new(elem, event_type, move |event| {
+        if let Some(mouse_event) = event.dyn_ref::<MouseEvent>() {
+            let position = Point {
+                x: mouse_event.offset_x() as i16,
+                y: mouse_event.offset_y() as i16,
+            };
+            events::send(&sender, UiEvent::Pointer(pointer_event(position)));
+        }
+    })
+}
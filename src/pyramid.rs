@@ -0,0 +1,332 @@
+use crate::image::RawImage;
+use crate::mask::Mask;
+use anyhow::{anyhow, Result};
+
+// Binomial approximation of a gaussian, as in Burt and Adelson.
+const KERNEL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+
+// Four channels in floats, since the levels of a Laplacian pyramid are
+// differences and go negative.
+#[derive(Clone)]
+pub struct Level {
+    values: Vec<[f32; 4]>,
+    width: u32,
+    height: u32,
+}
+
+impl Level {
+    pub fn from_raw(image: &RawImage) -> Self {
+        let values = image
+            .pixels()
+            .chunks_exact(4)
+            .map(|pixel| [0, 1, 2, 3].map(|c| pixel[c] as f32))
+            .collect();
+        Level {
+            values,
+            width: image.width(),
+            height: image.height(),
+        }
+    }
+
+    // The same weight in every channel.
+    fn from_mask(mask: &Mask) -> Self {
+        let values = mask
+            .values()
+            .iter()
+            .map(|&value| [value as f32 / 255.0; 4])
+            .collect();
+        Level {
+            values,
+            width: mask.width(),
+            height: mask.height(),
+        }
+    }
+
+    pub fn to_raw(&self) -> RawImage {
+        let pixels = self
+            .values
+            .iter()
+            .flat_map(|value| value.map(|channel| channel.round().clamp(0.0, 255.0) as u8))
+            .collect();
+        RawImage::from_raw(pixels, self.width, self.height)
+    }
+
+    // Borders are mirrored without repeating the edge pixel.
+    fn get(&self, x: i32, y: i32) -> [f32; 4] {
+        let reflect = |i: i32, size: u32| {
+            let last = size as i32 - 1;
+            if last == 0 {
+                0
+            } else {
+                let period = 2 * last;
+                let i = i.rem_euclid(period);
+                if i > last {
+                    period - i
+                } else {
+                    i
+                }
+            }
+        };
+        let (x, y) = (reflect(x, self.width), reflect(y, self.height));
+        self.values[(y as u32 * self.width + x as u32) as usize]
+    }
+
+    fn map(&self, other: &Level, f: impl Fn(f32, f32) -> f32) -> Level {
+        let values = self
+            .values
+            .iter()
+            .zip(&other.values)
+            .map(|(a, b)| [0, 1, 2, 3].map(|c| f(a[c], b[c])))
+            .collect();
+        Level { values, ..*self }
+    }
+
+    // Blurred with the binomial kernel and every other pixel dropped.
+    pub fn reduce(&self) -> Level {
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let horizontal: Vec<[f32; 4]> = (0..self.height as i32)
+            .flat_map(|y| (0..width as i32).map(move |x| (x, y)))
+            .map(|(x, y)| filter(|i| self.get(2 * x + i, y)))
+            .collect();
+        let horizontal = Level {
+            values: horizontal,
+            width,
+            height: self.height,
+        };
+        let values = (0..height as i32)
+            .flat_map(|y| (0..width as i32).map(move |x| (x, y)))
+            .map(|(x, y)| filter(|i| horizontal.get(x, 2 * y + i)))
+            .collect();
+        Level {
+            values,
+            width,
+            height,
+        }
+    }
+
+    // Zeros are inserted between the pixels and filled in by the same
+    // kernel, doubled for each axis to keep the brightness.
+    pub fn expand(&self, width: u32, height: u32) -> Level {
+        let spread = |i: i32, source: &dyn Fn(i32) -> [f32; 4]| {
+            let mut sum = [0.0; 4];
+            for (offset, weight) in (-2..=2).zip(KERNEL) {
+                let position = i + offset;
+                if position % 2 == 0 {
+                    let value = source(position / 2);
+                    for c in 0..4 {
+                        sum[c] += 2.0 * weight * value[c];
+                    }
+                }
+            }
+            sum
+        };
+        let horizontal: Vec<[f32; 4]> = (0..self.height as i32)
+            .flat_map(|y| (0..width as i32).map(move |x| (x, y)))
+            .map(|(x, y)| spread(x, &|i| self.get(i, y)))
+            .collect();
+        let horizontal = Level {
+            values: horizontal,
+            width,
+            height: self.height,
+        };
+        let values = (0..height as i32)
+            .flat_map(|y| (0..width as i32).map(move |x| (x, y)))
+            .map(|(x, y)| spread(y, &|i| horizontal.get(x, i)))
+            .collect();
+        Level {
+            values,
+            width,
+            height,
+        }
+    }
+}
+
+fn filter(sample: impl Fn(i32) -> [f32; 4]) -> [f32; 4] {
+    let mut sum = [0.0; 4];
+    for (offset, weight) in (-2..=2).zip(KERNEL) {
+        let value = sample(offset);
+        for c in 0..4 {
+            sum[c] += weight * value[c];
+        }
+    }
+    sum
+}
+
+pub fn gaussian(image: &RawImage, levels: usize) -> Vec<Level> {
+    reduce(Level::from_raw(image), levels)
+}
+
+// Finest level first, stopping early once a side would drop below two
+// pixels.
+fn reduce(level: Level, levels: usize) -> Vec<Level> {
+    let mut pyramid = vec![level];
+    while pyramid.len() < levels {
+        let last = &pyramid[pyramid.len() - 1];
+        if last.width.min(last.height) < 4 {
+            break;
+        }
+        pyramid.push(last.reduce());
+    }
+    pyramid
+}
+
+// Each level keeps the detail lost by reducing it, and the last one is the
+// coarsest gaussian level, so that the image can be rebuilt exactly.
+pub fn laplacian(image: &RawImage, levels: usize) -> Vec<Level> {
+    let gaussian = gaussian(image, levels);
+    let mut pyramid: Vec<Level> = gaussian
+        .windows(2)
+        .map(|pair| {
+            let expanded = pair[1].expand(pair[0].width, pair[0].height);
+            pair[0].map(&expanded, |fine, coarse| fine - coarse)
+        })
+        .collect();
+    pyramid.extend(gaussian.last().cloned());
+    pyramid
+}
+
+pub fn reconstruct(pyramid: &[Level]) -> Option<RawImage> {
+    let (coarsest, details) = pyramid.split_last()?;
+    let image = details
+        .iter()
+        .rev()
+        .fold(coarsest.clone(), |image, detail| {
+            let expanded = image.expand(detail.width, detail.height);
+            detail.map(&expanded, |detail, coarse| detail + coarse)
+        });
+    Some(image.to_raw())
+}
+
+// Multi-band blending: every band of detail is mixed through a mask blurred
+// to the same scale, so coarse structure fades over a wide seam while fine
+// texture switches sharply. Where the mask is set the overlay wins.
+pub fn blend(base: &RawImage, overlay: &RawImage, mask: &Mask, levels: usize) -> Result<RawImage> {
+    let (width, height) = (base.width(), base.height());
+    if overlay.width() != width
+        || overlay.height() != height
+        || mask.width() != width
+        || mask.height() != height
+    {
+        return Err(anyhow!(
+            "Cannot blend {}x{} over {}x{} through a {}x{} mask",
+            overlay.width(),
+            overlay.height(),
+            width,
+            height,
+            mask.width(),
+            mask.height()
+        ));
+    }
+    let base = laplacian(base, levels);
+    let overlay = laplacian(overlay, levels);
+    let weights = reduce(Level::from_mask(mask), base.len());
+    let blended: Vec<Level> = base
+        .iter()
+        .zip(&overlay)
+        .zip(&weights)
+        .map(|((base, overlay), weight)| {
+            let values = base
+                .values
+                .iter()
+                .zip(&overlay.values)
+                .zip(&weight.values)
+                .map(|((base, overlay), weight)| {
+                    [0, 1, 2, 3].map(|c| base[c] + weight[c] * (overlay[c] - base[c]))
+                })
+                .collect();
+            Level { values, ..*base }
+        })
+        .collect();
+    reconstruct(&blended).ok_or_else(|| anyhow!("Cannot blend empty pyramids"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Xorshift;
+
+    fn noise(width: u32, height: u32, seed: u64) -> RawImage {
+        let mut random = Xorshift::new(seed);
+        let pixels = (0..width * height * 4)
+            .map(|_| random.below(256) as u8)
+            .collect();
+        RawImage::from_raw(pixels, width, height)
+    }
+
+    fn flat(color: [u8; 4], width: u32, height: u32) -> RawImage {
+        RawImage::from_raw(color.repeat((width * height) as usize), width, height)
+    }
+
+    fn max_difference(a: &RawImage, b: &RawImage) -> u8 {
+        a.pixels()
+            .iter()
+            .zip(b.pixels())
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn levels_halve_until_a_side_gets_too_short() {
+        let sizes: Vec<_> = gaussian(&noise(37, 21, 1), 10)
+            .iter()
+            .map(|level| (level.width, level.height))
+            .collect();
+        assert_eq!(sizes, [(37, 21), (19, 11), (10, 6), (5, 3)]);
+    }
+
+    #[test]
+    fn flat_image_stays_flat_through_reduce_and_expand() {
+        let level = Level::from_raw(&flat([10, 120, 250, 255], 9, 7));
+        let round_trip = level.reduce().expand(9, 7);
+        for value in round_trip.values {
+            for (channel, expected) in value.iter().zip([10.0, 120.0, 250.0, 255.0]) {
+                assert!((channel - expected).abs() < 1e-3, "{:?}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn laplacian_pyramid_rebuilds_the_image() {
+        let image = noise(37, 21, 2);
+        let rebuilt = reconstruct(&laplacian(&image, 6)).unwrap();
+        assert_eq!(rebuilt.pixels(), image.pixels());
+        assert!(reconstruct(&[]).is_none());
+    }
+
+    #[test]
+    fn full_and_empty_masks_pick_one_image() {
+        let (base, overlay) = (noise(32, 24, 3), noise(32, 24, 4));
+        let mut full = Mask::new(32, 24);
+        full.values_mut().fill(255);
+        let blended = blend(&base, &overlay, &full, 5).unwrap();
+        assert!(max_difference(&blended, &overlay) <= 1);
+        let blended = blend(&base, &overlay, &Mask::new(32, 24), 5).unwrap();
+        assert!(max_difference(&blended, &base) <= 1);
+    }
+
+    // Far from the seam each side keeps its own image.
+    #[test]
+    fn half_mask_switches_images_across_the_seam() {
+        let (base, overlay) = (
+            flat([0, 0, 0, 255], 64, 16),
+            flat([200, 100, 50, 255], 64, 16),
+        );
+        let mut half = Mask::new(64, 16);
+        for row in half.values_mut().chunks_exact_mut(64) {
+            row[32..].fill(255);
+        }
+        let blended = blend(&base, &overlay, &half, 3).unwrap();
+        let at = |x: usize| &blended.pixels()[4 * (8 * 64 + x)..4 * (8 * 64 + x) + 4];
+        assert_eq!(at(2), [0, 0, 0, 255]);
+        assert_eq!(at(61), [200, 100, 50, 255]);
+        assert!(at(31)[0] > 0 && at(32)[0] < 200);
+    }
+
+    #[test]
+    fn mismatched_sizes_are_rejected() {
+        let base = noise(8, 8, 5);
+        assert!(blend(&base, &noise(8, 6, 6), &Mask::new(8, 8), 3).is_err());
+        assert!(blend(&base, &noise(8, 8, 6), &Mask::new(4, 8), 3).is_err());
+    }
+}
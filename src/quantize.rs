@@ -0,0 +1,460 @@
+use crate::constants::{KMEANS_ITERATIONS, KMEANS_SEED, OCTREE_DEPTH, QUANTIZE_SAMPLES};
+use crate::image::RawImage;
+use crate::random::Xorshift;
+use std::collections::HashMap;
+
+pub type Color = [u8; 3];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuantizeMethod {
+    MedianCut,
+    Octree,
+    KMeans,
+}
+
+impl QuantizeMethod {
+    pub fn next(self) -> Self {
+        match self {
+            QuantizeMethod::MedianCut => QuantizeMethod::Octree,
+            QuantizeMethod::Octree => QuantizeMethod::KMeans,
+            QuantizeMethod::KMeans => QuantizeMethod::MedianCut,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            QuantizeMethod::MedianCut => "Palette: median cut",
+            QuantizeMethod::Octree => "Palette: octree",
+            QuantizeMethod::KMeans => "Palette: k-means",
+        }
+    }
+}
+
+// Returns the remapped image together with its palette.
+pub fn quantize(image: &RawImage, colors: usize, method: QuantizeMethod) -> (RawImage, Vec<Color>) {
+    let palette = palette(image, colors, method);
+    (remap(image, &palette), palette)
+}
+
+// The palette is sorted by population, so the dominant colors come first.
+pub fn palette(image: &RawImage, colors: usize, method: QuantizeMethod) -> Vec<Color> {
+    let samples = samples(image);
+    if samples.is_empty() || colors == 0 {
+        return vec![];
+    }
+    let mut palette = match method {
+        QuantizeMethod::MedianCut => median_cut(samples, colors),
+        QuantizeMethod::Octree => octree(&samples, colors),
+        QuantizeMethod::KMeans => kmeans(&samples, colors),
+    };
+    palette.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    palette.into_iter().map(|(color, _)| color).collect()
+}
+
+pub fn remap(image: &RawImage, palette: &[Color]) -> RawImage {
+    let mut output = image.clone();
+    if palette.is_empty() {
+        return output;
+    }
+    // Photos repeat colors a lot, so each distinct one is only searched once.
+    let mut cache: HashMap<Color, usize> = HashMap::new();
+    for pixel in output.pixels_mut().chunks_exact_mut(4) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = *cache
+            .entry(color)
+            .or_insert_with(|| nearest(palette, &color));
+        pixel[..3].copy_from_slice(&palette[index]);
+    }
+    output
+}
+
+fn samples(image: &RawImage) -> Vec<Color> {
+    let count = image.pixels().len() / 4;
+    let stride = (count / QUANTIZE_SAMPLES).max(1);
+    image
+        .pixels()
+        .chunks_exact(4)
+        .step_by(stride)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect()
+}
+
+pub fn nearest(palette: &[Color], color: &Color) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, candidate)| {
+            (0..3)
+                .map(|c| {
+                    let d = candidate[c] as i32 - color[c] as i32;
+                    d * d
+                })
+                .sum::<i32>()
+        })
+        .map_or(0, |(index, _)| index)
+}
+
+fn mean(colors: &[Color]) -> Color {
+    let mut sum = [0u64; 3];
+    for color in colors {
+        for (total, value) in sum.iter_mut().zip(color) {
+            *total += *value as u64;
+        }
+    }
+    let count = colors.len().max(1) as u64;
+    [
+        (sum[0] / count) as u8,
+        (sum[1] / count) as u8,
+        (sum[2] / count) as u8,
+    ]
+}
+
+// ---------------------
+// -    median cut     -
+// ---------------------
+
+fn median_cut(samples: Vec<Color>, colors: usize) -> Vec<(Color, usize)> {
+    let mut boxes = vec![samples];
+    while boxes.len() < colors {
+        // Split the box with the widest channel at its median.
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(index, colors)| {
+                let (channel, range) = widest_channel(colors);
+                (index, channel, range)
+            })
+            .max_by_key(|(_, _, range)| *range);
+        let (index, channel) = match widest {
+            Some((index, channel, range)) if range > 0 => (index, channel),
+            _ => break,
+        };
+        let mut lower = boxes.swap_remove(index);
+        lower.sort_unstable_by_key(|color| color[channel]);
+        // The cut moves to the closest change of value, so that equal colors
+        // never end up on both sides of it.
+        let middle = lower.len() / 2;
+        let median = lower[middle][channel];
+        let below = lower.partition_point(|color| color[channel] < median);
+        let above = lower.partition_point(|color| color[channel] <= median);
+        let split = if below > 0 && (middle - below <= above - middle || above == lower.len()) {
+            below
+        } else {
+            above
+        };
+        let upper = lower.split_off(split);
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+    boxes
+        .iter()
+        .map(|colors| (mean(colors), colors.len()))
+        .collect()
+}
+
+fn widest_channel(colors: &[Color]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let (min, max) = colors.iter().fold((u8::MAX, u8::MIN), |(min, max), color| {
+                (min.min(color[c]), max.max(color[c]))
+            });
+            (c, max.saturating_sub(min))
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+// ---------------------
+// -      octree       -
+// ---------------------
+
+#[derive(Clone, Default)]
+struct OctreeNode {
+    // Index 0 is the root, which is never a child, so it marks a missing child.
+    children: [usize; 8],
+    sum: [u64; 3],
+    count: usize,
+    // Samples that went through this node, leaves included.
+    population: usize,
+    leaf: bool,
+}
+
+fn octree(samples: &[Color], colors: usize) -> Vec<(Color, usize)> {
+    let mut nodes = vec![OctreeNode::default()];
+    let mut reducible: Vec<Vec<usize>> = vec![vec![]; OCTREE_DEPTH];
+    reducible[0].push(0);
+    let mut leaves = 0;
+
+    for color in samples {
+        let mut node = 0;
+        nodes[node].population += 1;
+        for level in 0..OCTREE_DEPTH {
+            let shift = 7 - level;
+            let branch = ((color[0] >> shift & 1) << 2
+                | (color[1] >> shift & 1) << 1
+                | (color[2] >> shift & 1)) as usize;
+            if nodes[node].children[branch] == 0 {
+                let leaf = level + 1 == OCTREE_DEPTH;
+                nodes.push(OctreeNode {
+                    leaf,
+                    ..OctreeNode::default()
+                });
+                let child = nodes.len() - 1;
+                nodes[node].children[branch] = child;
+                if leaf {
+                    leaves += 1;
+                } else {
+                    reducible[level + 1].push(child);
+                }
+            }
+            node = nodes[node].children[branch];
+            nodes[node].population += 1;
+        }
+        let node = &mut nodes[node];
+        for (total, value) in node.sum.iter_mut().zip(color) {
+            *total += *value as u64;
+        }
+        node.count += 1;
+    }
+
+    // Fold the least populated of the deepest nodes into their parents. A level
+    // is sorted once, its populations don't change while it is being reduced.
+    let mut sorted = None;
+    while leaves > colors {
+        let level = match reducible.iter().rposition(|nodes| !nodes.is_empty()) {
+            Some(level) => level,
+            None => break,
+        };
+        if sorted != Some(level) {
+            reducible[level].sort_by_key(|&node| std::cmp::Reverse(nodes[node].population));
+            sorted = Some(level);
+        }
+        let node = match reducible[level].pop() {
+            Some(node) => node,
+            None => break,
+        };
+
+        let children = std::mem::take(&mut nodes[node].children);
+        for child in children.into_iter().filter(|&child| child != 0) {
+            let (sum, count) = (nodes[child].sum, nodes[child].count);
+            nodes[child].count = 0;
+            let parent = &mut nodes[node];
+            for (total, value) in parent.sum.iter_mut().zip(sum) {
+                *total += value;
+            }
+            parent.count += count;
+            leaves -= 1;
+        }
+        nodes[node].leaf = true;
+        leaves += 1;
+    }
+
+    nodes
+        .iter()
+        .filter(|node| node.leaf && node.count > 0)
+        .map(|node| {
+            let count = node.count as u64;
+            let color = [
+                (node.sum[0] / count) as u8,
+                (node.sum[1] / count) as u8,
+                (node.sum[2] / count) as u8,
+            ];
+            (color, node.count)
+        })
+        .collect()
+}
+
+// ---------------------
+// -      k-means      -
+// ---------------------
+
+fn kmeans(samples: &[Color], colors: usize) -> Vec<(Color, usize)> {
+    let mut random = Xorshift::new(KMEANS_SEED);
+    let mut centers = vec![to_point(&samples[random.below(samples.len())])];
+    let mut distances: Vec<f32> = samples
+        .iter()
+        .map(|sample| distance(&to_point(sample), &centers[0]))
+        .collect();
+
+    // k-means++ seeding, with a fixed seed so the palette is stable across runs.
+    while centers.len() < colors {
+        let total: f64 = distances.iter().map(|&d| d as f64).sum();
+        if total <= 0.0 {
+            break;
+        }
+        let mut target = random.next_f64() * total;
+        let mut chosen = None;
+        for (i, &d) in distances.iter().enumerate() {
+            if d > 0.0 && target < d as f64 {
+                chosen = Some(i);
+                break;
+            }
+            target -= d as f64;
+        }
+        let chosen = match chosen.or_else(|| distances.iter().rposition(|&d| d > 0.0)) {
+            Some(chosen) => chosen,
+            None => break,
+        };
+        let center = to_point(&samples[chosen]);
+        for (d, sample) in distances.iter_mut().zip(samples) {
+            *d = d.min(distance(&to_point(sample), &center));
+        }
+        centers.push(center);
+    }
+
+    let mut assignments = vec![usize::MAX; samples.len()];
+    let mut counts = vec![0; centers.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![[0.0f64; 3]; centers.len()];
+        counts = vec![0; centers.len()];
+        let mut changed = false;
+        for (sample, assignment) in samples.iter().zip(assignments.iter_mut()) {
+            let point = to_point(sample);
+            let closest = (0..centers.len())
+                .min_by(|&a, &b| {
+                    distance(&point, &centers[a]).total_cmp(&distance(&point, &centers[b]))
+                })
+                .unwrap_or(0);
+            changed |= closest != *assignment;
+            *assignment = closest;
+            for c in 0..3 {
+                sums[closest][c] += point[c] as f64;
+            }
+            counts[closest] += 1;
+        }
+        if !changed {
+            break;
+        }
+        for ((center, sum), &count) in centers.iter_mut().zip(&sums).zip(&counts) {
+            if count > 0 {
+                for c in 0..3 {
+                    center[c] = (sum[c] / count as f64) as f32;
+                }
+            }
+        }
+    }
+
+    centers
+        .iter()
+        .zip(counts)
+        .filter(|(_, count)| *count > 0)
+        .map(|(center, count)| {
+            let color = [
+                center[0].round() as u8,
+                center[1].round() as u8,
+                center[2].round() as u8,
+            ];
+            (color, count)
+        })
+        .collect()
+}
+
+fn to_point(color: &Color) -> [f32; 3] {
+    [color[0] as f32, color[1] as f32, color[2] as f32]
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [QuantizeMethod; 3] = [
+        QuantizeMethod::MedianCut,
+        QuantizeMethod::Octree,
+        QuantizeMethod::KMeans,
+    ];
+
+    // Four flat colors, each covering fewer pixels than the one before.
+    fn blocks() -> RawImage {
+        let colors: [(Color, usize); 4] = [
+            ([200, 30, 40], 40),
+            ([20, 180, 60], 30),
+            ([30, 40, 220], 20),
+            ([240, 240, 230], 10),
+        ];
+        let pixels: Vec<u8> = colors
+            .iter()
+            .flat_map(|&(color, count)| (0..count).map(move |_| color))
+            .flat_map(|[r, g, b]| [r, g, b, 255])
+            .collect();
+        RawImage::from_raw(pixels, 10, 10)
+    }
+
+    #[test]
+    fn every_method_finds_the_colors_of_a_flat_image() {
+        for method in METHODS {
+            let (image, palette) = quantize(&blocks(), 4, method);
+            assert_eq!(
+                palette,
+                [[200, 30, 40], [20, 180, 60], [30, 40, 220], [240, 240, 230]],
+                "{:?}",
+                method
+            );
+            assert_eq!(image.pixels(), blocks().pixels(), "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn fewer_colors_are_shared_by_the_pixels() {
+        for method in METHODS {
+            // Folding an octree node drops all of its leaves at once, so it may
+            // end with fewer colors than asked for.
+            let (image, palette) = quantize(&blocks(), 2, method);
+            assert!((1..=2).contains(&palette.len()), "{:?}", method);
+            let mut used: Vec<&[u8]> = image.pixels().chunks_exact(4).map(|p| &p[..3]).collect();
+            used.sort();
+            used.dedup();
+            assert_eq!(used.len(), palette.len(), "{:?}", method);
+            assert!(used.iter().all(|color| palette.iter().any(|p| p == color)));
+        }
+    }
+
+    #[test]
+    fn remap_picks_the_nearest_color() {
+        let palette = [[0, 0, 0], [255, 255, 255], [255, 0, 0]];
+        assert_eq!(nearest(&palette, &[100, 90, 110]), 0);
+        assert_eq!(nearest(&palette, &[200, 190, 210]), 1);
+        assert_eq!(nearest(&palette, &[220, 40, 30]), 2);
+        let image = RawImage::from_raw(vec![100, 90, 110, 7, 220, 40, 30, 9], 2, 1);
+        assert_eq!(remap(&image, &palette).pixels(), [0, 0, 0, 7, 255, 0, 0, 9]);
+        assert_eq!(remap(&image, &[]).pixels(), image.pixels());
+        assert!(super::palette(&RawImage::new(), 8, QuantizeMethod::KMeans).is_empty());
+    }
+
+    // Palette colors a step apart share 5 bit cells, which is where a coarse
+    // lookup would go wrong.
+    #[test]
+    fn remap_agrees_with_a_brute_force_search() {
+        let mut random = Xorshift::new(7);
+        let palette: Vec<Color> = (0..24)
+            .map(|index| {
+                let base = [96 + 2 * index as u8, 120, 64 + index as u8];
+                base.map(|value| value + random.below(3) as u8)
+            })
+            .collect();
+        let pixels: Vec<u8> = (0..64 * 64)
+            .flat_map(|_| {
+                [
+                    80 + random.below(80),
+                    100 + random.below(40),
+                    50 + random.below(60),
+                    255,
+                ]
+            })
+            .map(|value| value as u8)
+            .collect();
+        let image = RawImage::from_raw(pixels, 64, 64);
+        let remapped = remap(&image, &palette);
+        for (out, pixel) in remapped
+            .pixels()
+            .chunks_exact(4)
+            .zip(image.pixels().chunks_exact(4))
+        {
+            let color = [pixel[0], pixel[1], pixel[2]];
+            assert_eq!(out[..3], palette[nearest(&palette, &color)], "{:?}", color);
+        }
+    }
+}
@@ -0,0 +1,512 @@
+use crate::binary::luma;
+use crate::constants::{CARVE_INSERT_FRACTION, CARVE_MASK_ENERGY};
+use crate::gray::GrayImage;
+use crate::image::RawImage;
+use crate::mask::Mask;
+
+// Which seams each simulation step removes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CarveMode {
+    Off,
+    Width,
+    Height,
+}
+
+impl CarveMode {
+    pub fn next(self) -> Self {
+        match self {
+            CarveMode::Off => CarveMode::Width,
+            CarveMode::Width => CarveMode::Height,
+            CarveMode::Height => CarveMode::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CarveMode::Off => "Carve: off",
+            CarveMode::Width => "Carve: width",
+            CarveMode::Height => "Carve: height",
+        }
+    }
+}
+
+// What the selection or painted mask means to the seams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaskRole {
+    Ignore,
+    Protect,
+    Remove,
+}
+
+impl MaskRole {
+    pub fn next(self) -> Self {
+        match self {
+            MaskRole::Ignore => MaskRole::Protect,
+            MaskRole::Protect => MaskRole::Remove,
+            MaskRole::Remove => MaskRole::Ignore,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MaskRole::Ignore => "Carve mask: ignore",
+            MaskRole::Protect => "Carve mask: protect",
+            MaskRole::Remove => "Carve mask: remove",
+        }
+    }
+}
+
+// How the frame is carved, and the seams that takes from or adds to it.
+pub struct SeamCarver {
+    mode: CarveMode,
+    mask_role: MaskRole,
+}
+
+impl SeamCarver {
+    pub fn new() -> Self {
+        SeamCarver {
+            mode: CarveMode::Off,
+            mask_role: MaskRole::Protect,
+        }
+    }
+
+    pub fn mode(&self) -> CarveMode {
+        self.mode
+    }
+
+    pub fn next_mode(&mut self) -> CarveMode {
+        self.mode = self.mode.next();
+        self.mode
+    }
+
+    pub fn mask_role(&self) -> MaskRole {
+        self.mask_role
+    }
+
+    pub fn next_mask_role(&mut self) -> MaskRole {
+        self.mask_role = self.mask_role.next();
+        self.mask_role
+    }
+
+    // One seam per simulation step, so that the frame visibly shrinks.
+    pub fn carve_seam(&self, image: &mut RawImage, mask: Option<&Mask>) {
+        if self.mode == CarveMode::Off {
+            return;
+        }
+        if let Some(mut carving) = Carving::from_frame(image, mask, self.mask_role) {
+            if carving.remove_seam(self.mode) {
+                *image = carving.to_frame();
+            }
+        }
+    }
+
+    // Seams are added along the side being carved, or the width when nothing
+    // is.
+    pub fn add_seams(&self, image: &mut RawImage, mask: Option<&Mask>) -> usize {
+        let mode = match self.mode {
+            CarveMode::Off => CarveMode::Width,
+            mode => mode,
+        };
+        let Some(mut carving) = Carving::from_frame(image, mask, self.mask_role) else {
+            return 0;
+        };
+        let (width, height) = carving.size();
+        let side = if mode == CarveMode::Height {
+            height
+        } else {
+            width
+        };
+        let count = ((side as f64 * CARVE_INSERT_FRACTION).ceil() as usize).max(1);
+        let inserted = carving.insert_seams(mode, count);
+        if inserted > 0 {
+            *image = carving.to_frame();
+        }
+        log!("{} seams added to a {}x{} image", inserted, width, height);
+        inserted
+    }
+}
+
+// The opaque part of a frame, cut out together with the energy the mask adds
+// to each of its pixels so that both lose the same seams. Vertical seams are
+// found directly and horizontal ones on the transposed image.
+pub struct Carving {
+    pixels: Vec<u8>,
+    bias: Vec<f32>,
+    width: usize,
+    height: usize,
+    offset: (usize, usize),
+    frame: (usize, usize),
+}
+
+impl Carving {
+    pub fn from_frame(frame: &RawImage, mask: Option<&Mask>, role: MaskRole) -> Option<Self> {
+        let (frame_width, frame_height) = (frame.width() as usize, frame.height() as usize);
+        let opaque = |index: usize| frame.pixels()[4 * index + 3] > 0;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+        for index in (0..frame_width * frame_height).filter(|&index| opaque(index)) {
+            let (x, y) = (index % frame_width, index / frame_width);
+            (min_x, min_y) = (min_x.min(x), min_y.min(y));
+            (max_x, max_y) = (max_x.max(x), max_y.max(y));
+        }
+        if min_x > max_x {
+            return None;
+        }
+        let (width, height) = (max_x - min_x + 1, max_y - min_y + 1);
+        let rows = || (min_y..=max_y).map(|y| y * frame_width + min_x);
+        let pixels = rows()
+            .flat_map(|start| &frame.pixels()[4 * start..4 * (start + width)])
+            .copied()
+            .collect();
+
+        let sign = match role {
+            MaskRole::Ignore => 0.0,
+            MaskRole::Protect => 1.0,
+            MaskRole::Remove => -1.0,
+        };
+        let bias = match mask {
+            Some(mask)
+                if sign != 0.0
+                    && mask.width() as usize == frame_width
+                    && mask.height() as usize == frame_height =>
+            {
+                rows()
+                    .flat_map(|start| &mask.values()[start..start + width])
+                    .map(|&value| sign * CARVE_MASK_ENERGY * value as f32 / 255.0)
+                    .collect()
+            }
+            _ => vec![0.0; width * height],
+        };
+        Some(Carving {
+            pixels,
+            bias,
+            width,
+            height,
+            offset: (min_x, min_y),
+            frame: (frame_width, frame_height),
+        })
+    }
+
+    // Put back where it was cut from, with whatever it gave up left
+    // transparent.
+    pub fn to_frame(&self) -> RawImage {
+        let (frame_width, frame_height) = self.frame;
+        let (x, y) = self.offset;
+        let mut pixels = vec![0; 4 * frame_width * frame_height];
+        for (row, source) in self.pixels.chunks_exact(4 * self.width).enumerate() {
+            let start = 4 * ((y + row) * frame_width + x);
+            pixels[start..start + source.len()].copy_from_slice(source);
+        }
+        RawImage::from_raw(pixels, frame_width as u32, frame_height as u32)
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    // False once there is a single line of pixels left.
+    pub fn remove_seam(&mut self, mode: CarveMode) -> bool {
+        match mode {
+            CarveMode::Off => false,
+            CarveMode::Width => self.remove_vertical(),
+            CarveMode::Height => self.transposed(Carving::remove_vertical),
+        }
+    }
+
+    // The inserted seams are all found at once, on a copy that loses them one
+    // after another, so that they spread out instead of stretching the same
+    // place over and over. As many as fit inside the frame are inserted.
+    pub fn insert_seams(&mut self, mode: CarveMode, count: usize) -> usize {
+        match mode {
+            CarveMode::Off => 0,
+            CarveMode::Width => {
+                let room = self.frame.0 - self.offset.0 - self.width;
+                self.insert_vertical(count.min(room))
+            }
+            CarveMode::Height => {
+                let room = self.frame.1 - self.offset.1 - self.height;
+                self.transposed(|carving| carving.insert_vertical(count.min(room)))
+            }
+        }
+    }
+
+    fn transposed<T>(&mut self, carve: impl FnOnce(&mut Self) -> T) -> T {
+        self.transpose();
+        let result = carve(self);
+        self.transpose();
+        result
+    }
+
+    fn transpose(&mut self) {
+        self.pixels = transpose(&self.pixels, 4, self.width, self.height);
+        self.bias = transpose(&self.bias, 1, self.width, self.height);
+        (self.width, self.height) = (self.height, self.width);
+    }
+
+    fn remove_vertical(&mut self) -> bool {
+        if self.width < 2 {
+            return false;
+        }
+        let seam = vertical_seam(
+            &energy(&self.pixels, &self.bias, self.width, self.height),
+            self.width,
+            self.height,
+        );
+        self.pixels = remove(&self.pixels, 4, self.width, &seam);
+        self.bias = remove(&self.bias, 1, self.width, &seam);
+        self.width -= 1;
+        true
+    }
+
+    fn insert_vertical(&mut self, count: usize) -> usize {
+        let (width, height) = (self.width, self.height);
+        let count = count.min(width);
+        let mut pixels = self.pixels.clone();
+        let mut bias = self.bias.clone();
+        let mut columns: Vec<usize> = (0..height).flat_map(|_| 0..width).collect();
+        let mut duplicated = vec![false; width * height];
+        for remaining in (width - count + 1..=width).rev() {
+            let seam = vertical_seam(
+                &energy(&pixels, &bias, remaining, height),
+                remaining,
+                height,
+            );
+            for (y, &x) in seam.iter().enumerate() {
+                duplicated[y * width + columns[y * remaining + x]] = true;
+            }
+            pixels = remove(&pixels, 4, remaining, &seam);
+            bias = remove(&bias, 1, remaining, &seam);
+            columns = remove(&columns, 1, remaining, &seam);
+        }
+
+        // Each copy is the average of the seam pixel and its right neighbour.
+        let mut pixels = Vec::with_capacity(4 * (width + count) * height);
+        let mut bias = Vec::with_capacity((width + count) * height);
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let pixel = &self.pixels[4 * index..4 * index + 4];
+                pixels.extend_from_slice(pixel);
+                bias.push(self.bias[index]);
+                if duplicated[index] {
+                    let next = y * width + (x + 1).min(width - 1);
+                    let neighbour = &self.pixels[4 * next..4 * next + 4];
+                    pixels.extend(
+                        pixel
+                            .iter()
+                            .zip(neighbour)
+                            .map(|(&a, &b)| ((a as u16 + b as u16) / 2) as u8),
+                    );
+                    bias.push(self.bias[index]);
+                }
+            }
+        }
+        self.pixels = pixels;
+        self.bias = bias;
+        self.width += count;
+        count
+    }
+}
+
+// Gradient magnitude, shifted by the mask.
+fn energy(pixels: &[u8], bias: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let gray = GrayImage::new(
+        pixels
+            .chunks_exact(4)
+            .map(|pixel| luma(pixel) as f32)
+            .collect(),
+        width as u32,
+        height as u32,
+    );
+    let (gx, gy) = gray.sobel();
+    gx.values()
+        .iter()
+        .zip(gy.values())
+        .zip(bias)
+        .map(|((gx, gy), bias)| gx.hypot(*gy) + bias)
+        .collect()
+}
+
+// The connected path from top to bottom, one pixel per row, with the least
+// total energy: each row adds the cheapest of the three costs above it, and
+// the path is traced back from the cheapest end.
+fn vertical_seam(energy: &[f32], width: usize, height: usize) -> Vec<usize> {
+    let mut cost = energy.to_vec();
+    for y in 1..height {
+        let (done, rest) = cost.split_at_mut(y * width);
+        let above = &done[(y - 1) * width..];
+        for (x, value) in rest[..width].iter_mut().enumerate() {
+            let (low, high) = (x.saturating_sub(1), (x + 1).min(width - 1));
+            *value += above[low..=high].iter().fold(f32::MAX, |a, &b| a.min(b));
+        }
+    }
+    let cheapest = |row: &[f32], low: usize| {
+        row.iter()
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(b.1))
+            .map_or(low, |(x, _)| low + x)
+    };
+    let mut seam = vec![0; height];
+    seam[height - 1] = cheapest(&cost[(height - 1) * width..], 0);
+    for y in (0..height - 1).rev() {
+        let x = seam[y + 1];
+        let (low, high) = (x.saturating_sub(1), (x + 1).min(width - 1));
+        seam[y] = cheapest(&cost[y * width + low..=y * width + high], low);
+    }
+    seam
+}
+
+fn remove<T: Copy>(values: &[T], channels: usize, width: usize, seam: &[usize]) -> Vec<T> {
+    values
+        .chunks_exact(width * channels)
+        .zip(seam)
+        .flat_map(|(row, &x)| {
+            row[..x * channels]
+                .iter()
+                .chain(&row[(x + 1) * channels..])
+                .copied()
+        })
+        .collect()
+}
+
+fn transpose<T: Copy>(values: &[T], channels: usize, width: usize, height: usize) -> Vec<T> {
+    (0..width)
+        .flat_map(|x| {
+            (0..height).flat_map(move |y| {
+                let start = (y * width + x) * channels;
+                values[start..start + channels].iter().copied()
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+    // Columns left of the edge are black, the others white. Columns at or
+    // beyond the opaque width are transparent.
+    fn edge(width: u32, height: u32, edge: u32, opaque: u32) -> RawImage {
+        let pixels = (0..height)
+            .flat_map(|_| 0..width)
+            .flat_map(|x| match x {
+                x if x >= opaque => [0; 4],
+                x if x < edge => BLACK,
+                _ => WHITE,
+            })
+            .collect();
+        RawImage::from_raw(pixels, width, height)
+    }
+
+    fn row(image: &RawImage, y: usize) -> Vec<[u8; 4]> {
+        let width = image.width() as usize;
+        image.pixels()[4 * y * width..4 * (y + 1) * width]
+            .chunks_exact(4)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+            .collect()
+    }
+
+    fn carver(mode: CarveMode, role: MaskRole) -> SeamCarver {
+        let mut carver = SeamCarver::new();
+        while carver.mode() != mode {
+            carver.next_mode();
+        }
+        while carver.mask_role() != role {
+            carver.next_mask_role();
+        }
+        carver
+    }
+
+    #[test]
+    fn seam_follows_the_cheapest_path() {
+        // A valley that steps one column to the right on every row.
+        let (width, height) = (6, 4);
+        let energy: Vec<f32> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| if x == y + 1 { 0.0 } else { 5.0 }))
+            .collect();
+        assert_eq!(vertical_seam(&energy, width, height), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn removed_seam_keeps_away_from_the_edge() {
+        let mut image = edge(12, 8, 6, 12);
+        carver(CarveMode::Width, MaskRole::Ignore).carve_seam(&mut image, None);
+        assert_eq!((image.width(), image.height()), (12, 8));
+        for y in 0..8 {
+            let row = row(&image, y);
+            // Still one sharp step from black to white, one column shorter.
+            let opaque = &row[..11];
+            assert!(opaque.iter().all(|&pixel| pixel == BLACK || pixel == WHITE));
+            assert_eq!(
+                opaque.windows(2).filter(|pair| pair[0] != pair[1]).count(),
+                1
+            );
+            assert_eq!(row[11], [0; 4]);
+        }
+    }
+
+    #[test]
+    fn height_mode_removes_a_row() {
+        let mut image = edge(12, 8, 6, 12);
+        carver(CarveMode::Height, MaskRole::Ignore).carve_seam(&mut image, None);
+        assert_eq!(row(&image, 7), [[0; 4]; 12]);
+        assert_eq!(row(&image, 6), row(&edge(12, 8, 6, 12), 6));
+    }
+
+    #[test]
+    fn mask_steers_the_seam() {
+        let image = edge(8, 4, 8, 8);
+        let mut mask = Mask::new(8, 4);
+        for row in mask.values_mut().chunks_exact_mut(8) {
+            row[5] = 255;
+        }
+        let mut carving = Carving::from_frame(&image, Some(&mask), MaskRole::Remove).unwrap();
+        assert!(carving.remove_seam(CarveMode::Width));
+        assert!(carving.bias.iter().all(|&bias| bias == 0.0));
+
+        // Protected everywhere but one column, the seam has to take it.
+        mask.values_mut()
+            .iter_mut()
+            .for_each(|value| *value = 255 - *value);
+        let mut carving = Carving::from_frame(&image, Some(&mask), MaskRole::Protect).unwrap();
+        carving.remove_seam(CarveMode::Width);
+        assert!(carving.bias.iter().all(|&bias| bias == CARVE_MASK_ENERGY));
+    }
+
+    #[test]
+    fn nothing_is_carved_when_off_or_down_to_one_column() {
+        let original = edge(8, 4, 4, 8);
+        let mut image = original.clone();
+        carver(CarveMode::Off, MaskRole::Ignore).carve_seam(&mut image, None);
+        assert_eq!(image.pixels(), original.pixels());
+
+        let mut carving = Carving::from_frame(&edge(8, 4, 4, 1), None, MaskRole::Ignore).unwrap();
+        assert!(!carving.remove_seam(CarveMode::Width));
+        assert!(Carving::from_frame(&edge(8, 4, 4, 0), None, MaskRole::Ignore).is_none());
+    }
+
+    #[test]
+    fn inserted_seams_widen_the_image_up_to_the_frame() {
+        let mut carving = Carving::from_frame(&edge(14, 6, 5, 10), None, MaskRole::Ignore).unwrap();
+        assert_eq!(carving.insert_seams(CarveMode::Width, 6), 4);
+        assert_eq!(carving.size(), (14, 6));
+        let image = carving.to_frame();
+        for y in 0..6 {
+            // Flat areas are stretched while the edge stays a single step.
+            let row = row(&image, y);
+            let step = row.windows(2).filter(|pair| pair[0] != pair[1]).count();
+            assert_eq!(step, 1, "{:?}", row);
+        }
+    }
+
+    #[test]
+    fn added_seams_are_a_tenth_of_the_side() {
+        let mut image = edge(40, 6, 20, 30);
+        let carver = carver(CarveMode::Off, MaskRole::Ignore);
+        assert_eq!(carver.add_seams(&mut image, None), 3);
+        assert_eq!(row(&image, 0)[32], WHITE);
+        assert_eq!(row(&image, 0)[33], [0; 4]);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::canvas::{Point, Renderer};
+use crate::constants::SELECTION_COLOR;
+use crate::mask::{Mask, Shape};
+use crate::pointer::PointerEvent;
+use anyhow::Result;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SelectionTool {
+    None,
+    Rectangle,
+    Ellipse,
+    Polygon,
+}
+
+impl SelectionTool {
+    pub fn next(self) -> Self {
+        match self {
+            SelectionTool::None => SelectionTool::Rectangle,
+            SelectionTool::Rectangle => SelectionTool::Ellipse,
+            SelectionTool::Ellipse => SelectionTool::Polygon,
+            SelectionTool::Polygon => SelectionTool::None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SelectionTool::None => "Selection: none",
+            SelectionTool::Rectangle => "Selection: rectangle",
+            SelectionTool::Ellipse => "Selection: ellipse",
+            SelectionTool::Polygon => "Selection: polygon",
+        }
+    }
+}
+
+pub struct Selection {
+    tool: SelectionTool,
+    points: Vec<Point>,
+    dragging: bool,
+    inverted: bool,
+    feather: u32,
+}
+
+impl Selection {
+    pub fn new(feather: u32) -> Self {
+        Selection {
+            tool: SelectionTool::None,
+            points: vec![],
+            dragging: false,
+            inverted: false,
+            feather,
+        }
+    }
+
+    pub fn tool(&self) -> SelectionTool {
+        self.tool
+    }
+
+    pub fn feather(&self) -> u32 {
+        self.feather
+    }
+
+    pub fn set_feather(&mut self, feather: u32) {
+        self.feather = feather;
+    }
+
+    pub fn inverted_label(&self) -> &'static str {
+        if self.inverted {
+            "Apply: outside"
+        } else {
+            "Apply: inside"
+        }
+    }
+
+    pub fn next_tool(&mut self) -> SelectionTool {
+        self.tool = self.tool.next();
+        self.points.clear();
+        self.dragging = false;
+        self.tool
+    }
+
+    pub fn toggle_inverted(&mut self) -> bool {
+        self.inverted = !self.inverted;
+        self.inverted
+    }
+
+    pub fn handle_pointer(&mut self, event: PointerEvent) {
+        if self.tool == SelectionTool::None {
+            return;
+        }
+        match event {
+            PointerEvent::Down(position) => {
+                self.points = vec![position];
+                self.dragging = true;
+            }
+            PointerEvent::Move(position) if self.dragging => self.extend(position),
+            PointerEvent::Up(position) if self.dragging => {
+                self.extend(position);
+                self.dragging = false;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn shape(&self) -> Option<Shape> {
+        match (self.tool, self.points.as_slice()) {
+            (SelectionTool::Rectangle, [from, to]) if from.x != to.x && from.y != to.y => {
+                Some(Shape::Rectangle {
+                    from: *from,
+                    to: *to,
+                })
+            }
+            (SelectionTool::Ellipse, [from, to]) if from.x != to.x && from.y != to.y => {
+                Some(Shape::Ellipse {
+                    from: *from,
+                    to: *to,
+                })
+            }
+            (SelectionTool::Polygon, points) if points.len() >= 3 => {
+                Some(Shape::Polygon(points.to_vec()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn to_mask(&self, width: u32, height: u32) -> Option<Mask> {
+        self.shape().map(|shape| {
+            let mut mask = Mask::from_shape(&shape, width, height);
+            if self.inverted {
+                mask.invert();
+            }
+            mask.feather(self.feather);
+            mask
+        })
+    }
+
+    pub fn draw(&self, renderer: &Renderer) -> Result<()> {
+        match self.shape() {
+            Some(Shape::Rectangle { from, to }) => {
+                renderer.stroke_polygon(
+                    &[
+                        from,
+                        Point { x: to.x, y: from.y },
+                        to,
+                        Point { x: from.x, y: to.y },
+                    ],
+                    SELECTION_COLOR,
+                );
+                Ok(())
+            }
+            Some(Shape::Ellipse { from, to }) => {
+                renderer.stroke_ellipse(&from, &to, SELECTION_COLOR)
+            }
+            Some(Shape::Polygon(points)) => {
+                renderer.stroke_polygon(&points, SELECTION_COLOR);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn extend(&mut self, position: Point) {
+        match self.tool {
+            SelectionTool::Rectangle | SelectionTool::Ellipse => {
+                self.points.truncate(1);
+                self.points.push(position);
+            }
+            SelectionTool::Polygon => {
+                if self.points.last() != Some(&position) {
+                    self.points.push(position);
+                }
+            }
+            SelectionTool::None => {}
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use crate::browser::{context, create_raf_closure, now, request_animation_frame, LoopClosure};
+use crate::browser::{
+    context, create_raf_closure, new_canvas, now, request_animation_frame, side_canvas, LoopClosure,
+};
 use crate::canvas::Renderer;
 use anyhow::anyhow;
 use anyhow::Result;
@@ -10,7 +12,7 @@ use std::rc::Rc;
 pub trait Simulation {
     async fn initialize(&self) -> Result<Box<dyn Simulation>>;
     fn update(&mut self, renderer: &Renderer);
-    fn draw(&self, render: &Renderer);
+    fn draw(&mut self, render: &Renderer);
 }
 
 pub struct SimulationLoop {
@@ -31,6 +33,8 @@ impl SimulationLoop {
         };
         let renderer = Renderer {
             context: context()?,
+            overlay: new_canvas()?,
+            side: side_canvas()?,
         };
 
         let f: SharedLoopClosure = Rc::new(RefCell::new(None));
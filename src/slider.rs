@@ -0,0 +1,17 @@
+use crate::events::{self, EventSender, Listener, Parameter, UiEvent};
+use anyhow::Result;
+use web_sys::HtmlInputElement;
+
+pub fn add_input_handler(
+    elem: HtmlInputElement,
+    sender: EventSender,
+    parameter: Parameter,
+) -> Result<Listener> {
+    let input = elem.clone();
+    Listener::new(&elem, "input", move |_event| {
+        events::send(
+            &sender,
+            UiEvent::ParameterChange(parameter, input.value_as_number()),
+        );
+    })
+}
@@ -0,0 +1,351 @@
+use crate::constants::{
+    MATCH_RATIO, RANSAC_CONFIDENCE, RANSAC_MAX_ITERATIONS, RANSAC_THRESHOLD,
+    STABILIZATION_FEATURES, STABILIZATION_MAX_ZOOM, STABILIZATION_ZOOM_RELAX,
+};
+use crate::gray::GrayImage;
+use crate::homography::{self, Homography};
+use crate::image::RawImage;
+use crate::orb::{self, Feature};
+use std::collections::VecDeque;
+use std::ops::{Add, Sub};
+
+// Rigid camera motion around the frame center.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Motion {
+    pub dx: f64,
+    pub dy: f64,
+    pub angle: f64,
+}
+
+impl Add for Motion {
+    type Output = Motion;
+
+    fn add(self, other: Motion) -> Motion {
+        Motion {
+            dx: self.dx + other.dx,
+            dy: self.dy + other.dy,
+            angle: self.angle + other.angle,
+        }
+    }
+}
+
+impl Sub for Motion {
+    type Output = Motion;
+
+    fn sub(self, other: Motion) -> Motion {
+        Motion {
+            dx: self.dx - other.dx,
+            dy: self.dy - other.dy,
+            angle: self.angle - other.angle,
+        }
+    }
+}
+
+impl Motion {
+    // Rotates around the center, translates and then zooms around the center.
+    pub fn homography(&self, (cx, cy): (f64, f64), zoom: f64) -> Homography {
+        let (cos, sin) = (zoom * self.angle.cos(), zoom * self.angle.sin());
+        Homography([
+            cos,
+            -sin,
+            cx - cos * cx + sin * cy + zoom * self.dx,
+            sin,
+            cos,
+            cy - sin * cx - cos * cy + zoom * self.dy,
+            0.0,
+            0.0,
+            1.0,
+        ])
+    }
+}
+
+// The camera path is the sum of the motions between consecutive frames. Each
+// frame is moved from its place on the path to the average of the last
+// positions, and zoomed just enough that no border shows.
+pub struct Stabilizer {
+    previous: Option<(u32, u32, Vec<Feature>)>,
+    position: Motion,
+    trajectory: VecDeque<Motion>,
+    window: usize,
+    zoom: f64,
+}
+
+impl Stabilizer {
+    pub fn new(window: usize) -> Self {
+        Stabilizer {
+            previous: None,
+            position: Motion::default(),
+            trajectory: VecDeque::with_capacity(window),
+            window: window.max(1),
+            zoom: 1.0,
+        }
+    }
+
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.max(1);
+        while self.trajectory.len() > self.window {
+            self.trajectory.pop_front();
+        }
+    }
+
+    pub fn stabilize(&mut self, frame: &RawImage) -> RawImage {
+        let (width, height) = (frame.width(), frame.height());
+        let center = (width as f64 / 2.0, height as f64 / 2.0);
+        let features = orb::orb(&GrayImage::from_raw(frame), STABILIZATION_FEATURES);
+        let motion = match self.previous.take() {
+            Some((w, h, previous)) if w == width && h == height => {
+                estimate_motion(&previous, &features, center).unwrap_or_default()
+            }
+            // A frame of another size starts a new video.
+            Some(_) => {
+                *self = Stabilizer::new(self.window);
+                Motion::default()
+            }
+            None => Motion::default(),
+        };
+        self.previous = Some((width, height, features));
+
+        self.position = self.position + motion;
+        if self.trajectory.len() == self.window {
+            self.trajectory.pop_front();
+        }
+        self.trajectory.push_back(self.position);
+        let total = self
+            .trajectory
+            .iter()
+            .fold(Motion::default(), |total, &position| total + position);
+        let count = self.trajectory.len() as f64;
+        let smoothed = Motion {
+            dx: total.dx / count,
+            dy: total.dy / count,
+            angle: total.angle / count,
+        };
+        let correction = smoothed - self.position;
+
+        // The zoom follows a larger correction at once and relaxes slowly.
+        let required = required_zoom(&correction, width as f64, height as f64);
+        self.zoom = required
+            .max(self.zoom - STABILIZATION_ZOOM_RELAX)
+            .clamp(1.0, STABILIZATION_MAX_ZOOM);
+        log!(
+            "camera moved ({:.1}, {:.1}) {:.2}°, corrected by ({:.1}, {:.1}) {:.2}° at zoom {:.2}",
+            motion.dx,
+            motion.dy,
+            motion.angle.to_degrees(),
+            correction.dx,
+            correction.dy,
+            correction.angle.to_degrees(),
+            self.zoom
+        );
+        homography::warp(
+            frame,
+            &correction.homography(center, self.zoom),
+            width,
+            height,
+        )
+    }
+}
+
+// Matched ORB features, relative to the center, are fitted with a RANSAC
+// homography from which only the rigid part is kept.
+fn estimate_motion(
+    previous: &[Feature],
+    current: &[Feature],
+    (cx, cy): (f64, f64),
+) -> Option<Motion> {
+    let point = |feature: &Feature| {
+        (
+            feature.keypoint.x as f64 - cx,
+            feature.keypoint.y as f64 - cy,
+        )
+    };
+    let correspondences: Vec<_> = orb::match_features(current, previous, MATCH_RATIO, true)
+        .into_iter()
+        .map(|m| (point(&previous[m.train]), point(&current[m.query])))
+        .collect();
+    let estimate = homography::ransac(
+        &correspondences,
+        RANSAC_THRESHOLD,
+        RANSAC_CONFIDENCE,
+        RANSAC_MAX_ITERATIONS,
+    )?;
+    let h = estimate.homography.0;
+    Some(Motion {
+        dx: h[2],
+        dy: h[5],
+        angle: h[3].atan2(h[0]),
+    })
+}
+
+// The smallest zoom for which every corner of the view maps back inside the
+// frame, found by bisection.
+fn required_zoom(correction: &Motion, width: f64, height: f64) -> f64 {
+    let (half_width, half_height) = (width / 2.0, height / 2.0);
+    let (cos, sin) = (correction.angle.cos(), correction.angle.sin());
+    let covers = |zoom: f64| {
+        [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .iter()
+            .all(|&(sx, sy)| {
+                let x = sx * half_width / zoom - correction.dx;
+                let y = sy * half_height / zoom - correction.dy;
+                let (px, py) = (cos * x + sin * y, -sin * x + cos * y);
+                px.abs() <= half_width + 1e-9 && py.abs() <= half_height + 1e-9
+            })
+    };
+    if covers(1.0) {
+        return 1.0;
+    }
+    if !covers(STABILIZATION_MAX_ZOOM) {
+        return STABILIZATION_MAX_ZOOM;
+    }
+    let (mut low, mut high) = (1.0, STABILIZATION_MAX_ZOOM);
+    for _ in 0..32 {
+        let middle = (low + high) / 2.0;
+        if covers(middle) {
+            high = middle;
+        } else {
+            low = middle;
+        }
+    }
+    high
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Xorshift;
+
+    const WIDTH: u32 = 160;
+    const HEIGHT: u32 = 120;
+
+    // Discs of random colors scattered over the plane, seen with the camera
+    // moved by an offset.
+    fn view(offset: (f64, f64)) -> RawImage {
+        let mut random = Xorshift::new(5);
+        let discs: Vec<_> = (0..50)
+            .map(|_| {
+                let center = (
+                    random.below(200) as f64 - 20.0,
+                    random.below(160) as f64 - 20.0,
+                );
+                let radius = 4.0 + random.below(12) as f64;
+                let color = [0, 0, 0].map(|_| 30 + random.below(220) as u8);
+                (center, radius, color)
+            })
+            .collect();
+        let mut pixels = Vec::with_capacity((WIDTH * HEIGHT * 4) as usize);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let (px, py) = (x as f64 - offset.0, y as f64 - offset.1);
+                let color = discs
+                    .iter()
+                    .rev()
+                    .find(|((cx, cy), radius, _)| (px - cx).hypot(py - cy) <= *radius)
+                    .map_or([10, 10, 10], |disc| disc.2);
+                pixels.extend(color);
+                pixels.push(255);
+            }
+        }
+        RawImage::from_raw(pixels, WIDTH, HEIGHT)
+    }
+
+    fn features(image: &RawImage) -> Vec<Feature> {
+        orb::orb(&GrayImage::from_raw(image), STABILIZATION_FEATURES)
+    }
+
+    // Mean absolute difference over the middle of two images.
+    fn difference(a: &RawImage, b: &RawImage) -> f64 {
+        let (mut total, mut count) = (0.0, 0.0);
+        for y in HEIGHT / 4..3 * HEIGHT / 4 {
+            for x in WIDTH / 4..3 * WIDTH / 4 {
+                let index = (4 * (y * WIDTH + x)) as usize;
+                for c in 0..3 {
+                    total += a.pixels()[index + c].abs_diff(b.pixels()[index + c]) as f64;
+                    count += 1.0;
+                }
+            }
+        }
+        total / count
+    }
+
+    #[test]
+    fn motion_homography_rotates_around_the_center_and_shifts() {
+        let center = (80.0, 60.0);
+        let still = Motion::default().homography(center, 1.0);
+        assert_eq!(still, Homography::identity());
+
+        let turn = Motion {
+            dx: 5.0,
+            dy: -2.0,
+            angle: std::f64::consts::FRAC_PI_2,
+        };
+        let h = turn.homography(center, 1.0);
+        let (x, y) = h.apply(center).unwrap();
+        assert!((x - 85.0).abs() < 1e-9 && (y - 58.0).abs() < 1e-9);
+        let (x, y) = h.apply((90.0, 60.0)).unwrap();
+        assert!((x - 85.0).abs() < 1e-9 && (y - 68.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn zoom_hides_the_border_a_correction_uncovers() {
+        assert_eq!(required_zoom(&Motion::default(), 100.0, 80.0), 1.0);
+        let shift = Motion {
+            dx: 10.0,
+            ..Motion::default()
+        };
+        assert!((required_zoom(&shift, 100.0, 80.0) - 1.25).abs() < 1e-6);
+        let far = Motion {
+            dx: 60.0,
+            ..Motion::default()
+        };
+        assert_eq!(required_zoom(&far, 100.0, 80.0), STABILIZATION_MAX_ZOOM);
+    }
+
+    #[test]
+    fn estimated_motion_is_the_camera_translation() {
+        let center = (WIDTH as f64 / 2.0, HEIGHT as f64 / 2.0);
+        let before = features(&view((0.0, 0.0)));
+        let after = features(&view((6.0, -4.0)));
+        let motion = estimate_motion(&before, &after, center).unwrap();
+        assert!((motion.dx - 6.0).abs() < 0.5, "{:?}", motion);
+        assert!((motion.dy + 4.0).abs() < 0.5, "{:?}", motion);
+        assert!(motion.angle.abs() < 0.01, "{:?}", motion);
+    }
+
+    #[test]
+    fn single_frame_window_leaves_frames_alone() {
+        let mut stabilizer = Stabilizer::new(1);
+        for offset in [(0.0, 0.0), (6.0, -4.0)] {
+            let frame = view(offset);
+            assert_eq!(stabilizer.stabilize(&frame).pixels(), frame.pixels());
+        }
+    }
+
+    // After a long steady shot the path is barely moved by one shaken
+    // frame, which is moved back onto the first view and zoomed.
+    #[test]
+    fn shaken_frame_is_moved_back_onto_the_path() {
+        let mut stabilizer = Stabilizer::new(30);
+        let first = view((0.0, 0.0));
+        for _ in 0..29 {
+            stabilizer.stabilize(&first);
+        }
+        let shaken = view((6.0, -4.0));
+        let stabilized = stabilizer.stabilize(&shaken);
+
+        let correction = Motion {
+            dx: 6.0 / 30.0 - 6.0,
+            dy: 4.0 - 4.0 / 30.0,
+            angle: 0.0,
+        };
+        let zoom = required_zoom(&correction, WIDTH as f64, HEIGHT as f64);
+        let center = (WIDTH as f64 / 2.0, HEIGHT as f64 / 2.0);
+        let expected = homography::warp(
+            &first,
+            &Motion::default().homography(center, zoom),
+            WIDTH,
+            HEIGHT,
+        );
+        assert!(difference(&stabilized, &expected) < difference(&shaken, &expected) / 10.0);
+    }
+}
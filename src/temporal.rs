@@ -0,0 +1,151 @@
+use crate::image::RawImage;
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug)]
+pub enum TemporalFilter {
+    RunningAverage,
+    // Weight of the newest frame.
+    ExponentialSmoothing(f32),
+    // Gain applied to the absolute difference with the previous frame.
+    FrameDifference(f32),
+    // How much of the trail survives each frame.
+    MotionTrails(f32),
+    TemporalMedian,
+    LongExposure,
+}
+
+// Ring buffer of the frames that previously reached a step, plus the running
+// state of the filters that accumulate over every frame instead.
+#[derive(Clone)]
+pub struct FrameHistory {
+    frames: VecDeque<RawImage>,
+    depth: usize,
+    accumulator: Vec<f32>,
+    exposures: u32,
+}
+
+impl FrameHistory {
+    pub fn new(depth: usize) -> Self {
+        FrameHistory {
+            frames: VecDeque::with_capacity(depth),
+            depth,
+            accumulator: vec![],
+            exposures: 0,
+        }
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.frames.len() > depth {
+            self.frames.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.accumulator.clear();
+        self.exposures = 0;
+    }
+
+    fn push(&mut self, frame: RawImage) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.frames.len() == self.depth {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    // A frame of another size starts a new video, so the history is dropped.
+    fn prepare(&mut self, frame: &RawImage) {
+        let mismatch =
+            |other: &RawImage| other.width() != frame.width() || other.height() != frame.height();
+        if self.frames.back().is_some_and(mismatch)
+            || (!self.accumulator.is_empty() && self.accumulator.len() != frame.pixels().len())
+        {
+            self.clear();
+        }
+    }
+}
+
+impl TemporalFilter {
+    pub fn apply(&self, image: &mut RawImage, history: &mut FrameHistory) {
+        history.prepare(image);
+        let current = image.clone();
+        match self {
+            TemporalFilter::RunningAverage => running_average(image, history),
+            TemporalFilter::ExponentialSmoothing(weight) => {
+                accumulate(image, history, |average, value| {
+                    average + (value - average) * weight.clamp(0.0, 1.0)
+                })
+            }
+            TemporalFilter::FrameDifference(gain) => frame_difference(image, history, *gain),
+            TemporalFilter::MotionTrails(decay) => accumulate(image, history, |trail, value| {
+                value.max(trail * decay.clamp(0.0, 1.0))
+            }),
+            TemporalFilter::TemporalMedian => temporal_median(image, history),
+            TemporalFilter::LongExposure => {
+                history.exposures += 1;
+                let exposures = history.exposures as f32;
+                accumulate(image, history, |average, value| {
+                    average + (value - average) / exposures
+                })
+            }
+        }
+        history.push(current);
+    }
+}
+
+// Alpha is never filtered, only the color channels.
+fn color_channels(pixels: &mut [u8]) -> impl Iterator<Item = (usize, &mut u8)> {
+    pixels.iter_mut().enumerate().filter(|(i, _)| i % 4 != 3)
+}
+
+fn running_average(image: &mut RawImage, history: &FrameHistory) {
+    let count = (history.frames.len() + 1) as u32;
+    let frames = &history.frames;
+    for (i, value) in color_channels(image.pixels_mut()) {
+        let sum = *value as u32
+            + frames
+                .iter()
+                .map(|frame| frame.pixels()[i] as u32)
+                .sum::<u32>();
+        *value = ((sum + count / 2) / count) as u8;
+    }
+}
+
+fn accumulate(image: &mut RawImage, history: &mut FrameHistory, step: impl Fn(f32, f32) -> f32) {
+    if history.accumulator.is_empty() {
+        history.accumulator = image.pixels().iter().map(|&value| value as f32).collect();
+        return;
+    }
+    let accumulator = &mut history.accumulator;
+    for (i, value) in color_channels(image.pixels_mut()) {
+        accumulator[i] = step(accumulator[i], *value as f32);
+        *value = accumulator[i].round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+// Without a previous frame nothing has moved yet.
+fn frame_difference(image: &mut RawImage, history: &FrameHistory, gain: f32) {
+    let previous = history.frames.back();
+    for (i, value) in color_channels(image.pixels_mut()) {
+        let difference = previous.map_or(0.0, |frame| {
+            (*value as f32 - frame.pixels()[i] as f32).abs()
+        });
+        *value = (difference * gain).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+fn temporal_median(image: &mut RawImage, history: &FrameHistory) {
+    let frames = &history.frames;
+    let mut samples = Vec::with_capacity(frames.len() + 1);
+    for (i, value) in color_channels(image.pixels_mut()) {
+        samples.clear();
+        samples.push(*value);
+        samples.extend(frames.iter().map(|frame| frame.pixels()[i]));
+        let middle = samples.len() / 2;
+        *value = *samples.select_nth_unstable(middle).1;
+    }
+}